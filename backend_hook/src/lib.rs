@@ -1,7 +1,14 @@
 use flowsnet_platform_sdk::logger;
+use gosim_project::bot_templates::*;
 use gosim_project::db_manipulate::*;
 use gosim_project::db_populate::*;
+use gosim_project::issue_bot::{label_review_decision, NotificationKind};
+use gosim_project::llm_usage::{flush_llm_usage, get_llm_usage_stats};
+use gosim_project::pr_matcher::*;
+use gosim_project::reconcile::get_drift_report;
+use gosim_project::tag_taxonomy::list_tags;
 use gosim_project::vector_search::*;
+use gosim_project::{BOT_LOCALE, CAMPAIGN};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
@@ -64,6 +71,42 @@ async fn handler(
     router
         .insert("/conclude", vec![post(conclude_issue_handler)])
         .unwrap();
+    router
+        .insert("/templates", vec![post(save_template_handler)])
+        .unwrap();
+    router
+        .insert("/templates/preview", vec![post(preview_template_handler)])
+        .unwrap();
+    router
+        .insert("/drift", vec![get(drift_report_handler)])
+        .unwrap();
+    router
+        .insert("/contributors", vec![get(list_contributors_handler)])
+        .unwrap();
+    router
+        .insert("/contributors/:login", vec![get(get_contributor_handler)])
+        .unwrap();
+    router
+        .insert("/leaderboard", vec![get(leaderboard_handler)])
+        .unwrap();
+    router
+        .insert("/tags", vec![get(list_tags_handler)])
+        .unwrap();
+    router
+        .insert("/llm/usage", vec![get(llm_usage_handler)])
+        .unwrap();
+    router
+        .insert("/tags/search", vec![get(search_tags_handler)])
+        .unwrap();
+    router
+        .insert("/pulls", vec![get(list_pulls_handler)])
+        .unwrap();
+    router
+        .insert("/matches", vec![get(list_match_candidates_handler)])
+        .unwrap();
+    router
+        .insert("/matches/review", vec![post(review_match_handler)])
+        .unwrap();
 
     if let Err(e) = route(router).await {
         match e {
@@ -96,20 +139,24 @@ async fn approve_issue_budget_handler(
     let success_str = format!("{issue_id} approved for budget: {issue_budget}");
     let fail_str = format!("budget approval operation failed on {issue_id}");
     match assign_issue_budget_in_db(&pool, &issue_id, issue_budget).await {
-        Ok(()) => send_response(
-            200,
-            vec![
-                (
-                    String::from("content-type"),
-                    String::from("application/json"),
-                ),
-                (
-                    String::from("Access-Control-Allow-Origin"),
-                    String::from("*"),
-                ),
-            ],
-            success_str.as_bytes().to_vec(),
-        ),
+        Ok(()) => {
+            // the db is the source of truth, a failed label is only logged
+            let _ = label_review_decision(&issue_id, true).await;
+            send_response(
+                200,
+                vec![
+                    (
+                        String::from("content-type"),
+                        String::from("application/json"),
+                    ),
+                    (
+                        String::from("Access-Control-Allow-Origin"),
+                        String::from("*"),
+                    ),
+                ],
+                success_str.as_bytes().to_vec(),
+            );
+        }
         Err(_) => send_response(
             500,
             vec![
@@ -146,7 +193,9 @@ async fn search_handler(
     };
 
     let query = load.query;
-    match search_collection(&query, "gosim_search").await {
+    let result = search_collection(&query, "gosim_search").await;
+    let _ = flush_llm_usage(&get_pool().await).await;
+    match result {
         Ok(search_result) => {
             let search_result_str = json!(search_result).to_string();
 
@@ -211,8 +260,11 @@ async fn batch_decline_issue_handler(
 
     let issue_ids = load.issue_ids;
     let pool = get_pool().await;
-    match batch_decline_issues_in_db(&pool, issue_ids).await {
+    match batch_decline_issues_in_db(&pool, issue_ids.clone()).await {
         Ok(_) => {
+            for issue_id in &issue_ids {
+                let _ = label_review_decision(issue_id, false).await;
+            }
             send_response(
                 200,
                 vec![
@@ -227,6 +279,10 @@ async fn batch_decline_issue_handler(
         }
         Err(failed_ids) => {
             log::error!("Error, failed processing these: {:?}", failed_ids);
+            let failed: Vec<&str> = failed_ids.split(',').collect();
+            for issue_id in issue_ids.iter().filter(|id| !failed.contains(&id.as_str())) {
+                let _ = label_review_decision(issue_id, false).await;
+            }
             let fail_str = json!(failed_ids).to_string();
             send_response(
                 500,
@@ -465,3 +521,600 @@ async fn list_issues_multi_by_post_handler(
         issues_str.as_bytes().to_vec(),
     );
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TemplateLoad {
+    pub campaign: Option<String>,
+    pub locale: Option<String>,
+    pub notification_kind: String,
+    pub template_body: Option<String>,
+    pub enabled: Option<bool>,
+    pub issue_id: Option<String>,
+}
+
+async fn save_template_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let load: TemplateLoad = match serde_json::from_slice(&_body) {
+        Ok(obj) => obj,
+        Err(_e) => {
+            log::error!("failed to parse body: {}", _e);
+            return;
+        }
+    };
+
+    let kind = match load.notification_kind.parse::<NotificationKind>() {
+        Ok(k) => k,
+        Err(e) => {
+            send_response(
+                400,
+                vec![(
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                )],
+                e.to_string().as_bytes().to_vec(),
+            );
+            return;
+        }
+    };
+    let campaign = load.campaign.unwrap_or(CAMPAIGN.to_string());
+    let locale = load.locale.unwrap_or(BOT_LOCALE.to_string());
+    let template_body = load.template_body.unwrap_or_default();
+    let enabled = load.enabled.unwrap_or_default();
+
+    let pool = get_pool().await;
+    match save_template(&pool, &campaign, &locale, &kind, &template_body, enabled).await {
+        Ok(()) => send_response(
+            200,
+            vec![
+                (String::from("content-type"), String::from("plain/text")),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            format!("template saved for {campaign}/{locale}/{}", kind.as_str())
+                .as_bytes()
+                .to_vec(),
+        ),
+        Err(e) => send_response(
+            400,
+            vec![
+                (String::from("content-type"), String::from("plain/text")),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            e.to_string().as_bytes().to_vec(),
+        ),
+    }
+}
+
+async fn preview_template_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let load: TemplateLoad = match serde_json::from_slice(&_body) {
+        Ok(obj) => obj,
+        Err(_e) => {
+            log::error!("failed to parse body: {}", _e);
+            return;
+        }
+    };
+
+    let kind = match load.notification_kind.parse::<NotificationKind>() {
+        Ok(k) => k,
+        Err(e) => {
+            send_response(
+                400,
+                vec![(
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                )],
+                e.to_string().as_bytes().to_vec(),
+            );
+            return;
+        }
+    };
+    let campaign = load.campaign.unwrap_or(CAMPAIGN.to_string());
+    let locale = load.locale.unwrap_or(BOT_LOCALE.to_string());
+    let issue_id = load.issue_id.unwrap_or_default();
+
+    let pool = get_pool().await;
+    match preview_template(
+        &pool,
+        &campaign,
+        &locale,
+        &kind,
+        &issue_id,
+        load.template_body.as_deref(),
+    )
+    .await
+    {
+        Ok(rendered) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!({ "rendered": rendered })
+                .to_string()
+                .as_bytes()
+                .to_vec(),
+        ),
+        Err(e) => send_response(
+            400,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!({ "error": e.to_string() })
+                .to_string()
+                .as_bytes()
+                .to_vec(),
+        ),
+    }
+}
+
+async fn drift_report_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let run_id = _qry.get("run_id").and_then(|v| v.as_str());
+    let pool = get_pool().await;
+
+    match get_drift_report(&pool, run_id).await {
+        Ok(drifts) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(drifts).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error getting drift report: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to get drift report".to_vec(),
+            )
+        }
+    }
+}
+
+async fn list_contributors_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let page = _qry
+        .get("page")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|p| *p > 0)
+        .unwrap_or(1);
+    let page_size = _qry
+        .get("page_size")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|p| *p > 0)
+        .unwrap_or(50);
+    let pool = get_pool().await;
+
+    match list_contributors(&pool, page, page_size).await {
+        Ok(contributors) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(contributors).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error listing contributors: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to list contributors".to_vec(),
+            )
+        }
+    }
+}
+
+async fn get_contributor_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let login = _qry
+        .get("login")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let pool = get_pool().await;
+
+    match get_contributor(&pool, &login).await {
+        Ok(Some(contributor)) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(contributor).to_string().as_bytes().to_vec(),
+        ),
+        Ok(None) => send_response(
+            404,
+            vec![(String::from("content-type"), String::from("plain/text"))],
+            format!("no contributor {login}").as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error getting contributor {}: {:?}", login, e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to get contributor".to_vec(),
+            )
+        }
+    }
+}
+
+async fn leaderboard_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let rank_by = _qry.get("rank_by").and_then(|v| v.as_str());
+    let limit = _qry
+        .get("limit")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|l| *l > 0)
+        .unwrap_or(20);
+    let pool = get_pool().await;
+
+    match get_leaderboard(&pool, rank_by, limit).await {
+        Ok(contributors) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(contributors).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error getting leaderboard: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to get leaderboard".to_vec(),
+            )
+        }
+    }
+}
+
+async fn llm_usage_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let days = _qry
+        .get("days")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<u32>().ok()))
+        .filter(|d| *d > 0)
+        .unwrap_or(7);
+    let pool = get_pool().await;
+
+    match get_llm_usage_stats(&pool, days).await {
+        Ok(stats) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(stats).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error getting llm usage: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to get llm usage".to_vec(),
+            )
+        }
+    }
+}
+
+async fn list_tags_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let limit = _qry
+        .get("limit")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|l| *l > 0)
+        .unwrap_or(100);
+    let pool = get_pool().await;
+
+    match list_tags(&pool, limit).await {
+        Ok(tags) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(tags).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error listing tags: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to list tags".to_vec(),
+            )
+        }
+    }
+}
+
+async fn search_tags_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let tags: Vec<String> = _qry
+        .get("tags")
+        .and_then(|v| v.as_str())
+        .map(|s| {
+            s.split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    if tags.is_empty() {
+        send_response(
+            400,
+            vec![(
+                String::from("Access-Control-Allow-Origin"),
+                String::from("*"),
+            )],
+            b"tags is required, e.g. ?tags=rust,web development".to_vec(),
+        );
+        return;
+    }
+    let pool = get_pool().await;
+
+    match search_by_keyword_tags(pool, tags).await {
+        Ok(ids) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(ids).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error searching by tags: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to search by tags".to_vec(),
+            )
+        }
+    }
+}
+
+async fn list_match_candidates_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let status = _qry
+        .get("status")
+        .and_then(|v| v.as_str())
+        .unwrap_or("pending");
+    let page = _qry
+        .get("page")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|p| *p > 0)
+        .unwrap_or(1);
+    let page_size = _qry
+        .get("page_size")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|p| *p > 0)
+        .unwrap_or(50);
+    let pool = get_pool().await;
+
+    match get_match_candidates(&pool, status, page, page_size).await {
+        Ok(candidates) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(candidates).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error listing match candidates: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to list match candidates".to_vec(),
+            )
+        }
+    }
+}
+
+async fn review_match_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    #[derive(Serialize, Deserialize)]
+    struct ReviewLoad {
+        candidate_id: u32,
+        accept: bool,
+    }
+    let load: ReviewLoad = match serde_json::from_slice(&_body) {
+        Ok(obj) => obj,
+        Err(_e) => {
+            log::error!("failed to parse ReviewLoad: {}", _e);
+            send_response(
+                400,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"expected candidate_id and accept".to_vec(),
+            );
+            return;
+        }
+    };
+    let pool = get_pool().await;
+
+    match review_match_candidate(&pool, load.candidate_id, load.accept).await {
+        Ok(()) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            format!(
+                "match {} {}",
+                load.candidate_id,
+                if load.accept { "accepted" } else { "rejected" }
+            )
+            .as_bytes()
+            .to_vec(),
+        ),
+        Err(e) => match e.downcast_ref::<MatchConflict>() {
+            Some(conflict) => send_response(
+                409,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                conflict.to_string().as_bytes().to_vec(),
+            ),
+            None => {
+                log::error!("Error reviewing match {}: {:?}", load.candidate_id, e);
+                send_response(
+                    500,
+                    vec![(String::from("content-type"), String::from("plain/text"))],
+                    b"failed to review match".to_vec(),
+                )
+            }
+        },
+    }
+}
+
+async fn list_pulls_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let page = _qry
+        .get("page")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|p| *p > 0)
+        .unwrap_or(1);
+    let page_size = _qry
+        .get("page_size")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+        .filter(|p| *p > 0)
+        .unwrap_or(50);
+    // comma separated, e.g. filters=low_effort,unapproved,changes
+    let filters: Vec<&str> = _qry
+        .get("filters")
+        .and_then(|v| v.as_str())
+        .map_or(Vec::new(), |f| f.split(',').map(|s| s.trim()).collect());
+    let pool = get_pool().await;
+
+    match list_pulls_by_multi(&pool, filters, page, page_size).await {
+        Ok(pulls) => send_response(
+            200,
+            vec![
+                (
+                    String::from("content-type"),
+                    String::from("application/json"),
+                ),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            json!(pulls).to_string().as_bytes().to_vec(),
+        ),
+        Err(e) => {
+            log::error!("Error listing pull requests: {:?}", e);
+            send_response(
+                500,
+                vec![(String::from("content-type"), String::from("plain/text"))],
+                b"failed to list pull requests".to_vec(),
+            )
+        }
+    }
+}
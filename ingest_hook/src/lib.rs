@@ -0,0 +1,66 @@
+use dotenv::dotenv;
+use flowsnet_platform_sdk::logger;
+use gosim_project::db_populate::get_pool;
+use gosim_project::llm_usage::flush_llm_usage;
+use gosim_project::webhook_ingest::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use webhook_flows::{create_endpoint, request_handler, send_response};
+
+#[no_mangle]
+#[tokio::main(flavor = "current_thread")]
+pub async fn on_deploy() {
+    create_endpoint().await;
+}
+
+fn header_value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+// Receives GitHub "issues", "issue_comment", "pull_request" and "label" deliveries, the
+// hourly search in track_github stays on as a safety net for missed deliveries.
+#[request_handler(post)]
+async fn handler(
+    headers: Vec<(String, String)>,
+    _subpath: String,
+    _qry: HashMap<String, Value>,
+    body: Vec<u8>,
+) {
+    dotenv().ok();
+    logger::init();
+
+    let secret = match std::env::var("GITHUB_WEBHOOK_SECRET") {
+        Ok(s) if !s.is_empty() => s,
+        _ => {
+            log::error!("GITHUB_WEBHOOK_SECRET is not set, refusing delivery");
+            send_response(500, vec![], b"webhook secret not configured".to_vec());
+            return;
+        }
+    };
+
+    let signature = header_value(&headers, "X-Hub-Signature-256").unwrap_or_default();
+    if !verify_signature(&secret, &body, signature) {
+        log::error!("Rejected delivery with bad signature");
+        send_response(401, vec![], b"invalid signature".to_vec());
+        return;
+    }
+
+    let event = header_value(&headers, "X-GitHub-Event")
+        .unwrap_or_default()
+        .to_string();
+    let pool = get_pool().await;
+    let result = ingest_github_event(&pool, &event, &body).await;
+    // new issues are summarized on ingest
+    let _ = flush_llm_usage(&pool).await;
+    match result {
+        Ok(true) => send_response(200, vec![], b"ingested".to_vec()),
+        Ok(false) => send_response(200, vec![], b"ignored".to_vec()),
+        Err(e) => {
+            log::error!("Error ingesting {} event: {:?}", event, e);
+            send_response(500, vec![], format!("{}", e).into_bytes());
+        }
+    }
+}
@@ -0,0 +1,8 @@
+-- confidence and source span of the budget picked by budget_extract, kept next to issue_budget
+ALTER TABLE issues_open
+ADD COLUMN issue_budget_confidence FLOAT AFTER issue_budget,
+ADD COLUMN issue_budget_evidence JSON AFTER issue_budget_confidence;
+
+ALTER TABLE issues_master
+ADD COLUMN issue_budget_confidence FLOAT AFTER issue_budget,
+ADD COLUMN issue_budget_evidence JSON AFTER issue_budget_confidence;
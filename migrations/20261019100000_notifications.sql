@@ -0,0 +1,14 @@
+-- outbox for bot comments, one row per (issue, kind) so the same kind is never posted twice
+CREATE TABLE notifications (
+    notification_id INT AUTO_INCREMENT PRIMARY KEY,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    notification_kind VARCHAR(50) NOT NULL,  -- budget_allocated, issue_declined, distribute_fund, stale_reminder, stale_final_warning, stale_released
+    comment_body TEXT NOT NULL,
+    status ENUM('pending', 'sending', 'sent', 'failed') DEFAULT 'pending',
+    attempts INT DEFAULT 0,
+    last_error TEXT,
+    date_created DATETIME DEFAULT CURRENT_TIMESTAMP,
+    date_claimed DATETIME,  -- when the sender claimed the row, rows left in 'sending' past a timeout are flagged as failed
+    date_sent DATETIME,
+    UNIQUE KEY issue_kind (issue_id, notification_kind)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
@@ -0,0 +1,10 @@
+-- bot comment templates per campaign and locale, placeholders like {assignee}, {budget}, {deadline}, {form_link}
+CREATE TABLE message_templates (
+    campaign VARCHAR(50) NOT NULL,
+    locale VARCHAR(10) NOT NULL,
+    notification_kind VARCHAR(50) NOT NULL,
+    template_body TEXT NOT NULL,
+    enabled BOOLEAN DEFAULT 0,
+    date_updated DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (campaign, locale, notification_kind)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
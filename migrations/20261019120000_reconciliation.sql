@@ -0,0 +1,19 @@
+-- github state of tracked issues as of the last reconciliation run
+ALTER TABLE issues_master
+ADD COLUMN issue_node_id VARCHAR(64) AFTER issue_id,
+ADD COLUMN issue_state ENUM('open', 'closed', 'deleted') DEFAULT 'open' AFTER issue_status,
+ADD COLUMN date_reconciled DATETIME AFTER issue_state,
+ADD COLUMN missed_lookups INT DEFAULT 0 AFTER date_reconciled;  -- runs in a row the issue was missing from GitHub, reset when it is found again
+
+-- one row per field found to differ between issues_master and GitHub
+CREATE TABLE reconcile_drift (
+    drift_id INT AUTO_INCREMENT PRIMARY KEY,
+    run_id VARCHAR(20) NOT NULL,  -- start time of the reconciliation run
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    drift_field VARCHAR(50) NOT NULL,  -- issue_state, issue_title, issue_label, issue_assignees, issue_linked_pr, issue_url, project_id
+    db_value TEXT,
+    github_value TEXT,
+    corrected BOOLEAN DEFAULT 0,  -- 0 when only reported, e.g. a changed url
+    date_detected DATETIME DEFAULT CURRENT_TIMESTAMP,
+    INDEX run_issue (run_id, issue_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
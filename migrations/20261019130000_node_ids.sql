@@ -0,0 +1,36 @@
+-- GitHub global node ids, the stable identity of issues, PRs, repos and users;
+-- the url columns stay as the current, mutable address
+ALTER TABLE projects
+ADD COLUMN project_node_id VARCHAR(64) AFTER project_id,
+ADD UNIQUE KEY project_node_id (project_node_id);
+
+ALTER TABLE issues_master
+ADD COLUMN project_node_id VARCHAR(64) AFTER project_id,
+ADD COLUMN issue_creator_node_id VARCHAR(64) AFTER issue_creator,
+ADD COLUMN issue_linked_pr_node_id VARCHAR(64) AFTER issue_linked_pr,
+ADD UNIQUE KEY issue_node_id (issue_node_id);
+
+ALTER TABLE issues_open
+ADD COLUMN issue_node_id VARCHAR(64) AFTER issue_id,
+ADD COLUMN project_node_id VARCHAR(64) AFTER project_id,
+ADD COLUMN issue_creator_node_id VARCHAR(64) AFTER issue_creator;
+
+ALTER TABLE issues_assigned
+ADD COLUMN issue_node_id VARCHAR(64) AFTER issue_id,
+ADD COLUMN assignee_node_id VARCHAR(64) AFTER issue_assignee;
+
+ALTER TABLE issues_closed
+ADD COLUMN issue_node_id VARCHAR(64) AFTER issue_id,
+ADD COLUMN issue_linked_pr_node_id VARCHAR(64) AFTER issue_linked_pr;
+
+ALTER TABLE issues_comment
+ADD COLUMN issue_node_id VARCHAR(64) AFTER issue_id,
+ADD COLUMN comment_node_id VARCHAR(64) AFTER comment_id,
+ADD COLUMN comment_creator_node_id VARCHAR(64) AFTER comment_creator,
+ADD UNIQUE KEY comment_node_id (comment_node_id);
+
+ALTER TABLE pull_requests
+ADD COLUMN pull_node_id VARCHAR(64) AFTER pull_id,
+ADD COLUMN pull_author_node_id VARCHAR(64) AFTER pull_author,
+ADD COLUMN project_node_id VARCHAR(64) AFTER project_id,
+ADD UNIQUE KEY pull_node_id (pull_node_id);
@@ -0,0 +1,26 @@
+-- the staging table now holds every ASSIGNED/UNASSIGNED event of an issue, not just one row
+ALTER TABLE issues_assigned
+DROP PRIMARY KEY,
+ADD COLUMN assigned_row_id INT AUTO_INCREMENT PRIMARY KEY FIRST,
+ADD COLUMN event_node_id VARCHAR(64) AFTER issue_node_id,
+ADD COLUMN event_type ENUM('assigned', 'unassigned') NOT NULL DEFAULT 'assigned' AFTER event_node_id,
+ADD COLUMN event_actor VARCHAR(50) AFTER assignee_node_id,
+ADD UNIQUE KEY assigned_event (issue_id, issue_assignee, event_type, date_assigned);
+
+-- full assignment history of tracked issues, issues_master.issue_assignees is derived from it
+CREATE TABLE issue_assignments (
+    assignment_id INT AUTO_INCREMENT PRIMARY KEY,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    event_node_id VARCHAR(64),  -- null for events received by webhook and for snapshot rows
+    event_type ENUM('assigned', 'unassigned') NOT NULL,
+    assignee VARCHAR(50) NOT NULL,
+    assignee_node_id VARCHAR(64),
+    event_actor VARCHAR(50),  -- who assigned or unassigned
+    event_date DATETIME NOT NULL,
+    -- snapshot rows record a change seen in the current state of an issue, by the closed issue
+    -- search, reconciliation or a stale release, and are dated when the change was seen
+    source ENUM('event', 'snapshot') NOT NULL DEFAULT 'event',
+    UNIQUE KEY event_node_id (event_node_id),
+    UNIQUE KEY assignment_event (issue_id, assignee, event_type, event_date),
+    KEY issue_id (issue_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
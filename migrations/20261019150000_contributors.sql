@@ -0,0 +1,28 @@
+-- GitHub users who were assigned tracked issues; login is what mentions and payouts use,
+-- github_id is the user's databaseId, which survives login changes
+CREATE TABLE contributors (
+    contributor_id INT AUTO_INCREMENT PRIMARY KEY,
+    login VARCHAR(50) NOT NULL,
+    github_id BIGINT,
+    node_id VARCHAR(64),
+    avatar_url VARCHAR(255),
+    date_updated DATETIME DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    UNIQUE KEY login (login),
+    UNIQUE KEY github_id (github_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+ALTER TABLE issue_assignments
+ADD COLUMN contributor_id INT AFTER assignee_node_id,
+ADD KEY contributor_id (contributor_id);
+
+-- one row per assignee of a concluded issue, the budget split evenly between them
+CREATE TABLE payouts (
+    payout_id INT AUTO_INCREMENT PRIMARY KEY,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    contributor_id INT,  -- null while the login is not in contributors yet
+    login VARCHAR(50) NOT NULL,
+    amount INT NOT NULL DEFAULT 0,
+    date_concluded DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY issue_login (issue_id, login),
+    KEY contributor_id (contributor_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
@@ -0,0 +1,14 @@
+-- when a PR was opened, for time-to-merge
+ALTER TABLE pull_requests
+ADD COLUMN date_created DATETIME AFTER project_node_id,
+ADD KEY pull_author (pull_author);
+
+-- campaign an issue was tracked in, rows from before the column belong to the first one
+ALTER TABLE issues_master
+ADD COLUMN campaign VARCHAR(50) AFTER project_id,
+ADD KEY issue_creator (issue_creator);
+
+UPDATE issues_master SET campaign = 'hacktoberfest-2023' WHERE campaign IS NULL;
+
+ALTER TABLE issue_assignments
+ADD KEY assignee (assignee);
@@ -0,0 +1,18 @@
+-- how an issue's linked PR was found, see pr_linking::LinkMethod, and how sure that is (0-1)
+ALTER TABLE issues_master
+ADD COLUMN issue_linked_pr_method VARCHAR(30) AFTER issue_linked_pr_node_id,
+ADD COLUMN issue_linked_pr_confidence FLOAT AFTER issue_linked_pr_method;
+
+ALTER TABLE issues_closed
+ADD COLUMN issue_linked_pr_method VARCHAR(30) AFTER issue_linked_pr_node_id,
+ADD COLUMN issue_linked_pr_confidence FLOAT AFTER issue_linked_pr_method;
+
+-- links recorded before this were all from the closing event
+UPDATE issues_master
+SET issue_linked_pr_method = 'closing_event', issue_linked_pr_confidence = 1.0
+WHERE issue_linked_pr IS NOT NULL AND issue_linked_pr_method IS NULL;
+
+-- PRs linked to an issue are kept and marked instead of deleted
+ALTER TABLE pull_requests
+ADD COLUMN issue_id VARCHAR(255) AFTER project_node_id,  -- url of the issue the PR resolved
+ADD KEY issue_id (issue_id);
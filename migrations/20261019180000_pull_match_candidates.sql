@@ -0,0 +1,16 @@
+-- issues proposed for merged PRs that no issue links to, waiting for an admin's decision
+CREATE TABLE pull_match_candidates (
+    candidate_id INT AUTO_INCREMENT PRIMARY KEY,
+    pull_id VARCHAR(255) NOT NULL,  -- url of pull_request
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    score FLOAT NOT NULL,
+    signals JSON,  -- same_repo, author_assigned, days_after_assignment, title_similarity
+    status ENUM('pending', 'accepted', 'rejected') NOT NULL DEFAULT 'pending',
+    date_created DATETIME DEFAULT CURRENT_TIMESTAMP,
+    date_reviewed DATETIME,
+    UNIQUE KEY pull_issue (pull_id, issue_id),
+    KEY status (status)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+ALTER TABLE pull_requests
+ADD COLUMN date_match_checked DATETIME AFTER date_merged;
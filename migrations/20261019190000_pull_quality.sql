@@ -0,0 +1,9 @@
+-- size, reviews, CI state and labels of a PR, for checking effort before a payout
+ALTER TABLE pull_requests
+ADD COLUMN additions INT AFTER date_merged,
+ADD COLUMN deletions INT AFTER additions,
+ADD COLUMN changed_files INT AFTER deletions,
+ADD COLUMN approvals INT NOT NULL DEFAULT 0 AFTER changed_files,
+ADD COLUMN approvers JSON AFTER approvals,
+ADD COLUMN ci_status VARCHAR(20) AFTER approvers,  -- statusCheckRollup state of the last commit
+ADD COLUMN pull_labels JSON AFTER ci_status;
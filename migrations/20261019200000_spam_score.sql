@@ -0,0 +1,13 @@
+-- how likely an issue is a made-up or low effort contribution, 0 to 1, and the signals behind it
+ALTER TABLE issues_master
+ADD COLUMN spam_score FLOAT AFTER review_status,
+ADD COLUMN spam_signals JSON AFTER spam_score,
+ADD COLUMN date_spam_checked DATETIME AFTER spam_signals,
+ADD KEY spam_score (spam_score);
+
+-- account creation dates for the new account check, kept apart from contributors so that
+-- issue creators looked up for it don't show up as contributors
+CREATE TABLE github_accounts (
+    login VARCHAR(50) PRIMARY KEY,
+    date_joined DATETIME NOT NULL
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
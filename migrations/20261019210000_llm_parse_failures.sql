@@ -0,0 +1,11 @@
+-- model replies that could not be parsed into the expected JSON, even after asking the model to repair them
+CREATE TABLE llm_parse_failures (
+    failure_id INT AUTO_INCREMENT PRIMARY KEY,
+    issue_or_project_id VARCHAR(255) NOT NULL,
+    task VARCHAR(30) NOT NULL,  -- summarization, keywords or classification
+    reply TEXT,
+    repaired_reply TEXT,
+    parse_error TEXT,
+    date_failed DATETIME DEFAULT CURRENT_TIMESTAMP,
+    KEY issue_or_project_id (issue_or_project_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
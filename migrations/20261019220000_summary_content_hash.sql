@@ -0,0 +1,8 @@
+-- sha256 of the texts a summary was made from, an item is summarized again only when they change
+ALTER TABLE issues_repos_summarized
+ADD COLUMN content_hash CHAR(64) AFTER keyword_tags;
+
+-- hash of the texts that failed to parse, the same texts are not sent again until the backoff passes
+ALTER TABLE llm_parse_failures
+ADD COLUMN content_hash CHAR(64) AFTER task,
+ADD KEY item_hash (issue_or_project_id, content_hash);
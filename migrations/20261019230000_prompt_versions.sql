@@ -0,0 +1,4 @@
+-- which prompt template and model produced each summary, e.g. "issue_summary@v2" and "together/meta-llama/Llama-3-8b-chat-hf"
+ALTER TABLE issues_repos_summarized
+ADD COLUMN prompt_version VARCHAR(40) AFTER content_hash,
+ADD COLUMN llm_model VARCHAR(100) AFTER prompt_version;
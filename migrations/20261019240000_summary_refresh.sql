@@ -0,0 +1,7 @@
+-- when a summary was first made and last replaced, and the vector store point holding its embedding,
+-- so a changed summary is embedded again into the same point instead of a new one;
+-- rows indexed before this have no point id yet and get theirs when the point is found on re-upload
+ALTER TABLE issues_repos_summarized
+ADD COLUMN date_summarized DATETIME DEFAULT CURRENT_TIMESTAMP AFTER llm_model,
+ADD COLUMN date_updated DATETIME DEFAULT CURRENT_TIMESTAMP AFTER date_summarized,
+ADD COLUMN vector_point_id BIGINT UNSIGNED AFTER indexed;
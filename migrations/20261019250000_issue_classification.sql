@@ -0,0 +1,18 @@
+-- difficulty, effort, skills and type the LLM gives each issue while summarizing it,
+-- kept here until the issue is in issues_master and copied over by classification_master
+CREATE TABLE issue_classifications (
+    issue_id VARCHAR(255) PRIMARY KEY,  -- url of an issue
+    difficulty VARCHAR(20),  -- good-first-issue, intermediate or advanced
+    effort_hours INT,  -- estimated hours of work, 1 to 40
+    skills JSON,  -- lowercase skill names, e.g. ["rust", "webassembly"]
+    issue_type VARCHAR(20),  -- bug, feature, docs or test
+    content_hash CHAR(64),  -- same hash as the summary, classified again when the issue text changes
+    llm_model VARCHAR(100),
+    date_classified DATETIME DEFAULT CURRENT_TIMESTAMP
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+ALTER TABLE issues_master
+ADD COLUMN issue_difficulty VARCHAR(20),
+ADD COLUMN issue_effort_hours INT,
+ADD COLUMN issue_skills JSON,
+ADD COLUMN issue_type VARCHAR(20);
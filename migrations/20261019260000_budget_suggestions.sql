@@ -0,0 +1,5 @@
+-- budget the recommender proposes for a queued issue and why, for reviewers to accept or override
+ALTER TABLE issues_master
+ADD COLUMN suggested_budget INT,
+ADD COLUMN suggested_budget_rationale TEXT,
+ADD COLUMN date_budget_suggested DATETIME;
@@ -0,0 +1,22 @@
+-- canonical keyword tags; tags close to another one are merged into it
+CREATE TABLE tags (
+    tag_id INT AUTO_INCREMENT PRIMARY KEY,
+    tag_name VARCHAR(100) NOT NULL,
+    embedding JSON,
+    date_created DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY tag_name (tag_name)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- every spelling seen for a tag, keyed by its letters and digits only
+CREATE TABLE tag_aliases (
+    alias_key VARCHAR(100) PRIMARY KEY,
+    alias VARCHAR(100) NOT NULL,
+    tag_id INT NOT NULL,
+    source VARCHAR(20) NOT NULL, -- new, embedding, llm or manual
+    similarity FLOAT,
+    KEY tag_id (tag_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- keyword_tags mapped to canonical tags, NULL until canonicalize_summary_tags runs
+ALTER TABLE issues_repos_summarized
+ADD COLUMN canonical_tags JSON;
@@ -0,0 +1,16 @@
+-- one row per LLM completion or embeddings request, costs are estimated from token counts
+CREATE TABLE llm_usage (
+    usage_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    provider VARCHAR(50) NOT NULL,
+    model VARCHAR(255) NOT NULL,
+    task VARCHAR(50) NOT NULL, -- summarization, keywords, classification or embedding
+    campaign VARCHAR(100) NOT NULL,
+    prompt_tokens INT NOT NULL DEFAULT 0,
+    completion_tokens INT NOT NULL DEFAULT 0,
+    tokens_estimated BOOLEAN NOT NULL DEFAULT FALSE, -- the provider did not report token counts
+    latency_ms INT NOT NULL DEFAULT 0,
+    estimated_cost DECIMAL(12, 6) NOT NULL DEFAULT 0, -- USD
+    succeeded BOOLEAN NOT NULL DEFAULT TRUE,
+    date_created DATETIME DEFAULT CURRENT_TIMESTAMP,
+    KEY campaign_date (campaign, date_created)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
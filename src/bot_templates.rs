@@ -0,0 +1,337 @@
+use crate::issue_bot::{enqueue_notification, NotificationKind};
+use crate::{BOT_LOCALE, CAMPAIGN};
+use chrono::{Duration, Utc};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use std::collections::HashMap;
+
+pub static DEFAULT_LOCALE: &str = "en";
+pub static PLACEHOLDERS: [&str; 6] = [
+    "assignee",
+    "budget",
+    "deadline",
+    "form_link",
+    "issue_title",
+    "issue_id",
+];
+pub static DEADLINE_DAYS: i64 = 3;
+
+// Built-in English messages, used when a campaign has no stored template for a kind.
+pub fn default_template(kind: &NotificationKind) -> &'static str {
+    match kind {
+        NotificationKind::BudgetAllocated => "@{assignee} Congratulations! GOSIM grant approved.\nYour proposal is approved to get ${budget} fund to fix the issue.",
+        NotificationKind::IssueDeclined => "I'm sorry, your proposal for this issue wasn't approved.",
+        NotificationKind::DistributeFund => "@{assignee}, well done! According to the PR commit history, @{assignee} should receive ${budget}.\nPlease fill in this form to claim your fund: {form_link}",
+        NotificationKind::StaleReminder => "@{assignee} friendly reminder: there has been no activity here for a while. Please link your PR to this issue, or leave a comment about your progress.",
+        NotificationKind::StaleFinalWarning => "@{assignee} please link your PR to the issue it fixed by {deadline}. Otherwise this issue will be deemed not completed, you will be unassigned, and we can't provide the fund.",
+        NotificationKind::StaleReleased => "@{assignee} there has been no activity on this issue for a long time, so it has been unassigned and is open for other contributors again.",
+    }
+}
+
+// Replaces `{name}` placeholders with values from `vars`; `{{` and `}}` are literal braces.
+// Unknown placeholders and placeholders without a value are errors, so a broken
+// template is caught by the preview instead of being posted to GitHub.
+pub fn render_template(template: &str, vars: &HashMap<&str, String>) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for n in chars.by_ref() {
+                    if n == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(n);
+                }
+                if !closed {
+                    return Err(anyhow::anyhow!("Unclosed placeholder: {{{}", name));
+                }
+                let name = name.trim();
+                if !PLACEHOLDERS.contains(&name) {
+                    return Err(anyhow::anyhow!("Unknown placeholder: {{{}}}", name));
+                }
+                match vars.get(name) {
+                    Some(v) if !v.is_empty() => out.push_str(v),
+                    _ => return Err(anyhow::anyhow!("No value for placeholder: {{{}}}", name)),
+                }
+            }
+            '}' => return Err(anyhow::anyhow!("Unmatched '}}' in template")),
+            _ => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+// Checks a template against placeholder values that are all present, so only
+// syntax and unknown names can fail.
+pub fn validate_template(template: &str) -> anyhow::Result<()> {
+    let vars: HashMap<&str, String> = PLACEHOLDERS
+        .iter()
+        .map(|p| (*p, String::from("x")))
+        .collect();
+    render_template(template, &vars).map(|_| ())
+}
+
+// Disabled templates are only returned with `include_disabled`, for previews.
+pub async fn get_template(
+    pool: &Pool,
+    campaign: &str,
+    locale: &str,
+    kind: &NotificationKind,
+    include_disabled: bool,
+) -> anyhow::Result<String> {
+    let mut conn = pool.get_conn().await?;
+
+    for loc in [locale, DEFAULT_LOCALE] {
+        let template: Option<String> = conn
+            .exec_first(
+                r"SELECT template_body FROM message_templates
+                WHERE campaign = :campaign AND locale = :locale AND notification_kind = :notification_kind
+                AND (enabled = 1 OR :include_disabled)",
+                params! {
+                    "campaign" => campaign,
+                    "locale" => loc,
+                    "notification_kind" => kind.as_str(),
+                    "include_disabled" => include_disabled,
+                },
+            )
+            .await?;
+        if let Some(t) = template {
+            return Ok(t);
+        }
+    }
+
+    Ok(default_template(kind).to_string())
+}
+
+pub async fn save_template(
+    pool: &Pool,
+    campaign: &str,
+    locale: &str,
+    kind: &NotificationKind,
+    template_body: &str,
+    enabled: bool,
+) -> anyhow::Result<()> {
+    validate_template(template_body)?;
+
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT INTO message_templates (campaign, locale, notification_kind, template_body, enabled)
+    VALUES (:campaign, :locale, :notification_kind, :template_body, :enabled)
+    ON DUPLICATE KEY UPDATE
+    template_body = VALUES(template_body),
+    enabled = VALUES(enabled),
+    date_updated = NOW();";
+
+    if let Err(e) = conn
+        .exec_drop(
+            query,
+            params! {
+                "campaign" => campaign,
+                "locale" => locale,
+                "notification_kind" => kind.as_str(),
+                "template_body" => template_body,
+                "enabled" => enabled,
+            },
+        )
+        .await
+    {
+        log::error!("Error saving message template: {:?}", e);
+        return Err(e.into());
+    };
+
+    Ok(())
+}
+
+// The form assignees fill in to claim their fund; there is no sensible default, so
+// distribute_fund messages are not sent until it is set.
+pub fn fund_form_link() -> anyhow::Result<String> {
+    match std::env::var("FUND_FORM_LINK") {
+        Ok(link) if !link.trim().is_empty() => Ok(link.trim().to_string()),
+        _ => Err(anyhow::anyhow!(
+            "FUND_FORM_LINK is not set, distribute_fund messages can not be rendered"
+        )),
+    }
+}
+
+pub async fn build_template_context(
+    pool: &Pool,
+    issue_id: &str,
+) -> anyhow::Result<HashMap<&'static str, String>> {
+    let mut conn = pool.get_conn().await?;
+
+    let row: Option<(String, Option<String>, Option<i32>)> = conn
+        .exec_first(
+            r"SELECT issue_title, issue_assignees, issue_budget FROM issues_master WHERE issue_id = :issue_id",
+            params! {
+                "issue_id" => issue_id,
+            },
+        )
+        .await?;
+    let (issue_title, issue_assignees, issue_budget) = row.ok_or_else(|| {
+        anyhow::anyhow!("No issue found with the provided issue_id: {}", issue_id)
+    })?;
+
+    // issue_assignees is a JSON array of logins, the first one is addressed
+    let assignee = issue_assignees
+        .and_then(|s| serde_json::from_str::<Vec<Option<String>>>(&s).ok())
+        .and_then(|v| v.into_iter().flatten().next())
+        .unwrap_or_default();
+    let budget = issue_budget
+        .filter(|b| *b > 0)
+        .map(|b| b.to_string())
+        .unwrap_or_default();
+    let deadline = (Utc::now() + Duration::try_days(DEADLINE_DAYS).unwrap())
+        .format("%Y-%m-%d")
+        .to_string();
+    let form_link = fund_form_link().unwrap_or_default();
+
+    let mut vars = HashMap::new();
+    vars.insert("assignee", assignee);
+    vars.insert("budget", budget);
+    vars.insert("deadline", deadline);
+    vars.insert("form_link", form_link);
+    vars.insert("issue_title", issue_title);
+    vars.insert("issue_id", issue_id.to_string());
+
+    Ok(vars)
+}
+
+// Renders a draft, or the stored template when no draft is given, against a real
+// issue so admins can check the message before enabling it.
+pub async fn preview_template(
+    pool: &Pool,
+    campaign: &str,
+    locale: &str,
+    kind: &NotificationKind,
+    issue_id: &str,
+    draft: Option<&str>,
+) -> anyhow::Result<String> {
+    let template = match draft {
+        Some(d) => d.to_string(),
+        None => get_template(pool, campaign, locale, kind, true).await?,
+    };
+    let vars = build_template_context(pool, issue_id).await?;
+
+    render_template(&template, &vars)
+}
+
+pub async fn render_bot_message(
+    pool: &Pool,
+    kind: &NotificationKind,
+    issue_id: &str,
+) -> anyhow::Result<String> {
+    render_bot_message_with_vars(pool, kind, issue_id, Vec::new()).await
+}
+
+// Same as render_bot_message, with some placeholder values supplied by the caller,
+// e.g. a deadline computed from a policy instead of the default one.
+pub async fn render_bot_message_with_vars(
+    pool: &Pool,
+    kind: &NotificationKind,
+    issue_id: &str,
+    overrides: Vec<(&'static str, String)>,
+) -> anyhow::Result<String> {
+    let template = get_template(pool, CAMPAIGN, BOT_LOCALE, kind, false).await?;
+    let mut vars = build_template_context(pool, issue_id).await?;
+    vars.extend(overrides);
+
+    render_template(&template, &vars)
+}
+
+pub async fn queue_bot_message(
+    pool: &Pool,
+    kind: NotificationKind,
+    issue_id: &str,
+) -> anyhow::Result<()> {
+    queue_bot_message_with_vars(pool, kind, issue_id, Vec::new()).await
+}
+
+pub async fn queue_bot_message_with_vars(
+    pool: &Pool,
+    kind: NotificationKind,
+    issue_id: &str,
+    overrides: Vec<(&'static str, String)>,
+) -> anyhow::Result<()> {
+    let comment = render_bot_message_with_vars(pool, &kind, issue_id, overrides)
+        .await
+        .map_err(|e| {
+            log::error!(
+                "Error rendering {} message for {}: {:?}",
+                kind.as_str(),
+                issue_id,
+                e
+            );
+            e
+        })?;
+
+    enqueue_notification(pool, issue_id, kind, &comment).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<&'static str, String> {
+        let mut vars = HashMap::new();
+        vars.insert("assignee", String::from("octocat"));
+        vars.insert("budget", String::from("100"));
+        vars
+    }
+
+    #[test]
+    fn replaces_placeholders() {
+        let out = render_template("@{assignee} gets ${ budget }.", &vars()).unwrap();
+        assert_eq!(out, "@octocat gets $100.");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let out = render_template("{{assignee}} is {assignee}", &vars()).unwrap();
+        assert_eq!(out, "{assignee} is octocat");
+    }
+
+    #[test]
+    fn rejects_broken_templates() {
+        assert!(render_template("hi {assignee", &vars()).is_err());
+        assert!(render_template("hi assignee}", &vars()).is_err());
+        assert!(render_template("hi {name}", &vars()).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_and_empty_values() {
+        assert!(render_template("by {deadline}", &vars()).is_err());
+
+        let mut vars = vars();
+        vars.insert("form_link", String::new());
+        assert!(render_template("fill in {form_link}", &vars).is_err());
+    }
+
+    #[test]
+    fn default_templates_are_valid() {
+        for kind in [
+            NotificationKind::BudgetAllocated,
+            NotificationKind::IssueDeclined,
+            NotificationKind::DistributeFund,
+            NotificationKind::StaleReminder,
+            NotificationKind::StaleFinalWarning,
+            NotificationKind::StaleReleased,
+        ] {
+            assert!(validate_template(default_template(&kind)).is_ok());
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use crate::llm_provider::{chat_for_task, LlmTask};
+use crate::llm_utils::extract_json_object;
+use crate::TOTAL_BUDGET;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+pub static MIN_BUDGET: i32 = 10;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum BudgetSource {
+    #[default]
+    Body,
+    Label,
+    Llm,
+}
+
+// One budget reading, kept together with where it came from so reviewers can see
+// why a number was picked. `span` is a byte range into the body, or into the label name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BudgetCandidate {
+    pub amount: i32,
+    pub confidence: f32,
+    pub source: BudgetSource,
+    pub rule: String,
+    pub span: (usize, usize),
+    pub matched_text: String,
+}
+
+struct BudgetRule {
+    name: &'static str,
+    regex: Regex,
+    confidence: f32,
+}
+
+// amounts like "250", "1,500", "1500.00" or "1.5k"; cents are dropped
+const AMOUNT: &str = r"((?:\d{1,3}(?:,\d{3})+|\d+)(?:\.\d{1,2})?(?:[kK]\b)?)";
+const RANGE_TAIL: &str =
+    r"(?:\s*(?:-|–|~|to)\s*(?:US)?\$?\s*((?:\d{1,3}(?:,\d{3})+|\d+)(?:\.\d{1,2})?(?:[kK]\b)?))?";
+
+lazy_static! {
+    static ref BODY_RULES: Vec<BudgetRule> = vec![
+        BudgetRule {
+            name: "budget_keyword",
+            regex: Regex::new(&format!(
+                r"(?i)budget\s*[:=\-]?\s*(?:US)?\$?\s*{AMOUNT}{RANGE_TAIL}"
+            ))
+            .unwrap(),
+            confidence: 0.9,
+        },
+        BudgetRule {
+            name: "bounty_keyword",
+            regex: Regex::new(&format!(
+                r"(?i)(?:bounty|reward|grant)\s*[:=\-]?\s*(?:US)?\$\s*{AMOUNT}{RANGE_TAIL}"
+            ))
+            .unwrap(),
+            confidence: 0.85,
+        },
+        BudgetRule {
+            name: "dollar_bounty",
+            regex: Regex::new(&format!(
+                r"(?i)(?:US)?\$\s*{AMOUNT}{RANGE_TAIL}\s*(?:USD\s*)?(?:bounty|reward|grant|budget)"
+            ))
+            .unwrap(),
+            confidence: 0.85,
+        },
+        BudgetRule {
+            name: "money_bag",
+            regex: Regex::new(&format!(r"💰\s*(?:US)?\$?\s*{AMOUNT}{RANGE_TAIL}")).unwrap(),
+            confidence: 0.8,
+        },
+        BudgetRule {
+            name: "bare_dollar",
+            regex: Regex::new(&format!(r"(?:US)?\$\s*{AMOUNT}{RANGE_TAIL}")).unwrap(),
+            confidence: 0.4,
+        },
+    ];
+    static ref LABEL_RULE: Regex = Regex::new(&format!(
+        r"(?i)^\s*(?:(?:bounty|budget|reward|💰)\s*[-:_ ]?\s*\$?|\$)\s*{AMOUNT}\s*(?:USD|\$)?\s*$"
+    ))
+    .unwrap();
+    static ref TRAILING_DOLLAR_LABEL: Regex =
+        Regex::new(&format!(r"(?i)^\s*{AMOUNT}\s*(?:\$|USD)\s*$")).unwrap();
+}
+
+fn parse_amount(raw: &str) -> Option<i32> {
+    let raw = raw.replace(',', "");
+    let amount = match raw.strip_suffix(['k', 'K']) {
+        Some(thousands) => thousands.parse::<f64>().ok()? * 1000.0,
+        None => raw.parse::<f64>().ok()?,
+    } as i32;
+    if amount >= MIN_BUDGET && amount <= TOTAL_BUDGET {
+        Some(amount)
+    } else {
+        None
+    }
+}
+
+// "Budget 2024" names a year, not an amount; only plain four digit numbers without a
+// currency sign are taken for one
+fn is_year_like(raw: &str) -> bool {
+    raw.len() == 4
+        && raw.bytes().all(|b| b.is_ascii_digit())
+        && (1900..=2099).contains(&raw.parse::<i32>().unwrap_or(0))
+}
+
+pub fn extract_budget_from_body(body: &str) -> Vec<BudgetCandidate> {
+    let mut out = Vec::new();
+
+    for rule in BODY_RULES.iter() {
+        for cap in rule.regex.captures_iter(body) {
+            let whole = cap.get(0).unwrap();
+            let amount_match = cap.get(1).unwrap();
+            let has_currency = body[whole.start()..amount_match.start()].contains('$');
+            if !has_currency && is_year_like(amount_match.as_str()) {
+                continue;
+            }
+            let amount = match parse_amount(amount_match.as_str()) {
+                Some(a) => a,
+                None => continue,
+            };
+            // for ranges like "$100-$200" the lower bound is taken, with less confidence
+            let is_range = cap.get(2).is_some();
+            let (rule_name, confidence) = if is_range {
+                (format!("{}_range", rule.name), rule.confidence * 0.8)
+            } else {
+                (rule.name.to_string(), rule.confidence)
+            };
+
+            out.push(BudgetCandidate {
+                amount,
+                confidence,
+                source: BudgetSource::Body,
+                rule: rule_name,
+                span: (whole.start(), whole.end()),
+                matched_text: whole.as_str().to_string(),
+            });
+        }
+    }
+
+    out
+}
+
+pub fn extract_budget_from_labels(labels: &[String]) -> Vec<BudgetCandidate> {
+    let mut out = Vec::new();
+
+    for label in labels {
+        let cap = match LABEL_RULE
+            .captures(label)
+            .or_else(|| TRAILING_DOLLAR_LABEL.captures(label))
+        {
+            Some(c) => c,
+            None => continue,
+        };
+        let amount_match = cap.get(1).unwrap();
+        if let Some(amount) = parse_amount(amount_match.as_str()) {
+            out.push(BudgetCandidate {
+                amount,
+                confidence: 0.95,
+                source: BudgetSource::Label,
+                rule: String::from("label"),
+                span: (amount_match.start(), amount_match.end()),
+                matched_text: label.clone(),
+            });
+        }
+    }
+
+    out
+}
+
+// Picks the most confident candidate; on a tie, labels win over the body and the
+// earlier mention in the body wins over a later one.
+pub fn best_budget_candidate(body: &str, labels: &[String]) -> Option<BudgetCandidate> {
+    let mut candidates = extract_budget_from_labels(labels);
+    candidates.extend(extract_budget_from_body(body));
+
+    candidates.into_iter().fold(None, |best, c| match best {
+        None => Some(c),
+        Some(b) => {
+            if c.confidence > b.confidence
+                || (c.confidence == b.confidence
+                    && b.source == BudgetSource::Body
+                    && (c.source == BudgetSource::Label || c.span.0 < b.span.0))
+            {
+                Some(c)
+            } else {
+                Some(b)
+            }
+        }
+    })
+}
+
+pub async fn extract_budget_llm(body: &str) -> anyhow::Result<Option<BudgetCandidate>> {
+    #[derive(Deserialize)]
+    struct LlmBudget {
+        budget: Option<f64>,
+        quote: Option<String>,
+    }
+
+    let system_prompt = r#"
+    You read GitHub issue bodies and find the money amount offered for fixing the issue (a budget, bounty or reward), in US dollars. Ignore amounts that are not offered for the work. Reply with RFC8259-compliant JSON only:
+    { "budget": the amount as a number, or null if no amount is offered,
+      "quote": "the exact text from the issue body where the amount is mentioned, or null" }"#;
+
+    let input = body.chars().take(4000).collect::<String>();
+    let reply = chat_for_task(LlmTask::Classification, system_prompt, &input, 80).await?;
+
+    let json_str = match extract_json_object(&reply) {
+        Some(j) => j,
+        None => return Ok(None),
+    };
+    let parsed: LlmBudget = serde_json::from_str(json_str)?;
+
+    let amount = match parsed.budget {
+        Some(b) if b >= MIN_BUDGET as f64 && b <= TOTAL_BUDGET as f64 => b as i32,
+        _ => return Ok(None),
+    };
+
+    let quote = parsed.quote.unwrap_or_default();
+    let (span, confidence) = match body.find(quote.as_str()) {
+        Some(start) if !quote.is_empty() => ((start, start + quote.len()), 0.5),
+        // the model quoted something that is not in the body, trust it less
+        _ => ((0, 0), 0.3),
+    };
+
+    Ok(Some(BudgetCandidate {
+        amount,
+        confidence,
+        source: BudgetSource::Llm,
+        rule: String::from("llm"),
+        span,
+        matched_text: quote,
+    }))
+}
+
+pub async fn extract_budget_with_fallback(
+    body: &str,
+    labels: &[String],
+) -> Option<BudgetCandidate> {
+    if let Some(candidate) = best_budget_candidate(body, labels) {
+        return Some(candidate);
+    }
+
+    let use_llm = std::env::var("BUDGET_LLM_FALLBACK")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    if !use_llm || body.trim().is_empty() {
+        return None;
+    }
+
+    match extract_budget_llm(body).await {
+        Ok(candidate) => candidate,
+        Err(e) => {
+            log::error!("Error extracting budget with llm: {:?}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body_amounts(body: &str) -> Vec<(i32, String)> {
+        extract_budget_from_body(body)
+            .into_iter()
+            .map(|c| (c.amount, c.rule))
+            .collect()
+    }
+
+    #[test]
+    fn body_amount_formats() {
+        assert_eq!(
+            best_budget_candidate("Budget: $250", &[]).unwrap().amount,
+            250
+        );
+        assert_eq!(
+            best_budget_candidate("budget = 1,500", &[]).unwrap().amount,
+            1500
+        );
+        assert_eq!(
+            best_budget_candidate("Budget: US$ 99.99", &[])
+                .unwrap()
+                .amount,
+            99
+        );
+        assert_eq!(
+            best_budget_candidate("Bounty: $1.5k", &[]).unwrap().amount,
+            1500
+        );
+        assert_eq!(best_budget_candidate("💰 2K", &[]).unwrap().amount, 2000);
+    }
+
+    #[test]
+    fn range_takes_lower_bound_with_less_confidence() {
+        let best = best_budget_candidate("Budget: $100 - $200", &[]).unwrap();
+        assert_eq!(best.amount, 100);
+        assert_eq!(best.rule, "budget_keyword_range");
+        assert!(best.confidence < 0.9);
+
+        let best = best_budget_candidate("reward $1k to $2k", &[]).unwrap();
+        assert_eq!(best.amount, 1000);
+    }
+
+    #[test]
+    fn amounts_outside_limits_are_dropped() {
+        assert!(best_budget_candidate("Budget: $5", &[]).is_none());
+        assert!(best_budget_candidate("Budget: $900k", &[]).is_none());
+        assert!(best_budget_candidate("costs 2kg of coffee", &[]).is_none());
+    }
+
+    #[test]
+    fn keyword_beats_bare_dollar() {
+        let best =
+            best_budget_candidate("the server costs $20 a month, bounty: $300", &[]).unwrap();
+        assert_eq!(best.amount, 300);
+        assert_eq!(best.rule, "bounty_keyword");
+        assert!(body_amounts("$20 a month")
+            .iter()
+            .all(|(_, rule)| rule == "bare_dollar"));
+    }
+
+    #[test]
+    fn label_rules() {
+        let labels = |l: &[&str]| l.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let amounts = |l: &[&str]| {
+            extract_budget_from_labels(&labels(l))
+                .into_iter()
+                .map(|c| c.amount)
+                .collect::<Vec<i32>>()
+        };
+
+        assert_eq!(amounts(&["bounty $50"]), vec![50]);
+        assert_eq!(amounts(&["💰 100"]), vec![100]);
+        assert_eq!(amounts(&["$75"]), vec![75]);
+        assert_eq!(amounts(&["200 USD"]), vec![200]);
+        assert_eq!(
+            amounts(&["bug", "good first issue", "v2.0"]),
+            Vec::<i32>::new()
+        );
+    }
+
+    #[test]
+    fn label_beats_body_keyword() {
+        let best = best_budget_candidate("Budget: $40", &[String::from("bounty $60")]).unwrap();
+        assert_eq!(best.amount, 60);
+        assert_eq!(best.source, BudgetSource::Label);
+    }
+
+    #[test]
+    fn earlier_body_mention_wins_tie() {
+        let best =
+            best_budget_candidate("$400 bounty for the fix, reward: $300 for docs", &[]).unwrap();
+        assert_eq!(best.amount, 400);
+        assert_eq!(best.rule, "dollar_bounty");
+
+        let best =
+            best_budget_candidate("reward: $300 for docs, $400 bounty for the fix", &[]).unwrap();
+        assert_eq!(best.amount, 300);
+        assert_eq!(best.rule, "bounty_keyword");
+    }
+
+    #[test]
+    fn year_after_budget_keyword_is_not_an_amount() {
+        assert!(best_budget_candidate("Budget 2024 planning", &[]).is_none());
+        assert!(best_budget_candidate("Part of the budget: 2025 roadmap", &[]).is_none());
+        assert_eq!(
+            best_budget_candidate("Budget: $2024", &[]).unwrap().amount,
+            2024
+        );
+        assert_eq!(
+            best_budget_candidate("Budget 2500", &[]).unwrap().amount,
+            2500
+        );
+    }
+}
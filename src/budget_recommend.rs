@@ -0,0 +1,364 @@
+use crate::budget_extract::MIN_BUDGET;
+use crate::db_manipulate::count_budget_by_status;
+use crate::llm_usage::spend_pause_reason;
+use crate::vector_search::search_similar;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use std::env;
+
+// Starting points by difficulty, before effort, history and funds are taken into account.
+pub static DIFFICULTY_BUDGETS: [(&str, i32); 3] = [
+    ("good-first-issue", 30),
+    ("intermediate", 80),
+    ("advanced", 200),
+];
+pub static UNCLASSIFIED_BUDGET: i32 = 60;
+pub static HOURLY_RATE: i32 = 15;
+// approved issues at least this close to the issue count as similar
+pub static SIMILARITY_THRESHOLD: f32 = 0.8;
+// suggestions are refreshed once a day since the remaining funds keep changing
+pub static SUGGESTION_MAX_AGE_HOURS: i64 = 24;
+
+#[derive(Clone, Debug, Default)]
+pub struct BudgetInputs {
+    pub difficulty: Option<String>,
+    pub effort_hours: Option<i32>,
+    pub repo_stars: i32,
+    pub requested_budget: Option<i32>, // what the issue body asks for, if anything
+    pub similar_approved: Vec<(i32, f32)>, // budgets of similar approved issues with their similarity
+    pub budget_balance: i32,
+    pub queued_issues: i32,
+}
+
+fn round_to_five(amount: f32) -> i32 {
+    ((amount / 5.0).round() * 5.0) as i32
+}
+
+// Blends a difficulty and effort estimate with what similar issues were approved at, then
+// keeps the result within what was asked for and the issue's share of the remaining funds.
+// Each step that changes the number adds a line to the rationale.
+pub fn recommend_budget(inputs: &BudgetInputs) -> (i32, Vec<String>) {
+    let mut rationale = Vec::new();
+
+    let base = match inputs.difficulty.as_deref() {
+        Some(difficulty) => {
+            let base = DIFFICULTY_BUDGETS
+                .iter()
+                .find(|(d, _)| *d == difficulty)
+                .map(|(_, b)| *b)
+                .unwrap_or(UNCLASSIFIED_BUDGET);
+            rationale.push(format!("{} issues start at ${}", difficulty, base));
+            base
+        }
+        None => {
+            rationale.push(format!(
+                "not classified yet, starting at ${}",
+                UNCLASSIFIED_BUDGET
+            ));
+            UNCLASSIFIED_BUDGET
+        }
+    };
+
+    let mut estimate = base as f32;
+    if let Some(hours) = inputs.effort_hours {
+        let by_effort = hours * HOURLY_RATE;
+        estimate = (estimate + by_effort as f32) / 2.0;
+        rationale.push(format!(
+            "about {} hours of work at ${}/h is ${}",
+            hours, HOURLY_RATE, by_effort
+        ));
+    }
+
+    let star_factor = match inputs.repo_stars {
+        s if s >= 10_000 => 1.2,
+        s if s >= 1_000 => 1.1,
+        _ => 1.0,
+    };
+    if star_factor > 1.0 {
+        estimate *= star_factor;
+        rationale.push(format!(
+            "{} stars, raised {:.0}%",
+            inputs.repo_stars,
+            (star_factor - 1.0) * 100.0
+        ));
+    }
+
+    let weight: f32 = inputs.similar_approved.iter().map(|(_, s)| s).sum();
+    if !inputs.similar_approved.is_empty() && weight > 0.0 {
+        let history = inputs
+            .similar_approved
+            .iter()
+            .map(|(b, s)| *b as f32 * s)
+            .sum::<f32>()
+            / weight;
+        // one precedent is a hint, several are a pattern
+        let history_share = if inputs.similar_approved.len() >= 2 {
+            0.6
+        } else {
+            0.3
+        };
+        estimate = estimate * (1.0 - history_share) + history * history_share;
+        rationale.push(format!(
+            "{} similar approved issue(s) averaged ${}",
+            inputs.similar_approved.len(),
+            round_to_five(history)
+        ));
+    }
+
+    let mut suggested = round_to_five(estimate).max(MIN_BUDGET);
+
+    if let Some(requested) = inputs.requested_budget {
+        if requested >= MIN_BUDGET && suggested > requested {
+            suggested = requested;
+            rationale.push(format!("capped at the ${} the issue asks for", requested));
+        }
+    }
+
+    if inputs.budget_balance <= 0 {
+        rationale.push(String::from("no campaign funds left"));
+        return (0, rationale);
+    }
+    let fair_share = inputs.budget_balance / inputs.queued_issues.max(1);
+    let funds_cap = (fair_share * 2).max(MIN_BUDGET).min(inputs.budget_balance);
+    if suggested > funds_cap {
+        suggested = round_to_five(funds_cap as f32).min(funds_cap);
+        rationale.push(format!(
+            "limited to ${} with ${} left for {} queued issues",
+            suggested, inputs.budget_balance, inputs.queued_issues
+        ));
+    }
+
+    (suggested, rationale)
+}
+
+// Budgets of approved issues near `text` in the vector store, with their similarity.
+async fn similar_approved_budgets(
+    pool: &Pool,
+    issue_id: &str,
+    text: &str,
+) -> anyhow::Result<Vec<(i32, f32)>> {
+    let collection_name = env::var("collection_name").unwrap_or("gosim_search".to_string());
+    let similar: Vec<(String, f32)> = search_similar(text, &collection_name, 20)
+        .await?
+        .into_iter()
+        .filter(|(id, score)| id != issue_id && *score >= SIMILARITY_THRESHOLD)
+        .collect();
+    if similar.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<&String> = similar.iter().map(|(id, _)| id).collect();
+    let mut conn = pool.get_conn().await?;
+    let approved: Vec<(String, i32)> = conn
+        .exec_map(
+            r"SELECT issue_id, issue_budget FROM issues_master
+            WHERE review_status = 'approve' AND issue_budget > 0
+            AND JSON_CONTAINS(:ids, JSON_QUOTE(issue_id))",
+            params! {
+                "ids" => serde_json::json!(ids).to_string(),
+            },
+            |(issue_id, issue_budget): (String, i32)| (issue_id, issue_budget),
+        )
+        .await?;
+
+    Ok(approved
+        .into_iter()
+        .filter_map(|(id, budget)| {
+            let score = similar.iter().find(|(s, _)| *s == id)?.1;
+            Some((budget, score))
+        })
+        .collect())
+}
+
+// Suggests budgets for queued issues that have none yet or an old one. Reviewers see the
+// suggestion and its rationale on each issue, nothing is approved by it. Waits while a spend
+// limit is reached, the similar issue search embeds every issue it looks at.
+pub async fn recommend_budgets(pool: &Pool) -> anyhow::Result<()> {
+    if let Some(reason) = spend_pause_reason(pool).await? {
+        log::info!("Not suggesting budgets, {}", reason);
+        return Ok(());
+    }
+    let mut conn = pool.get_conn().await?;
+
+    let (_, _, budget_balance) = count_budget_by_status(pool).await?;
+    let queued_issues: Option<i32> = conn
+        .query_first("SELECT COUNT(*) FROM issues_master WHERE review_status = 'queue'")
+        .await?;
+    let queued_issues = queued_issues.unwrap_or(0);
+
+    let rows: Vec<Row> = conn
+        .exec(
+            r"SELECT im.issue_id, im.issue_title, im.issue_description, im.repo_stars,
+                im.issue_budget, im.issue_difficulty, im.issue_effort_hours,
+                irs.issue_or_project_summary
+            FROM issues_master im
+            LEFT JOIN issues_repos_summarized irs ON irs.issue_or_project_id = im.issue_id
+            WHERE im.review_status = 'queue'
+            AND (im.date_budget_suggested IS NULL
+                OR im.date_budget_suggested < DATE_SUB(NOW(), INTERVAL :max_age HOUR))
+            ORDER BY im.date_budget_suggested ASC
+            LIMIT 20",
+            params! {
+                "max_age" => SUGGESTION_MAX_AGE_HOURS,
+            },
+        )
+        .await?;
+    log::info!("Issues to suggest budgets for, count: {:?}", rows.len());
+
+    for row in rows {
+        let issue_id: String = row.get("issue_id").unwrap_or_default();
+        let issue_title: String = row.get("issue_title").unwrap_or_default();
+        let issue_description: String = row.get("issue_description").unwrap_or_default();
+        let summary: Option<String> = row
+            .get::<Option<String>, _>("issue_or_project_summary")
+            .unwrap_or(None);
+
+        // summaries are what the vector store holds, compare like with like when there is one
+        let text = summary.unwrap_or_else(|| {
+            format!("{}\n{}", issue_title, issue_description)
+                .chars()
+                .take(2000)
+                .collect()
+        });
+        let similar_approved = similar_approved_budgets(pool, &issue_id, &text)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("Error finding issues similar to {}: {:?}", issue_id, e);
+                Vec::new()
+            });
+
+        let inputs = BudgetInputs {
+            difficulty: row
+                .get::<Option<String>, _>("issue_difficulty")
+                .unwrap_or(None),
+            effort_hours: row
+                .get::<Option<i32>, _>("issue_effort_hours")
+                .unwrap_or(None),
+            repo_stars: row
+                .get::<Option<i32>, _>("repo_stars")
+                .unwrap_or(None)
+                .unwrap_or_default(),
+            requested_budget: row.get::<Option<i32>, _>("issue_budget").unwrap_or(None),
+            similar_approved,
+            budget_balance,
+            queued_issues,
+        };
+        let (suggested_budget, rationale) = recommend_budget(&inputs);
+
+        if let Err(e) = conn
+            .exec_drop(
+                r"UPDATE issues_master
+                SET suggested_budget = :suggested_budget, suggested_budget_rationale = :rationale,
+                date_budget_suggested = NOW()
+                WHERE issue_id = :issue_id",
+                params! {
+                    "issue_id" => &issue_id,
+                    "suggested_budget" => suggested_budget,
+                    "rationale" => rationale.join("; "),
+                },
+            )
+            .await
+        {
+            log::error!("Error saving suggested budget of {}: {:?}", issue_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(difficulty: Option<&str>) -> BudgetInputs {
+        BudgetInputs {
+            difficulty: difficulty.map(String::from),
+            budget_balance: 10_000,
+            queued_issues: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn starts_from_the_difficulty() {
+        let (budget, rationale) = recommend_budget(&inputs(None));
+        assert_eq!(budget, UNCLASSIFIED_BUDGET);
+        assert!(rationale[0].starts_with("not classified"));
+        assert_eq!(recommend_budget(&inputs(Some("good-first-issue"))).0, 30);
+        assert_eq!(recommend_budget(&inputs(Some("advanced"))).0, 200);
+        assert_eq!(
+            recommend_budget(&inputs(Some("unknown"))).0,
+            UNCLASSIFIED_BUDGET
+        );
+    }
+
+    #[test]
+    fn blends_effort_and_stars() {
+        let with_effort = BudgetInputs {
+            effort_hours: Some(4),
+            ..inputs(Some("intermediate"))
+        };
+        // (80 + 4 * 15) / 2
+        assert_eq!(recommend_budget(&with_effort).0, 70);
+
+        let popular = BudgetInputs {
+            repo_stars: 12_000,
+            ..inputs(Some("advanced"))
+        };
+        assert_eq!(recommend_budget(&popular).0, 240);
+    }
+
+    #[test]
+    fn leans_on_similar_approved_issues() {
+        let one = BudgetInputs {
+            similar_approved: vec![(100, 0.9)],
+            ..inputs(Some("good-first-issue"))
+        };
+        // 30 * 0.7 + 100 * 0.3 = 51
+        assert_eq!(recommend_budget(&one).0, 50);
+
+        let several = BudgetInputs {
+            similar_approved: vec![(100, 0.9), (100, 0.8)],
+            ..inputs(Some("good-first-issue"))
+        };
+        // 30 * 0.4 + 100 * 0.6 = 72
+        assert_eq!(recommend_budget(&several).0, 70);
+    }
+
+    #[test]
+    fn never_exceeds_the_requested_budget() {
+        let requested = BudgetInputs {
+            requested_budget: Some(50),
+            ..inputs(Some("intermediate"))
+        };
+        let (budget, rationale) = recommend_budget(&requested);
+        assert_eq!(budget, 50);
+        assert!(rationale.iter().any(|r| r.contains("asks for")));
+
+        // amounts below the minimum are not real requests
+        let tiny = BudgetInputs {
+            requested_budget: Some(5),
+            ..inputs(Some("intermediate"))
+        };
+        assert_eq!(recommend_budget(&tiny).0, 80);
+    }
+
+    #[test]
+    fn stays_within_the_remaining_funds() {
+        let broke = BudgetInputs {
+            budget_balance: 0,
+            ..inputs(Some("advanced"))
+        };
+        assert_eq!(recommend_budget(&broke).0, 0);
+
+        // $100 for 10 queued issues allows twice the $10 share
+        let tight = BudgetInputs {
+            budget_balance: 100,
+            queued_issues: 10,
+            ..inputs(Some("advanced"))
+        };
+        let (budget, rationale) = recommend_budget(&tight);
+        assert_eq!(budget, 20);
+        assert!(rationale.last().unwrap().starts_with("limited to $20"));
+    }
+}
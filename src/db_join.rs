@@ -1,30 +1,67 @@
+use crate::pr_linking::LinkMethod;
+use crate::CAMPAIGN;
 use mysql_async::prelude::*;
 use mysql_async::*;
 
 pub async fn open_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
     let mut conn = pool.get_conn().await?;
 
+    // rows recorded before node ids were captured get them from the staging table
+    let backfill = r"
+    UPDATE issues_master im
+    JOIN issues_open io ON im.issue_id = io.issue_id
+    SET im.issue_node_id = COALESCE(im.issue_node_id, io.issue_node_id),
+        im.project_node_id = COALESCE(im.project_node_id, io.project_node_id),
+        im.issue_creator_node_id = COALESCE(im.issue_creator_node_id, io.issue_creator_node_id);
+    ";
+
+    if let Err(e) = conn.query_drop(backfill).await {
+        log::error!("Error backfilling node ids into issues_master: {:?}", e);
+    };
+
+    // issue_node_id is unique, an issue seen under a new url after a rename or transfer
+    // is not inserted twice; reconciliation moves the existing row to the new url
     let query = r"
     INSERT IGNORE INTO issues_master (
         issue_id, 
+        issue_node_id,
         project_id, 
+        project_node_id,
         issue_title, 
         issue_creator,
+        issue_creator_node_id,
         issue_budget,
-        issue_description
+        issue_budget_confidence,
+        issue_budget_evidence,
+        issue_description,
+        campaign
     )
     SELECT 
         io.issue_id, 
+        io.issue_node_id,
         io.project_id, 
+        io.project_node_id,
         io.issue_title, 
         io.issue_creator,
+        io.issue_creator_node_id,
         io.issue_budget,
-        io.issue_description
+        io.issue_budget_confidence,
+        io.issue_budget_evidence,
+        io.issue_description,
+        :campaign
     FROM 
         issues_open io;
     ";
 
-    if let Err(e) = conn.query_drop(query).await {
+    if let Err(e) = conn
+        .exec_drop(
+            query,
+            params! {
+                "campaign" => CAMPAIGN,
+            },
+        )
+        .await
+    {
         log::error!(
             "Error consolidating issues_open into issues_master: {:?}",
             e
@@ -37,32 +74,204 @@ pub async fn open_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
 pub async fn assigned_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"
-UPDATE issues_master im
-JOIN issues_assigned ia ON im.issue_id = ia.issue_id
-SET im.date_issue_assigned = ia.date_assigned,
-    im.issue_assignees = JSON_ARRAY(ia.issue_assignee);        
+    // staged events go into the history under the url issues_master knows the issue by
+    let history = r"
+INSERT IGNORE INTO issue_assignments (issue_id, event_node_id, event_type, assignee, assignee_node_id, contributor_id, event_actor, event_date)
+SELECT im.issue_id, ia.event_node_id, ia.event_type, ia.issue_assignee, ia.assignee_node_id, c.contributor_id, ia.event_actor, ia.date_assigned
+FROM issues_assigned ia
+JOIN issues_master im ON im.issue_id = ia.issue_id
+    OR (ia.issue_node_id IS NOT NULL AND im.issue_node_id = ia.issue_node_id)
+LEFT JOIN contributors c ON c.login = ia.issue_assignee
+WHERE ia.issue_assignee IS NOT NULL AND ia.date_assigned IS NOT NULL;
     ";
 
-    if let Err(e) = conn.query_drop(query).await {
+    if let Err(e) = conn.query_drop(history).await {
         log::error!(
-            "Error consolidating issues_assigned into issues_master: {:?}",
+            "Error recording issues_assigned into issue_assignments: {:?}",
             e
         );
     };
 
+    // a webhook delivery whose event could not be looked up is recorded at delivery time
+    // without an event id; once the search brings the event itself, that row goes
+    let dedupe = r"
+DELETE w FROM issue_assignments w
+JOIN issue_assignments e ON e.issue_id = w.issue_id
+    AND e.assignee = w.assignee
+    AND e.event_type = w.event_type
+    AND e.event_node_id IS NOT NULL
+WHERE w.event_node_id IS NULL
+AND w.source = 'event'
+AND w.event_date BETWEEN e.event_date AND e.event_date + INTERVAL 10 MINUTE;
+    ";
+
+    if let Err(e) = conn.query_drop(dedupe).await {
+        log::error!("Error removing duplicate assignment events: {:?}", e);
+    };
+
+    // only issues with newly staged events are recomputed
+    derive_issue_assignees(
+        &mut conn,
+        r"SELECT DISTINCT im2.issue_id
+        FROM issues_assigned ia
+        JOIN issues_master im2 ON im2.issue_id = ia.issue_id
+            OR (ia.issue_node_id IS NOT NULL AND im2.issue_node_id = ia.issue_node_id)",
+        Params::Empty,
+    )
+    .await?;
+
+    Ok(())
+}
+
+// The one place issues_master.issue_assignees is written, from issue_assignments: an assignee
+// is current when their latest event is an assignment, issues left without assignees get
+// NULL. date_issue_assigned is the latest assignment GitHub reported an event for, snapshot
+// rows carry no real date. `scope` selects the issue_id of the issues to recompute.
+async fn derive_issue_assignees(conn: &mut Conn, scope: &str, params: Params) -> Result<()> {
+    let query = format!(
+        r"
+UPDATE issues_master im
+JOIN ({}) scope ON scope.issue_id = im.issue_id
+LEFT JOIN (
+    SELECT issue_id,
+        JSON_ARRAYAGG(assignee) AS assignees,
+        MAX(IF(source = 'event', event_date, NULL)) AS last_assigned
+    FROM (
+        SELECT issue_id, assignee, event_type, event_date, source,
+            ROW_NUMBER() OVER (
+                PARTITION BY issue_id, assignee
+                ORDER BY event_date DESC, assignment_id DESC
+            ) AS rn
+        FROM issue_assignments
+    ) latest
+    WHERE rn = 1 AND event_type = 'assigned'
+    GROUP BY issue_id
+) cur ON cur.issue_id = im.issue_id
+SET im.issue_assignees = cur.assignees,
+    im.date_issue_assigned = IF(
+        cur.assignees IS NULL, NULL, COALESCE(cur.last_assigned, im.date_issue_assigned)
+    );
+    ",
+        scope
+    );
+
+    if let Err(e) = conn.exec_drop(query, params).await {
+        log::error!("Error deriving issue assignees: {:?}", e);
+        return Err(e);
+    };
+
     Ok(())
 }
 
+// Records the assignees an issue has now, as seen by the closed issue search, reconciliation
+// or a stale release, as snapshot rows for whoever was assigned or unassigned without an
+// event in the history, then derives the issue's assignees again.
+pub async fn record_assignee_snapshot(
+    pool: &mysql_async::Pool,
+    issue_id: &str,
+    assignees: &[String],
+    event_actor: &str,
+) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let current: Vec<String> = conn
+        .exec_map(
+            r"SELECT assignee FROM (
+                SELECT assignee, event_type,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY assignee
+                        ORDER BY event_date DESC, assignment_id DESC
+                    ) AS rn
+                FROM issue_assignments
+                WHERE issue_id = :issue_id
+            ) latest
+            WHERE rn = 1 AND event_type = 'assigned'",
+            params! {
+                "issue_id" => issue_id,
+            },
+            |assignee: String| assignee,
+        )
+        .await?;
+
+    let contains =
+        |list: &[String], login: &str| list.iter().any(|l| l.eq_ignore_ascii_case(login));
+    let mut changes: Vec<(&str, &str)> = assignees
+        .iter()
+        .filter(|a| !contains(&current, a))
+        .map(|a| ("assigned", a.as_str()))
+        .collect();
+    changes.extend(
+        current
+            .iter()
+            .filter(|c| !contains(assignees, c))
+            .map(|c| ("unassigned", c.as_str())),
+    );
+
+    if !changes.is_empty() {
+        let query = r"INSERT IGNORE INTO issue_assignments (issue_id, event_type, assignee, contributor_id, event_actor, event_date, source)
+        SELECT :issue_id, :event_type, :assignee, (SELECT contributor_id FROM contributors WHERE login = :assignee LIMIT 1), :event_actor, NOW(), 'snapshot'";
+
+        if let Err(e) = query
+            .with(changes.iter().map(|(event_type, assignee)| {
+                params! {
+                    "issue_id" => issue_id,
+                    "event_type" => *event_type,
+                    "assignee" => *assignee,
+                    "event_actor" => event_actor,
+                }
+            }))
+            .batch(&mut conn)
+            .await
+        {
+            log::error!("Error recording assignee snapshot: {:?}", e);
+            return Err(e);
+        };
+    }
+
+    derive_issue_assignees(
+        &mut conn,
+        r"SELECT :issue_id AS issue_id",
+        params! {
+            "issue_id" => issue_id,
+        },
+    )
+    .await
+}
+
 pub async fn closed_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
     let mut conn = pool.get_conn().await?;
 
+    // the assignees of a closed issue go into the history like any other change
+    let closed: Vec<(String, Option<String>)> = conn
+        .query(
+            r"SELECT im.issue_id, ic.issue_assignees
+            FROM issues_master im
+            JOIN issues_closed ic ON im.issue_id = ic.issue_id
+                OR (ic.issue_node_id IS NOT NULL AND im.issue_node_id = ic.issue_node_id)",
+        )
+        .await?;
+    for (issue_id, issue_assignees) in closed {
+        let assignees: Vec<String> = issue_assignees
+            .and_then(|s| serde_json::from_str::<Vec<Option<String>>>(&s).ok())
+            .map(|v| v.into_iter().flatten().collect())
+            .unwrap_or_default();
+        if let Err(e) =
+            record_assignee_snapshot(pool, &issue_id, &assignees, "closed issue search").await
+        {
+            log::error!("Error recording assignees of closed {}: {:?}", issue_id, e);
+        }
+    }
+
     let query = r"
     UPDATE issues_master im
     JOIN issues_closed ic ON im.issue_id = ic.issue_id
+        OR (ic.issue_node_id IS NOT NULL AND im.issue_node_id = ic.issue_node_id)
     SET
-        im.issue_assignees = ic.issue_assignees,
-        im.issue_linked_pr = ic.issue_linked_pr;
+        im.issue_linked_pr = COALESCE(ic.issue_linked_pr, im.issue_linked_pr),
+        im.issue_linked_pr_node_id = IF(ic.issue_linked_pr IS NULL, im.issue_linked_pr_node_id, ic.issue_linked_pr_node_id),
+        im.issue_linked_pr_method = IF(ic.issue_linked_pr IS NULL, im.issue_linked_pr_method, ic.issue_linked_pr_method),
+        im.issue_linked_pr_confidence = IF(ic.issue_linked_pr IS NULL, im.issue_linked_pr_confidence, ic.issue_linked_pr_confidence),
+        im.issue_state = 'closed';
     ";
 
     if let Err(e) = conn.query_drop(query).await {
@@ -94,6 +303,28 @@ pub async fn comment_master(pool: &mysql_async::Pool) -> Result<()> {
     Ok(())
 }
 
+pub async fn classification_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"
+    UPDATE issues_master im
+    JOIN issue_classifications ic ON im.issue_id = ic.issue_id
+    SET im.issue_difficulty = ic.difficulty,
+        im.issue_effort_hours = ic.effort_hours,
+        im.issue_skills = ic.skills,
+        im.issue_type = ic.issue_type;
+        ";
+
+    if let Err(e) = conn.query_drop(query).await {
+        log::error!(
+            "Error consolidating issue_classifications into issues_master: {:?}",
+            e
+        );
+    };
+
+    Ok(())
+}
+
 pub async fn project_master_back_sync(pool: &mysql_async::Pool) -> anyhow::Result<()> {
     let mut conn = pool.get_conn().await?;
 
@@ -115,19 +346,82 @@ pub async fn project_master_back_sync(pool: &mysql_async::Pool) -> anyhow::Resul
     Ok(())
 }
 
-pub async fn remove_pull_by_issued_linked_pr(pool: &mysql_async::Pool) -> anyhow::Result<()> {
+// Closed issues the timeline gave no PR for get the latest merged PR an assignee authored
+// in the same repo after being assigned, if there is one not already linked elsewhere.
+pub async fn link_assignee_pulls(pool: &mysql_async::Pool) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let matches: Vec<(String, String, Option<String>)> = conn
+        .query_map(
+            r"SELECT im.issue_id, pr.pull_id, pr.pull_node_id
+            FROM issues_master im
+            JOIN pull_requests pr ON pr.pull_id = (
+                SELECT pr2.pull_id FROM pull_requests pr2
+                WHERE pr2.project_id = im.project_id
+                AND pr2.issue_id IS NULL
+                AND JSON_CONTAINS(im.issue_assignees, JSON_QUOTE(pr2.pull_author))
+                AND (im.date_issue_assigned IS NULL OR pr2.date_merged >= im.date_issue_assigned)
+                AND pr2.pull_id NOT IN (
+                    SELECT issue_linked_pr FROM issues_master WHERE issue_linked_pr IS NOT NULL
+                )
+                ORDER BY pr2.date_merged DESC
+                LIMIT 1
+            )
+            WHERE im.issue_state = 'closed'
+            AND im.issue_linked_pr IS NULL
+            AND im.issue_assignees IS NOT NULL",
+            |(issue_id, pull_id, pull_node_id): (String, String, Option<String>)| {
+                (issue_id, pull_id, pull_node_id)
+            },
+        )
+        .await?;
+
+    // two issues of one assignee can pick the same PR, the first update takes it and the
+    // join on `other` skips the rest; MySQL refuses a NOT EXISTS on the updated table
+    for (issue_id, pull_id, pull_node_id) in matches {
+        if let Err(e) = conn
+            .exec_drop(
+                r"UPDATE issues_master im
+                LEFT JOIN issues_master other ON other.issue_linked_pr = :pull_id
+                SET im.issue_linked_pr = :pull_id,
+                    im.issue_linked_pr_node_id = :pull_node_id,
+                    im.issue_linked_pr_method = :method,
+                    im.issue_linked_pr_confidence = :confidence
+                WHERE im.issue_id = :issue_id
+                AND im.issue_linked_pr IS NULL
+                AND other.issue_id IS NULL",
+                params! {
+                    "issue_id" => &issue_id,
+                    "pull_id" => &pull_id,
+                    "pull_node_id" => &pull_node_id,
+                    "method" => LinkMethod::AssigneePull.as_str(),
+                    "confidence" => LinkMethod::AssigneePull.confidence(),
+                },
+            )
+            .await
+        {
+            log::error!("Error linking {} to {}: {:?}", pull_id, issue_id, e);
+        };
+    }
+
+    Ok(())
+}
+
+// PRs already accounted for by an issue are marked with it, they stay in pull_requests
+// for the contributor stats.
+pub async fn mark_linked_pulls(pool: &mysql_async::Pool) -> anyhow::Result<()> {
     let mut conn = pool.get_conn().await?;
 
     let query = r#"
-    DELETE FROM pull_requests
-    WHERE pull_id IN (
-        SELECT issue_linked_pr FROM issues_master WHERE issue_linked_pr IS NOT NULL
-    );
+    UPDATE pull_requests pr
+    JOIN issues_master im ON im.issue_linked_pr = pr.pull_id
+    SET pr.issue_id = im.issue_id
+    WHERE pr.issue_id IS NULL;
             "#;
 
     if let Err(e) = conn.query_drop(query).await {
         log::error!(
-            "Error removing pull_request from issues_master by issue_linked_pr: {:?}",
+            "Error linking pull_request to issues_master by issue_linked_pr: {:?}",
             e
         );
     };
@@ -174,15 +468,17 @@ pub async fn master_project(pool: &mysql_async::Pool) -> anyhow::Result<()> {
     let mut conn = pool.get_conn().await?;
 
     let query = r"
-    INSERT INTO projects (project_id, issues_list)
+    INSERT INTO projects (project_id, project_node_id, issues_list)
     SELECT 
         project_id,
+        MAX(project_node_id),
         JSON_ARRAYAGG(issue_id)
     FROM 
-        (SELECT DISTINCT project_id, issue_id FROM issues_master) AS distinct_issues
+        (SELECT DISTINCT project_id, project_node_id, issue_id FROM issues_master) AS distinct_issues
     GROUP BY 
         project_id
     ON DUPLICATE KEY UPDATE
+        project_node_id = COALESCE(projects.project_node_id, VALUES(project_node_id)),
         issues_list = VALUES(issues_list);
         ";
 
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 
+use crate::db_join::record_assignee_snapshot;
 use crate::db_populate::*;
 use crate::issue_tracker::IssueOpen;
+use crate::spam_score::SPAM_THRESHOLD;
+use crate::tag_taxonomy::lookup_canonical_tags;
 use crate::TOTAL_BUDGET;
 use anyhow::anyhow;
 use mysql_async::prelude::*;
@@ -104,7 +107,41 @@ pub async fn count_budget_by_status(pool: &Pool) -> anyhow::Result<(i32, i32, i3
     Ok((TOTAL_BUDGET, total_budget_allocated, budget_balance))
 }
 
+// "difficulty:<level>", "type:<type>", "skill:<name>" and "max_effort:<hours>" filters;
+// values are checked before going into the query since filters come from the request.
+fn classification_condition(filter: &str) -> Option<String> {
+    let (key, value) = filter.split_once(':')?;
+    let value = value.trim().to_lowercase();
+
+    match key {
+        "difficulty" if DIFFICULTIES.contains(&value.as_str()) => {
+            Some(format!("issue_difficulty = '{}'", value))
+        }
+        "type" if ISSUE_TYPES.contains(&value.as_str()) => {
+            Some(format!("issue_type = '{}'", value))
+        }
+        "skill"
+            if !value.is_empty()
+                && value
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || " +#.-_/".contains(c)) =>
+        {
+            Some(format!(
+                "JSON_CONTAINS(issue_skills, JSON_QUOTE('{}'))",
+                value
+            ))
+        }
+        "max_effort" => value
+            .parse::<i32>()
+            .ok()
+            .map(|hours| format!("issue_effort_hours <= {}", hours)),
+        _ => None,
+    }
+}
+
 fn build_query_clause(filters: Vec<&str>) -> String {
+    let spam = format!("spam_score >= {}", SPAM_THRESHOLD);
+    let not_spam = format!("(spam_score IS NULL OR spam_score < {})", SPAM_THRESHOLD);
     let schema_array = [
         ("repo_stars", "repo_stars DESC"),
         ("issue_title", "issue_title ASC"),
@@ -113,6 +150,15 @@ fn build_query_clause(filters: Vec<&str>) -> String {
         ("issue_budget", "issue_budget DESC"),
         ("issue_assignees", "issue_assignees ASC"),
         ("date_issue_assigned", "date_issue_assigned ASC"),
+        ("spam_score", "spam_score DESC"),
+        (
+            "issue_difficulty",
+            "issue_difficulty IS NULL, FIELD(issue_difficulty, 'good-first-issue', 'intermediate', 'advanced') ASC",
+        ),
+        (
+            "issue_effort_hours",
+            "issue_effort_hours IS NULL, issue_effort_hours ASC",
+        ),
     ];
 
     let special_conditions = [
@@ -121,22 +167,32 @@ fn build_query_clause(filters: Vec<&str>) -> String {
         ("queue", "review_status = 'queue'"),
         ("approve", "review_status = 'approve'"),
         ("decline", "review_status = 'decline'"),
+        ("spam", spam.as_str()),
+        ("not_spam", not_spam.as_str()),
     ];
 
     let schema_map: HashMap<&str, &str> = schema_array.into_iter().collect();
     let condition_map: HashMap<&str, &str> = special_conditions.into_iter().collect();
 
-    let mut where_clause = String::new();
+    let mut wheres = Vec::new();
     let mut order_bys = Vec::new();
 
     for &filter in &filters {
         if let Some(&condition) = condition_map.get(filter) {
-            where_clause = format!("WHERE {}", condition);
+            wheres.push(condition.to_string());
         } else if let Some(&order_by) = schema_map.get(filter) {
             order_bys.push(order_by);
+        } else if let Some(condition) = classification_condition(filter) {
+            wheres.push(condition);
         }
     }
 
+    let where_clause = if wheres.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", wheres.join(" AND "))
+    };
+
     let order_by_clause = if order_bys.is_empty() {
         String::new()
     } else {
@@ -165,7 +221,7 @@ pub async fn list_issues_by_multi(
     let filter_str = build_query_clause(filters);
 
     let query = format!(
-        "SELECT issue_id, project_id, project_logo, issue_title, main_language, repo_stars, issue_budget, issue_creator, issue_description, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved FROM issues_master {} LIMIT {} OFFSET {}",
+        "SELECT issue_id, project_id, project_logo, issue_title, main_language, repo_stars, issue_budget, issue_budget_confidence, issue_budget_evidence, issue_creator, issue_description, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved, spam_score, spam_signals, issue_difficulty, issue_effort_hours, issue_skills, issue_type, suggested_budget, suggested_budget_rationale FROM issues_master {} LIMIT {} OFFSET {}",
         filter_str, page_size, offset
     );
 
@@ -184,6 +240,12 @@ pub async fn list_issues_by_multi(
             main_language: row.get("main_language").unwrap_or_default(),
             repo_stars: row.get::<i32, _>("repo_stars").unwrap_or_default(),
             issue_budget: row.get::<Option<i32>, _>("issue_budget").unwrap_or(None),
+            issue_budget_confidence: row
+                .get::<Option<f32>, _>("issue_budget_confidence")
+                .unwrap_or(None),
+            issue_budget_evidence: row
+                .get::<Option<String>, _>("issue_budget_evidence")
+                .unwrap_or(None),
             issue_creator: row.get("issue_creator").unwrap_or_default(),
             issue_description: row.get("issue_description").unwrap_or_default(),
             issue_assignees: row
@@ -197,6 +259,22 @@ pub async fn list_issues_by_multi(
             issue_budget_approved: row
                 .get::<bool, _>("issue_budget_approved")
                 .unwrap_or_default(),
+            spam_score: row.get::<Option<f32>, _>("spam_score").unwrap_or(None),
+            spam_signals: row.get::<Option<String>, _>("spam_signals").unwrap_or(None),
+            issue_difficulty: row
+                .get::<Option<String>, _>("issue_difficulty")
+                .unwrap_or(None),
+            issue_effort_hours: row
+                .get::<Option<i32>, _>("issue_effort_hours")
+                .unwrap_or(None),
+            issue_skills: row.get::<Option<String>, _>("issue_skills").unwrap_or(None),
+            issue_type: row.get::<Option<String>, _>("issue_type").unwrap_or(None),
+            suggested_budget: row
+                .get::<Option<i32>, _>("suggested_budget")
+                .unwrap_or(None),
+            suggested_budget_rationale: row
+                .get::<Option<String>, _>("suggested_budget_rationale")
+                .unwrap_or(None),
             running_budget: (total_budget, total_budget_allocated, budget_balance),
             issue_stats: (total_count, queue_count, approve_count, decline_count),
         };
@@ -301,7 +379,7 @@ pub async fn get_issues_open_from_master(
     let mut conn = pool.get_conn().await?;
 
     let query = format!(
-        "SELECT issue_title, issue_id, issue_creator, issue_description, project_id FROM issues_master 
+        "SELECT issue_title, issue_id, issue_node_id, issue_creator, issue_description, project_id, project_node_id FROM issues_master 
         WHERE issue_id NOT IN (SELECT issue_or_project_id FROM issues_repos_summarized WHERE issue_or_project_summary IS NOT NULL) 
         ORDER BY issue_id ASC
         LIMIT {} OFFSET {}",
@@ -311,19 +389,33 @@ pub async fn get_issues_open_from_master(
     let out: Vec<IssueOpen> = conn
         .query_map(
             query,
-            |(issue_title, issue_id, issue_creator, issue_description, project_id): (
+            |(
+                issue_title,
+                issue_id,
+                issue_node_id,
+                issue_creator,
+                issue_description,
+                project_id,
+                project_node_id,
+            ): (
                 String,
                 String,
+                Option<String>,
                 String,
                 String,
                 String,
+                Option<String>,
             )| IssueOpen {
                 issue_title,
                 issue_id,
+                issue_node_id,
                 issue_creator,
                 issue_budget: 0,
                 issue_description,
                 project_id,
+                project_node_id,
+                issue_creator_node_id: None,
+                issue_budget_evidence: None,
             },
         )
         .await?;
@@ -440,6 +532,8 @@ pub struct IssueAndComments {
     pub issue_creator: String,
     pub issue_description: String,
     pub issue_budget: Option<i32>,
+    pub issue_budget_confidence: Option<f32>,
+    pub issue_budget_evidence: Option<String>,
     pub issue_assignees: Option<String>, // or a more specific type if you know the structure of the JSON
     pub issue_linked_pr: Option<String>,
     pub issue_status: Option<String>,
@@ -455,7 +549,7 @@ pub async fn get_issue_w_comments_by_id(
     let mut conn = pool.get_conn().await?;
 
     let issue_query = format!(
-        "SELECT issue_id, project_id, main_language, repo_stars, issue_title, issue_creator, issue_description, issue_budget, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved FROM issues_master WHERE issue_id = '{}'",
+        "SELECT issue_id, project_id, main_language, repo_stars, issue_title, issue_creator, issue_description, issue_budget, issue_budget_confidence, issue_budget_evidence, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved, spam_score, spam_signals, issue_difficulty, issue_effort_hours, issue_skills, issue_type, suggested_budget, suggested_budget_rationale FROM issues_master WHERE issue_id = '{}'",
         issue_id
     );
 
@@ -484,6 +578,12 @@ pub async fn get_issue_w_comments_by_id(
         issue_budget: issue_row
             .get::<Option<i32>, _>("issue_budget")
             .unwrap_or(None),
+        issue_budget_confidence: issue_row
+            .get::<Option<f32>, _>("issue_budget_confidence")
+            .unwrap_or(None),
+        issue_budget_evidence: issue_row
+            .get::<Option<String>, _>("issue_budget_evidence")
+            .unwrap_or(None),
         issue_assignees: issue_row
             .get::<Option<String>, _>("issue_assignees")
             .unwrap_or(None),
@@ -497,6 +597,30 @@ pub async fn get_issue_w_comments_by_id(
         issue_budget_approved: issue_row
             .get::<bool, _>("issue_budget_approved")
             .unwrap_or_default(),
+        spam_score: issue_row
+            .get::<Option<f32>, _>("spam_score")
+            .unwrap_or(None),
+        spam_signals: issue_row
+            .get::<Option<String>, _>("spam_signals")
+            .unwrap_or(None),
+        issue_difficulty: issue_row
+            .get::<Option<String>, _>("issue_difficulty")
+            .unwrap_or(None),
+        issue_effort_hours: issue_row
+            .get::<Option<i32>, _>("issue_effort_hours")
+            .unwrap_or(None),
+        issue_skills: issue_row
+            .get::<Option<String>, _>("issue_skills")
+            .unwrap_or(None),
+        issue_type: issue_row
+            .get::<Option<String>, _>("issue_type")
+            .unwrap_or(None),
+        suggested_budget: issue_row
+            .get::<Option<i32>, _>("suggested_budget")
+            .unwrap_or(None),
+        suggested_budget_rationale: issue_row
+            .get::<Option<String>, _>("suggested_budget_rationale")
+            .unwrap_or(None),
         running_budget: (99999, 99999, 99999),
         issue_stats: (99999, 99999, 99999, 99999),
     };
@@ -522,6 +646,8 @@ pub async fn get_issue_w_comments_by_id(
         issue_creator: issue.issue_creator,
         issue_description: issue.issue_description,
         issue_budget: issue.issue_budget,
+        issue_budget_confidence: issue.issue_budget_confidence,
+        issue_budget_evidence: issue.issue_budget_evidence,
         issue_assignees: issue.issue_assignees,
         issue_linked_pr: issue.issue_linked_pr,
         issue_status: issue.issue_status,
@@ -581,7 +707,8 @@ pub async fn get_issue_ids_with_budget(pool: &Pool) -> Result<Vec<String>> {
 
     let selected_rows: Vec<String> = conn
         .exec_map(
-            "SELECT issue_id FROM issues_master WHERE issue_budget > 0 AND review_status='approve' AND date_issue_assigned > :one_hour_ago",
+            "SELECT issue_id FROM issues_master WHERE issue_budget > 0 AND review_status='approve' AND date_issue_assigned > :one_hour_ago
+            AND issue_id NOT IN (SELECT issue_id FROM notifications WHERE notification_kind = 'budget_allocated')",
                         params! {
                 "one_hour_ago" => &one_hour_ago
             },
@@ -595,7 +722,8 @@ pub async fn get_issue_ids_declined(pool: &Pool) -> Result<Vec<String>> {
     let mut conn = pool.get_conn().await?;
     let selected_rows: Vec<String> = conn
         .query_map(
-            "select issue_id from issues_master where review_status='decline' limit 5;",
+            "select issue_id from issues_master where review_status='decline'
+            and issue_id not in (select issue_id from notifications where notification_kind = 'issue_declined') limit 5;",
             |issue_id| issue_id,
         )
         .await?;
@@ -608,32 +736,151 @@ pub async fn get_issue_ids_distribute_fund(
     let mut conn = pool.get_conn().await?;
     let selected_rows: Vec<(Option<String>, String, i32)> = conn
         .query_map(
-            "SELECT issue_assignees, issue_id, issue_budget FROM issues_master WHERE issue_budget_approved=1 LIMIT 5",
+            "SELECT issue_assignees, issue_id, issue_budget FROM issues_master WHERE issue_budget_approved=1
+            AND issue_id NOT IN (SELECT issue_id FROM notifications WHERE notification_kind = 'distribute_fund') LIMIT 5",
             |(issue_assignees, issue_id, issue_budget): (Option<String>, Option<String>, Option<i32>)| {
-                let issue_assignee = issue_assignees.and_then(|s| s.split(",").next().map(String::from));
+                let issue_assignee = issue_assignees
+                    .and_then(|s| serde_json::from_str::<Vec<Option<String>>>(&s).ok())
+                    .and_then(|v| v.into_iter().flatten().next());
                 (issue_assignee, issue_id.unwrap_or_default(), issue_budget.unwrap_or(0))
             },
         )
         .await?;
     Ok(selected_rows)
 }
-pub async fn get_issue_ids_one_month_no_activity(pool: &Pool) -> Result<Vec<String>> {
+// Assigned issues without a linked PR, with the days since the assignee's last sign of life:
+// the assignment itself, their latest comment on the issue, or a PR they merged in the same repo.
+// Also the days since the last stale_* notice was queued and since the final warning was
+// posted, None when there is none.
+pub async fn get_stale_assignments(
+    pool: &Pool,
+    min_idle_days: i64,
+) -> Result<Vec<(String, i64, Option<i64>, Option<i64>)>> {
     let mut conn = pool.get_conn().await?;
-    // let _one_month_ago =
-    //     (Utc::now() - Duration::try_days(30).unwrap().to_std().unwrap()).naive_utc();
-    // let _formatted_one_month_ago = _one_month_ago.format("%Y-%m-%d %H:%M:%S").to_string();
-    let formatted_one_month_ago = "2023-10-06 13:04:00".to_string();
 
-    let selected_rows: Vec<String> = conn.exec_map(
-        "SELECT issue_id FROM issues_master WHERE date_issue_assigned < :formatted_one_month_ago AND issue_linked_pr IS NULL",
-        params! {
-            "one_month_ago" => formatted_one_month_ago,
-        },
-        |issue_id| issue_id,
-    ).await?;
+    let selected_rows: Vec<(String, i64, Option<i64>, Option<i64>)> = conn
+        .exec_map(
+            r"SELECT issue_id, idle_days, noticed_days_ago, warned_days_ago FROM (
+                SELECT im.issue_id,
+                    TIMESTAMPDIFF(DAY, GREATEST(
+                        im.date_issue_assigned,
+                        COALESCE((
+                            SELECT MAX(ic.comment_date) FROM issues_comment ic
+                            WHERE ic.issue_id = im.issue_id
+                            AND JSON_CONTAINS(im.issue_assignees, JSON_QUOTE(ic.comment_creator))
+                        ), im.date_issue_assigned),
+                        COALESCE((
+                            SELECT MAX(pr.date_merged) FROM pull_requests pr
+                            WHERE pr.project_id = im.project_id
+                            AND JSON_CONTAINS(im.issue_assignees, JSON_QUOTE(pr.pull_author))
+                        ), im.date_issue_assigned)
+                    ), NOW()) AS idle_days,
+                    (
+                        SELECT TIMESTAMPDIFF(DAY, MAX(n.date_created), NOW()) FROM notifications n
+                        WHERE n.issue_id = im.issue_id
+                        AND n.notification_kind IN ('stale_reminder', 'stale_final_warning')
+                    ) AS noticed_days_ago,
+                    (
+                        SELECT TIMESTAMPDIFF(DAY, n.date_sent, NOW()) FROM notifications n
+                        WHERE n.issue_id = im.issue_id
+                        AND n.notification_kind = 'stale_final_warning'
+                        AND n.status = 'sent'
+                    ) AS warned_days_ago
+                FROM issues_master im
+                WHERE im.date_issue_assigned IS NOT NULL
+                AND im.issue_assignees IS NOT NULL
+                AND im.issue_linked_pr IS NULL
+                AND im.issue_budget_approved = 0
+            ) AS activity
+            WHERE idle_days >= :min_idle_days",
+            params! {
+                "min_idle_days" => min_idle_days,
+            },
+            |(issue_id, idle_days, noticed_days_ago, warned_days_ago): (
+                String,
+                i64,
+                Option<i64>,
+                Option<i64>,
+            )| (issue_id, idle_days, noticed_days_ago, warned_days_ago),
+        )
+        .await?;
     Ok(selected_rows)
 }
 
+pub async fn get_issue_assignees(pool: &Pool, issue_id: &str) -> Result<Vec<String>> {
+    let mut conn = pool.get_conn().await?;
+
+    let issue_assignees: Option<Option<String>> = conn
+        .exec_first(
+            r"SELECT issue_assignees FROM issues_master WHERE issue_id = :issue_id",
+            params! {
+                "issue_id" => issue_id,
+            },
+        )
+        .await?;
+
+    Ok(issue_assignees
+        .flatten()
+        .and_then(|s| serde_json::from_str::<Vec<Option<String>>>(&s).ok())
+        .map(|v| v.into_iter().flatten().collect())
+        .unwrap_or_default())
+}
+
+// Un-assigns an issue and puts it back in the review queue so it can be offered again.
+pub async fn release_stale_assignment(pool: &Pool, issue_id: &str) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let queries = vec![
+        r"UPDATE issues_master
+        SET review_status = 'queue',
+            date_approved = NULL,
+            issue_status = CONCAT('stale assignment released on ', DATE(NOW()))
+        WHERE issue_id = :issue_id",
+        r"DELETE FROM issues_assigned WHERE issue_id = :issue_id",
+    ];
+
+    for query in queries {
+        if let Err(e) = conn
+            .exec_drop(
+                query,
+                params! {
+                    "issue_id" => issue_id,
+                },
+            )
+            .await
+        {
+            log::error!("Error releasing stale assignment: {:?}", e);
+            return Err(e);
+        };
+    }
+    // the assignees are unassigned through the history, which clears issue_assignees
+    record_assignee_snapshot(pool, issue_id, &[], "stale release").await
+}
+
+// Drops the stale_* notices of an issue so the next idle stretch, or the next assignee,
+// goes through reminder and final warning again.
+pub async fn clear_stale_notifications(pool: &Pool, issue_id: &str) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    if let Err(e) = conn
+        .exec_drop(
+            r"DELETE FROM notifications
+            WHERE issue_id = :issue_id
+            AND notification_kind IN ('stale_reminder', 'stale_final_warning', 'stale_released')
+            AND status <> 'sending'",
+            params! {
+                "issue_id" => issue_id,
+            },
+        )
+        .await
+    {
+        log::error!("Error clearing stale notifications: {:?}", e);
+        return Err(e);
+    };
+
+    Ok(())
+}
+
 pub async fn assign_issue_budget_in_db(
     pool: &mysql_async::Pool,
     issue_id: &str,
@@ -746,6 +993,41 @@ pub async fn conclude_issue_in_db(pool: &mysql_async::Pool, issue_id: &str) -> R
         .await
     {
         log::error!("Error concluding issue: {:?}", e);
+        return Err(e);
+    };
+
+    record_payouts(pool, issue_id).await
+}
+
+// Splits the budget of a concluded issue evenly between its current assignees, the
+// remainder of the integer division stays with the campaign. Concluding again after a
+// budget edit updates the amounts.
+pub async fn record_payouts(pool: &mysql_async::Pool, issue_id: &str) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT INTO payouts (issue_id, contributor_id, login, amount)
+                  SELECT im.issue_id, c.contributor_id, a.login,
+                      im.issue_budget DIV JSON_LENGTH(im.issue_assignees)
+                  FROM issues_master im
+                  JOIN JSON_TABLE(im.issue_assignees, '$[*]' COLUMNS (login VARCHAR(50) PATH '$')) a
+                  LEFT JOIN contributors c ON c.login = a.login
+                  WHERE im.issue_id = :issue_id
+                  AND im.issue_budget_approved = True
+                  AND JSON_LENGTH(im.issue_assignees) > 0
+                  ON DUPLICATE KEY UPDATE
+                  contributor_id = COALESCE(VALUES(contributor_id), contributor_id),
+                  amount = VALUES(amount)";
+
+    if let Err(e) = conn
+        .exec_drop(
+            query,
+            params! {
+                "issue_id" => issue_id,
+            },
+        )
+        .await
+    {
+        log::error!("Error recording payouts for {}: {:?}", issue_id, e);
     };
 
     Ok(())
@@ -772,12 +1054,304 @@ pub async fn conclude_issues_batch_in_db(
             .await
         {
             log::error!("Error concluding issues batch: {:?}", e);
+            return Err(e);
         };
+        record_payouts(pool, issue_id).await?;
     }
 
     Ok(())
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PullOut {
+    pub pull_id: String,
+    pub pull_title: String,
+    pub pull_author: Option<String>,
+    pub project_id: String,
+    pub issue_id: Option<String>,
+    pub date_merged: Option<String>,
+    pub additions: Option<i32>,
+    pub deletions: Option<i32>,
+    pub changed_files: Option<i32>,
+    pub approvals: i32,
+    pub approvers: Vec<String>,
+    pub ci_status: Option<String>,
+    pub pull_labels: Vec<String>,
+}
+
+// PRs changing fewer lines than this are listed as low effort
+pub static LOW_EFFORT_CHANGES: i32 = 10;
+
+// Filters narrow the list together, sorts apply in the order given.
+fn build_pull_query_clause(filters: Vec<&str>) -> String {
+    let low_effort = format!(
+        "COALESCE(additions, 0) + COALESCE(deletions, 0) < {}",
+        LOW_EFFORT_CHANGES
+    );
+    let conditions = [
+        ("low_effort", low_effort.as_str()),
+        ("ci_failing", "ci_status IN ('FAILURE', 'ERROR')"),
+        ("unapproved", "approvals = 0"),
+        (
+            "flagged",
+            "(JSON_CONTAINS(pull_labels, '\"spam\"') OR JSON_CONTAINS(pull_labels, '\"invalid\"'))",
+        ),
+        ("unlinked", "issue_id IS NULL"),
+    ];
+    let sorts = [
+        (
+            "changes",
+            "COALESCE(additions, 0) + COALESCE(deletions, 0) DESC",
+        ),
+        ("approvals", "approvals DESC"),
+        ("date_merged", "date_merged DESC"),
+    ];
+
+    let condition_map: HashMap<&str, &str> = conditions.into_iter().collect();
+    let sort_map: HashMap<&str, &str> = sorts.into_iter().collect();
+
+    let mut wheres = Vec::new();
+    let mut order_bys = Vec::new();
+    for filter in filters {
+        if let Some(&condition) = condition_map.get(filter) {
+            wheres.push(condition);
+        } else if let Some(&order_by) = sort_map.get(filter) {
+            order_bys.push(order_by);
+        }
+    }
+
+    let where_clause = if wheres.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", wheres.join(" AND "))
+    };
+    let order_by_clause = if order_bys.is_empty() {
+        String::from("ORDER BY date_merged DESC")
+    } else {
+        format!("ORDER BY {}", order_bys.join(", "))
+    };
+
+    format!("{} {}", where_clause, order_by_clause)
+        .trim()
+        .to_string()
+}
+
+pub async fn list_pulls_by_multi(
+    pool: &Pool,
+    filters: Vec<&str>,
+    page: usize,
+    page_size: usize,
+) -> Result<Vec<PullOut>> {
+    let mut conn = pool.get_conn().await?;
+    let offset = (page - 1) * page_size;
+
+    let query = format!(
+        "SELECT pull_id, pull_title, pull_author, project_id, issue_id, DATE_FORMAT(date_merged, '%Y-%m-%d %H:%i:%s'),
+        additions, deletions, changed_files, approvals, approvers, ci_status, pull_labels
+        FROM pull_requests {} LIMIT {} OFFSET {}",
+        build_pull_query_clause(filters),
+        page_size,
+        offset
+    );
+
+    let rows: Vec<Row> = conn.query(query).await?;
+    let mut pulls = Vec::new();
+    for row in rows {
+        let approvers: Option<String> = row.get("approvers").unwrap_or_default();
+        let pull_labels: Option<String> = row.get("pull_labels").unwrap_or_default();
+        pulls.push(PullOut {
+            pull_id: row.get("pull_id").unwrap_or_default(),
+            pull_title: row.get("pull_title").unwrap_or_default(),
+            pull_author: row.get("pull_author").unwrap_or_default(),
+            project_id: row.get("project_id").unwrap_or_default(),
+            issue_id: row.get("issue_id").unwrap_or_default(),
+            date_merged: row.get(5).unwrap_or_default(),
+            additions: row.get("additions").unwrap_or_default(),
+            deletions: row.get("deletions").unwrap_or_default(),
+            changed_files: row.get("changed_files").unwrap_or_default(),
+            approvals: row.get("approvals").unwrap_or_default(),
+            approvers: approvers
+                .and_then(|a| serde_json::from_str(&a).ok())
+                .unwrap_or_default(),
+            ci_status: row.get("ci_status").unwrap_or_default(),
+            pull_labels: pull_labels
+                .and_then(|l| serde_json::from_str(&l).ok())
+                .unwrap_or_default(),
+        });
+    }
+
+    Ok(pulls)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ContributorStats {
+    pub login: String,
+    pub github_id: Option<i64>,
+    pub avatar_url: Option<String>,
+    pub issues_opened: i64,
+    pub issues_assigned: i64,
+    pub prs_merged: i64,
+    pub budget_earned: i64,
+    pub avg_hours_to_merge: Option<f64>,
+    pub campaigns: Vec<String>,
+}
+
+// Everyone who opened, was assigned, merged or got paid for an issue, one row per login.
+const CONTRIBUTOR_STATS_QUERY: &str = r"
+    SELECT
+        l.login,
+        c.github_id,
+        c.avatar_url,
+        COALESCE(o.issues_opened, 0) AS issues_opened,
+        COALESCE(a.issues_assigned, 0) AS issues_assigned,
+        COALESCE(p.prs_merged, 0) AS prs_merged,
+        COALESCE(b.budget_earned, 0) AS budget_earned,
+        p.avg_hours_to_merge,
+        camp.campaigns
+    FROM (
+        SELECT issue_creator AS login FROM issues_master
+        UNION SELECT pull_author FROM pull_requests WHERE pull_author IS NOT NULL
+        UNION SELECT assignee FROM issue_assignments
+        UNION SELECT login FROM payouts
+    ) l
+    LEFT JOIN contributors c ON c.login = l.login
+    LEFT JOIN (
+        SELECT issue_creator AS login, COUNT(*) AS issues_opened
+        FROM issues_master GROUP BY issue_creator
+    ) o ON o.login = l.login
+    LEFT JOIN (
+        SELECT assignee AS login, COUNT(DISTINCT issue_id) AS issues_assigned
+        FROM issue_assignments WHERE event_type = 'assigned' GROUP BY assignee
+    ) a ON a.login = l.login
+    LEFT JOIN (
+        SELECT pull_author AS login, COUNT(*) AS prs_merged,
+            AVG(TIMESTAMPDIFF(HOUR, date_created, date_merged)) AS avg_hours_to_merge
+        FROM pull_requests WHERE pull_author IS NOT NULL GROUP BY pull_author
+    ) p ON p.login = l.login
+    LEFT JOIN (
+        SELECT login, CAST(SUM(amount) AS SIGNED) AS budget_earned
+        FROM payouts GROUP BY login
+    ) b ON b.login = l.login
+    LEFT JOIN (
+        SELECT x.login, JSON_ARRAYAGG(x.campaign) AS campaigns
+        FROM (
+            SELECT DISTINCT t.login, im.campaign
+            FROM (
+                SELECT issue_creator AS login, issue_id FROM issues_master
+                UNION SELECT assignee, issue_id FROM issue_assignments
+                UNION SELECT login, issue_id FROM payouts
+            ) t
+            JOIN issues_master im ON im.issue_id = t.issue_id
+            WHERE im.campaign IS NOT NULL
+        ) x
+        GROUP BY x.login
+    ) camp ON camp.login = l.login";
+
+async fn query_contributor_stats(
+    pool: &Pool,
+    tail: &str,
+    params: Params,
+) -> Result<Vec<ContributorStats>> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = format!("{} {}", CONTRIBUTOR_STATS_QUERY, tail);
+    let out: Vec<ContributorStats> = conn
+        .exec_map(
+            query,
+            params,
+            |(
+                login,
+                github_id,
+                avatar_url,
+                issues_opened,
+                issues_assigned,
+                prs_merged,
+                budget_earned,
+                avg_hours_to_merge,
+                campaigns,
+            ): (
+                String,
+                Option<i64>,
+                Option<String>,
+                i64,
+                i64,
+                i64,
+                i64,
+                Option<f64>,
+                Option<String>,
+            )| ContributorStats {
+                login,
+                github_id,
+                avatar_url,
+                issues_opened,
+                issues_assigned,
+                prs_merged,
+                budget_earned,
+                avg_hours_to_merge,
+                campaigns: campaigns
+                    .and_then(|c| serde_json::from_str(&c).ok())
+                    .unwrap_or_default(),
+            },
+        )
+        .await?;
+
+    Ok(out)
+}
+
+pub async fn list_contributors(
+    pool: &Pool,
+    page: usize,
+    page_size: usize,
+) -> Result<Vec<ContributorStats>> {
+    let offset = (page - 1) * page_size;
+    query_contributor_stats(
+        pool,
+        &format!("ORDER BY l.login ASC LIMIT {} OFFSET {}", page_size, offset),
+        Params::Empty,
+    )
+    .await
+}
+
+pub async fn get_contributor(pool: &Pool, login: &str) -> Result<Option<ContributorStats>> {
+    let out = query_contributor_stats(
+        pool,
+        "WHERE l.login = :login",
+        params! {
+            "login" => login,
+        },
+    )
+    .await?;
+
+    Ok(out.into_iter().next())
+}
+
+pub async fn get_leaderboard(
+    pool: &Pool,
+    rank_by: Option<&str>,
+    limit: usize,
+) -> Result<Vec<ContributorStats>> {
+    let schema_array = [
+        ("budget_earned", "ORDER BY budget_earned DESC"),
+        ("prs_merged", "ORDER BY prs_merged DESC"),
+        ("issues_assigned", "ORDER BY issues_assigned DESC"),
+        ("issues_opened", "ORDER BY issues_opened DESC"),
+    ];
+
+    let schema_map: HashMap<&str, &str> = schema_array.into_iter().collect();
+    let order_by = rank_by
+        .and_then(|r| schema_map.get(r))
+        .unwrap_or(&"ORDER BY budget_earned DESC");
+
+    let out: Vec<ContributorStats> = query_contributor_stats(
+        pool,
+        &format!("{}, prs_merged DESC, l.login ASC LIMIT {}", order_by, limit),
+        Params::Empty,
+    )
+    .await?;
+
+    Ok(out)
+}
+
 // pub async fn search_by_keyword_tags(tags_to_search: Vec<String>) -> anyhow::Result<Vec<String>> {
 //     let mut conn = pool.get_conn().await?;
 
@@ -834,14 +1408,20 @@ pub async fn search_by_keyword_tags(
     let mut results = Vec::new();
     let mut unique_ids = std::collections::HashSet::new();
 
+    // aliases resolve to their canonical tag; the fulltext match still finds summaries
+    // whose keywords are not mapped to tags yet
+    let canonical_tags = lookup_canonical_tags(&pool, &tags_to_search).await?;
     let search_string = tags_to_search.join(" ");
 
-    let query = r"SELECT issue_or_project_id FROM issues_repos_summarized WHERE MATCH(keyword_tags_text) AGAINST(:tags IN BOOLEAN MODE)";
+    let query = r"SELECT issue_or_project_id FROM issues_repos_summarized
+    WHERE JSON_OVERLAPS(canonical_tags, :canonical_tags)
+    OR MATCH(keyword_tags_text) AGAINST(:tags IN BOOLEAN MODE)";
 
     let rows: Vec<Row> = conn
         .exec(
             query,
             params! {
+                "canonical_tags" => serde_json::json!(canonical_tags).to_string(),
                 "tags" => &search_string,
             },
         )
@@ -1,12 +1,21 @@
+use crate::issue_classify::*;
 use crate::issue_tracker::*;
-use crate::llm_utils::parse_summary_and_keywords;
-use crate::llm_utils_together::*;
+use crate::llm_provider::*;
+use crate::llm_usage::spend_pause_reason;
+use crate::llm_utils::{
+    chat_summary, condense_long_readme, keywords_for_summary, summary_content_hash, SummaryFailure,
+};
+use crate::prompts::*;
+use crate::text_prep::*;
 use dotenv::dotenv;
 use mysql_async::prelude::*;
 use mysql_async::*;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
+// hours before texts that failed to parse are sent to the model again
+pub static PARSE_FAILURE_BACKOFF_HOURS: i64 = 24;
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct IssueOut {
     pub issue_id: String,
@@ -18,12 +27,22 @@ pub struct IssueOut {
     pub issue_creator: String,
     pub issue_description: String,
     pub issue_budget: Option<i32>,
+    pub issue_budget_confidence: Option<f32>,
+    pub issue_budget_evidence: Option<String>,
     pub issue_assignees: Option<String>,
     pub issue_linked_pr: Option<String>,
     pub issue_status: Option<String>,
     pub review_status: String,
     #[serde(default = "default_value")]
     pub issue_budget_approved: bool,
+    pub spam_score: Option<f32>,
+    pub spam_signals: Option<String>,
+    pub issue_difficulty: Option<String>,
+    pub issue_effort_hours: Option<i32>,
+    pub issue_skills: Option<String>,
+    pub issue_type: Option<String>,
+    pub suggested_budget: Option<i32>,
+    pub suggested_budget_rationale: Option<String>,
     pub running_budget: (i32, i32, i32),
     pub issue_stats: (i32, i32, i32, i32),
 }
@@ -89,22 +108,24 @@ pub async fn fill_project_w_repo_data(pool: &Pool, repo_data: RepoData) -> anyho
     let project_description = if !repo_data.repo_description.is_empty() {
         repo_data.repo_description.clone()
     } else if !repo_data.repo_readme.is_empty() {
-        repo_data.repo_readme.chars().take(1000).collect::<String>()
+        truncate_at_boundary(&clean_markdown(&repo_data.repo_readme), 1000)
     } else {
         String::from("No description available")
     };
 
     if   let Err(e) = conn
         .exec_drop(
-            r"INSERT INTO projects (project_id, project_logo, main_language, repo_stars, project_description)
-        VALUES (:project_id, :project_logo, :main_language, :repo_stars, :project_description)
+            r"INSERT INTO projects (project_id, project_node_id, project_logo, main_language, repo_stars, project_description)
+        VALUES (:project_id, :project_node_id, :project_logo, :main_language, :repo_stars, :project_description)
         ON DUPLICATE KEY UPDATE
+        project_node_id = COALESCE(project_node_id, VALUES(project_node_id)),
         project_logo = VALUES(project_logo),
         main_language = VALUES(main_language),
         repo_stars = VALUES(repo_stars),
         project_description = VALUES(project_description);",
             params! {
                 "project_id" => &repo_data.project_id,
+                "project_node_id" => &repo_data.project_node_id,
                 "project_logo" => &repo_data.project_logo,
                 "main_language" => &repo_data.main_language,
                 "repo_stars" => repo_data.repo_stars,
@@ -159,18 +180,29 @@ pub async fn pull_request_exists(pool: &mysql_async::Pool, pull_id: &str) -> any
 pub async fn add_issues_open(pool: &Pool, issue: &IssueOpen) -> Result<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"INSERT INTO issues_open (issue_id, project_id, issue_title, issue_creator, issue_budget, issue_description)
-                  VALUES (:issue_id, :project_id, :issue_title, :issue_creator, :issue_budget, :issue_description)";
+    let query = r"INSERT INTO issues_open (issue_id, issue_node_id, project_id, project_node_id, issue_title, issue_creator, issue_creator_node_id, issue_budget, issue_budget_confidence, issue_budget_evidence, issue_description)
+                  VALUES (:issue_id, :issue_node_id, :project_id, :project_node_id, :issue_title, :issue_creator, :issue_creator_node_id, :issue_budget, :issue_budget_confidence, :issue_budget_evidence, :issue_description)";
+
+    let issue_budget_confidence = issue.issue_budget_evidence.as_ref().map(|c| c.confidence);
+    let issue_budget_evidence = issue
+        .issue_budget_evidence
+        .as_ref()
+        .map(|c| json!(c).to_string());
 
     if let Err(e) = conn
         .exec_drop(
             query,
             params! {
                 "issue_id" => &issue.issue_id,
+                "issue_node_id" => &issue.issue_node_id,
                 "project_id" => &issue.project_id,
+                "project_node_id" => &issue.project_node_id,
                 "issue_title" => &issue.issue_title,
                 "issue_creator" => &issue.issue_creator,
+                "issue_creator_node_id" => &issue.issue_creator_node_id,
                 "issue_budget" => &issue.issue_budget,
+                "issue_budget_confidence" => issue_budget_confidence,
+                "issue_budget_evidence" => issue_budget_evidence,
                 "issue_description" => &issue.issue_description,
             },
         )
@@ -182,15 +214,61 @@ pub async fn add_issues_open(pool: &Pool, issue: &IssueOpen) -> Result<()> {
     Ok(())
 }
 
+// Writes an issue straight into issues_master, refreshing the title, description and state
+// of a row already there; used for webhook deliveries, where the issue may have been edited,
+// labeled or reopened since it was first recorded. The budget reading is refreshed only until
+// the issue is reviewed, so an approved or declined budget is left as the reviewer set it.
+pub async fn upsert_issue_master(pool: &Pool, issue: &IssueOpen, issue_state: &str) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT INTO issues_master (issue_id, issue_node_id, project_id, project_node_id, issue_title, issue_creator, issue_creator_node_id, issue_budget, issue_budget_confidence, issue_budget_evidence, issue_description, issue_state, campaign)
+                  VALUES (:issue_id, :issue_node_id, :project_id, :project_node_id, :issue_title, :issue_creator, :issue_creator_node_id, :issue_budget, :issue_budget_confidence, :issue_budget_evidence, :issue_description, :issue_state, :campaign)
+                  ON DUPLICATE KEY UPDATE
+                  issue_title = VALUES(issue_title),
+                  issue_description = VALUES(issue_description),
+                  issue_state = VALUES(issue_state),
+                  issue_budget = IF(review_status = 'queue' AND issue_budget_approved = 0, VALUES(issue_budget), issue_budget),
+                  issue_budget_confidence = IF(review_status = 'queue' AND issue_budget_approved = 0, VALUES(issue_budget_confidence), issue_budget_confidence),
+                  issue_budget_evidence = IF(review_status = 'queue' AND issue_budget_approved = 0, VALUES(issue_budget_evidence), issue_budget_evidence)";
+
+    if let Err(e) = conn
+        .exec_drop(
+            query,
+            params! {
+                "issue_id" => &issue.issue_id,
+                "issue_node_id" => &issue.issue_node_id,
+                "project_id" => &issue.project_id,
+                "project_node_id" => &issue.project_node_id,
+                "issue_title" => &issue.issue_title,
+                "issue_creator" => &issue.issue_creator,
+                "issue_creator_node_id" => &issue.issue_creator_node_id,
+                "issue_budget" => &issue.issue_budget,
+                "issue_budget_confidence" => issue.issue_budget_evidence.as_ref().map(|c| c.confidence),
+                "issue_budget_evidence" => issue.issue_budget_evidence.as_ref().map(|c| json!(c).to_string()),
+                "issue_description" => &issue.issue_description,
+                "issue_state" => issue_state,
+                "campaign" => crate::CAMPAIGN,
+            },
+        )
+        .await
+    {
+        log::error!("Error upsert issues_master: {:?}", e);
+        return Err(e);
+    };
+
+    Ok(())
+}
+
 pub async fn add_issues_comment(pool: &Pool, issue: IssueComment) -> Result<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"INSERT INTO issues_comment (issue_id, comment_creator, comment_date, comment_body)
-    SELECT :issue_id, :comment_creator, :comment_date, :comment_body
+    let query = r"INSERT INTO issues_comment (issue_id, issue_node_id, comment_node_id, comment_creator, comment_creator_node_id, comment_date, comment_body)
+    SELECT :issue_id, :issue_node_id, :comment_node_id, :comment_creator, :comment_creator_node_id, :comment_date, :comment_body
     FROM dual
     WHERE NOT EXISTS (
         SELECT 1 FROM issues_comment
-        WHERE issue_id = :issue_id AND comment_date = :comment_date
+        WHERE (issue_id = :issue_id AND comment_date = :comment_date)
+        OR comment_node_id = :comment_node_id
     ) LIMIT 1;";
 
     if let Err(e) = conn
@@ -198,7 +276,10 @@ pub async fn add_issues_comment(pool: &Pool, issue: IssueComment) -> Result<()>
             query,
             params! {
                 "issue_id" => &issue.issue_id,
+                "issue_node_id" => &issue.issue_node_id,
+                "comment_node_id" => &issue.comment_node_id,
                 "comment_creator" => &issue.comment_creator,
+                "comment_creator_node_id" => &issue.comment_creator_node_id,
                 "comment_date" => &issue.comment_date,
                 "comment_body" => &issue.comment_body,
             },
@@ -220,16 +301,20 @@ pub async fn add_issues_comment(pool: &Pool, issue: IssueComment) -> Result<()>
 pub async fn add_issues_open_batch(pool: &Pool, issues: Vec<IssueOpen>) -> Result<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"INSERT INTO issues_open (issue_id, project_id, issue_title, issue_budget, issue_description)
-                  VALUES (:issue_id, :project_id, :issue_title, :issue_budget, :issue_description)";
+    let query = r"INSERT INTO issues_open (issue_id, issue_node_id, project_id, project_node_id, issue_title, issue_budget, issue_budget_confidence, issue_budget_evidence, issue_description)
+                  VALUES (:issue_id, :issue_node_id, :project_id, :project_node_id, :issue_title, :issue_budget, :issue_budget_confidence, :issue_budget_evidence, :issue_description)";
 
     if let Err(e) = query
         .with(issues.iter().map(|issue| {
             params! {
                 "issue_id" => &issue.issue_id,
+                "issue_node_id" => &issue.issue_node_id,
                 "project_id" => &issue.project_id,
+                "project_node_id" => &issue.project_node_id,
                 "issue_title" => &issue.issue_title,
                 "issue_budget" => &issue.issue_budget,
+                "issue_budget_confidence" => issue.issue_budget_evidence.as_ref().map(|c| c.confidence),
+                "issue_budget_evidence" => issue.issue_budget_evidence.as_ref().map(|c| json!(c).to_string()),
                 "issue_description" => &issue.issue_description,
             }
         }))
@@ -242,21 +327,82 @@ pub async fn add_issues_open_batch(pool: &Pool, issues: Vec<IssueOpen>) -> Resul
     Ok(())
 }
 
+// Keeps one row per GitHub user, refreshing the login and avatar when they change, and links
+// payouts recorded under their login to them.
+pub async fn upsert_contributors(pool: &Pool, contributors: &[Contributor]) -> Result<()> {
+    if contributors.is_empty() {
+        return Ok(());
+    }
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT INTO contributors (login, github_id, node_id, avatar_url)
+                  VALUES (:login, :github_id, :node_id, :avatar_url)
+                  ON DUPLICATE KEY UPDATE
+                  login = VALUES(login),
+                  github_id = COALESCE(VALUES(github_id), github_id),
+                  node_id = COALESCE(VALUES(node_id), node_id),
+                  avatar_url = COALESCE(VALUES(avatar_url), avatar_url)";
+
+    if let Err(e) = conn
+        .exec_batch(
+            query,
+            contributors.iter().map(|c| {
+                params! {
+                    "login" => &c.login,
+                    "github_id" => c.database_id,
+                    "node_id" => &c.node_id,
+                    "avatar_url" => &c.avatar_url,
+                }
+            }),
+        )
+        .await
+    {
+        log::error!("Error upserting contributors: {:?}", e);
+    };
+
+    // payouts recorded before the assignee was known here
+    let query = r"UPDATE payouts p
+                  JOIN contributors c ON c.login = p.login
+                  SET p.contributor_id = c.contributor_id
+                  WHERE p.contributor_id IS NULL AND p.login = :login";
+
+    if let Err(e) = conn
+        .exec_batch(
+            query,
+            contributors.iter().map(|c| {
+                params! {
+                    "login" => &c.login,
+                }
+            }),
+        )
+        .await
+    {
+        log::error!("Error linking payouts to contributors: {:?}", e);
+    };
+
+    Ok(())
+}
+
 pub async fn add_issues_closed(pool: &Pool, issue: IssueClosed) -> Result<()> {
+    upsert_contributors(pool, &issue.assignee_profiles).await?;
     let mut conn = pool.get_conn().await?;
 
     let issue_assignees_json: Value = json!(issue.issue_assignees).into();
 
-    let query = r"INSERT INTO issues_closed (issue_id, issue_assignees, issue_linked_pr)
-                  VALUES (:issue_id, :issue_assignees, :issue_linked_pr)";
+    let query = r"INSERT INTO issues_closed (issue_id, issue_node_id, issue_assignees, issue_linked_pr, issue_linked_pr_node_id, issue_linked_pr_method, issue_linked_pr_confidence)
+                  VALUES (:issue_id, :issue_node_id, :issue_assignees, :issue_linked_pr, :issue_linked_pr_node_id, :issue_linked_pr_method, :issue_linked_pr_confidence)";
 
     if let Err(e) = conn
         .exec_drop(
             query,
             params! {
                 "issue_id" => &issue.issue_id,
+                "issue_node_id" => &issue.issue_node_id,
                 "issue_assignees" => &issue_assignees_json,
                 "issue_linked_pr" => issue.issue_linked_pr.as_deref(),
+                "issue_linked_pr_node_id" => &issue.issue_linked_pr_node_id,
+                "issue_linked_pr_method" => &issue.issue_linked_pr_method,
+                "issue_linked_pr_confidence" => issue.issue_linked_pr_confidence,
             },
         )
         .await
@@ -268,6 +414,9 @@ pub async fn add_issues_closed(pool: &Pool, issue: IssueClosed) -> Result<()> {
 }
 
 pub async fn add_issues_assigned(pool: &Pool, issue_assigned: IssueAssigned) -> Result<()> {
+    if let Some(profile) = &issue_assigned.assignee_profile {
+        upsert_contributors(pool, &[profile.clone()]).await?;
+    }
     let mut conn = pool.get_conn().await?;
 
     let issue_assignee = if issue_assigned.issue_assignee.is_empty() {
@@ -276,15 +425,21 @@ pub async fn add_issues_assigned(pool: &Pool, issue_assigned: IssueAssigned) ->
         Some(issue_assigned.issue_assignee)
     };
 
-    let query = r"INSERT INTO issues_assigned (issue_id, issue_assignee, date_assigned)
-                  VALUES (:issue_id, :issue_assignee, :date_assigned)";
+    // the hourly search sees the same events again, each is staged once
+    let query = r"INSERT IGNORE INTO issues_assigned (issue_id, issue_node_id, event_node_id, event_type, issue_assignee, assignee_node_id, event_actor, date_assigned)
+                  VALUES (:issue_id, :issue_node_id, :event_node_id, :event_type, :issue_assignee, :assignee_node_id, :event_actor, :date_assigned)";
 
     if let Err(e) = conn
         .exec_drop(
             query,
             params! {
                 "issue_id" => &issue_assigned.issue_id,
+                "issue_node_id" => &issue_assigned.issue_node_id,
+                "event_node_id" => &issue_assigned.event_node_id,
+                "event_type" => &issue_assigned.event_type,
                 "issue_assignee" => &issue_assignee,
+                "assignee_node_id" => &issue_assigned.assignee_node_id,
+                "event_actor" => &issue_assigned.event_actor,
                 "date_assigned" => &issue_assigned.date_assigned,
             },
         )
@@ -296,17 +451,22 @@ pub async fn add_issues_assigned(pool: &Pool, issue_assigned: IssueAssigned) ->
     Ok(())
 }
 
-pub async fn mark_id_indexed(pool: &Pool, issue_or_project_id: &str) -> Result<()> {
+pub async fn mark_id_indexed(
+    pool: &Pool,
+    issue_or_project_id: &str,
+    vector_point_id: u64,
+) -> Result<()> {
     let mut conn = pool.get_conn().await?;
 
     let query = r"UPDATE issues_repos_summarized
-    SET indexed=1 WHERE issue_or_project_id = :issue_or_project_id";
+    SET indexed=1, vector_point_id = :vector_point_id WHERE issue_or_project_id = :issue_or_project_id";
 
     if let Err(e) = conn
         .exec_drop(
             query,
             params! {
                 "issue_or_project_id" => &issue_or_project_id,
+                "vector_point_id" => vector_point_id,
             },
         )
         .await
@@ -317,19 +477,51 @@ pub async fn mark_id_indexed(pool: &Pool, issue_or_project_id: &str) -> Result<(
     Ok(())
 }
 
+// Whether the stored summary was made from the same texts.
+pub async fn summary_is_current(
+    pool: &Pool,
+    issue_or_project_id: &str,
+    content_hash: &str,
+) -> Result<bool> {
+    let mut conn = pool.get_conn().await?;
+
+    let stored: Option<Option<String>> = conn
+        .exec_first(
+            r"SELECT content_hash FROM issues_repos_summarized WHERE issue_or_project_id = :issue_or_project_id",
+            params! {
+                "issue_or_project_id" => issue_or_project_id,
+            },
+        )
+        .await?;
+
+    Ok(stored.flatten().as_deref() == Some(content_hash))
+}
+
 pub async fn add_or_update_summary_and_id(
     pool: &Pool,
     issue_or_project_id: &str,
     issue_or_project_summary: &str,
     keyword_tags: Vec<String>,
+    content_hash: &str,
+    prompt_version: &str,
+    llm_model: &str,
 ) -> Result<()> {
     let mut conn = pool.get_conn().await?;
     let keyword_tags_json_str = json!(keyword_tags).to_string();
 
-    let query = r"INSERT INTO issues_repos_summarized (issue_or_project_id, issue_or_project_summary, keyword_tags)
-    VALUES (:issue_or_project_id, :issue_or_project_summary, :keyword_tags_json_str)
+    // assignments run in order, `indexed` has to compare against the summary before it is replaced;
+    // a changed summary goes back to populate_vector_db to be embedded again, changed keywords to canonicalize_summary_tags
+    let query = r"INSERT INTO issues_repos_summarized (issue_or_project_id, issue_or_project_summary, keyword_tags, content_hash, prompt_version, llm_model, date_summarized, date_updated)
+    VALUES (:issue_or_project_id, :issue_or_project_summary, :keyword_tags_json_str, :content_hash, :prompt_version, :llm_model, NOW(), NOW())
     ON DUPLICATE KEY UPDATE
-    keyword_tags = :keyword_tags_json_str;";
+    indexed = IF(issue_or_project_summary <=> VALUES(issue_or_project_summary), indexed, 0),
+    issue_or_project_summary = VALUES(issue_or_project_summary),
+    canonical_tags = IF(keyword_tags <=> VALUES(keyword_tags), canonical_tags, NULL),
+    keyword_tags = VALUES(keyword_tags),
+    content_hash = VALUES(content_hash),
+    prompt_version = VALUES(prompt_version),
+    llm_model = VALUES(llm_model),
+    date_updated = NOW();";
 
     if let Err(e) = conn
         .exec_drop(
@@ -338,6 +530,9 @@ pub async fn add_or_update_summary_and_id(
                 "issue_or_project_id" => &issue_or_project_id,
                 "issue_or_project_summary" => &issue_or_project_summary,
                 "keyword_tags_json_str" => &keyword_tags_json_str,
+                "content_hash" => content_hash,
+                "prompt_version" => prompt_version,
+                "llm_model" => llm_model,
             },
         )
         .await
@@ -349,6 +544,72 @@ pub async fn add_or_update_summary_and_id(
 
     Ok(())
 }
+
+// Keeps replies that never parsed, so the prompt or model can be fixed and the item
+// summarized again; nothing is written to issues_repos_summarized for them.
+pub async fn add_llm_parse_failure(
+    pool: &Pool,
+    issue_or_project_id: &str,
+    task: LlmTask,
+    content_hash: &str,
+    failure: &SummaryFailure,
+) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT INTO llm_parse_failures (issue_or_project_id, task, content_hash, reply, repaired_reply, parse_error)
+                  VALUES (:issue_or_project_id, :task, :content_hash, :reply, :repaired_reply, :parse_error)";
+
+    if let Err(e) = conn
+        .exec_drop(
+            query,
+            params! {
+                "issue_or_project_id" => issue_or_project_id,
+                "task" => task.as_str(),
+                "content_hash" => content_hash,
+                "reply" => &failure.reply,
+                "repaired_reply" => &failure.repaired_reply,
+                "parse_error" => &failure.error,
+            },
+        )
+        .await
+    {
+        log::error!("Error recording llm parse failure: {:?}", e);
+        return Err(e);
+    };
+
+    Ok(())
+}
+
+// Whether the same texts failed to parse for the task within PARSE_FAILURE_BACKOFF_HOURS;
+// they are tried again after that, in case the prompt or model was fixed meanwhile.
+pub async fn parse_failed_recently(
+    pool: &Pool,
+    issue_or_project_id: &str,
+    task: LlmTask,
+    content_hash: &str,
+) -> Result<bool> {
+    let mut conn = pool.get_conn().await?;
+
+    let failed: Option<u8> = conn
+        .exec_first(
+            r"SELECT 1 FROM llm_parse_failures
+            WHERE issue_or_project_id = :issue_or_project_id
+            AND task = :task
+            AND content_hash = :content_hash
+            AND date_failed > DATE_SUB(NOW(), INTERVAL :hours HOUR)
+            LIMIT 1",
+            params! {
+                "issue_or_project_id" => issue_or_project_id,
+                "task" => task.as_str(),
+                "content_hash" => content_hash,
+                "hours" => PARSE_FAILURE_BACKOFF_HOURS,
+            },
+        )
+        .await?;
+
+    Ok(failed.is_some())
+}
+
 /* pub async fn add_summary_and_id(
     pool: &Pool,
     issue_or_project_id: &str,
@@ -380,18 +641,50 @@ pub async fn add_or_update_summary_and_id(
 pub async fn add_pull_request(pool: &Pool, pull: OuterPull) -> Result<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"INSERT INTO pull_requests (pull_id, pull_title, pull_author, project_id, date_merged)
-                  VALUES (:pull_id, :pull_title, :pull_author, :project_id, :date_merged)";
+    // a known node id under a new url means the repo was renamed or transferred, keep one row;
+    // webhook deliveries carry no reviews or checks, those keep what the search found
+    let query = r"INSERT INTO pull_requests (pull_id, pull_node_id, pull_title, pull_author, pull_author_node_id, project_id, project_node_id, date_created, date_merged,
+                  additions, deletions, changed_files, approvals, approvers, ci_status, pull_labels)
+                  VALUES (:pull_id, :pull_node_id, :pull_title, :pull_author, :pull_author_node_id, :project_id, :project_node_id, NULLIF(:date_created, ''), :date_merged,
+                  :additions, :deletions, :changed_files, COALESCE(:approvals, 0), :approvers, :ci_status, :pull_labels)
+                  ON DUPLICATE KEY UPDATE
+                  pull_id = VALUES(pull_id),
+                  pull_node_id = COALESCE(VALUES(pull_node_id), pull_node_id),
+                  pull_author_node_id = COALESCE(VALUES(pull_author_node_id), pull_author_node_id),
+                  project_id = VALUES(project_id),
+                  project_node_id = COALESCE(VALUES(project_node_id), project_node_id),
+                  date_created = COALESCE(VALUES(date_created), date_created),
+                  additions = COALESCE(VALUES(additions), additions),
+                  deletions = COALESCE(VALUES(deletions), deletions),
+                  changed_files = COALESCE(VALUES(changed_files), changed_files),
+                  approvers = IF(:approvals IS NULL, approvers, VALUES(approvers)),
+                  approvals = IF(:approvals IS NULL, approvals, VALUES(approvals)),
+                  ci_status = COALESCE(VALUES(ci_status), ci_status),
+                  pull_labels = VALUES(pull_labels)";
+
+    let approvers_json: Option<Value> = pull.approvers.as_ref().map(|a| json!(a));
+    let pull_labels_json: Value = json!(pull.pull_labels).into();
 
     if let Err(e) = conn
         .exec_drop(
             query,
             params! {
                 "pull_id" => &pull.pull_id,
+                "pull_node_id" => &pull.pull_node_id,
                 "pull_title" => &pull.pull_title,
                 "pull_author" => pull.pull_author.as_deref(),
+                "pull_author_node_id" => &pull.pull_author_node_id,
                 "project_id" => &pull.project_id,
-                "date_merged" => pull.merged_at,
+                "project_node_id" => &pull.project_node_id,
+                "date_created" => &pull.created_at,
+                "date_merged" => &pull.merged_at,
+                "additions" => pull.additions,
+                "deletions" => pull.deletions,
+                "changed_files" => pull.changed_files,
+                "approvals" => pull.approvers.as_ref().map(|a| a.len() as i32),
+                "approvers" => &approvers_json,
+                "ci_status" => &pull.ci_status,
+                "pull_labels" => &pull_labels_json,
             },
         )
         .await
@@ -402,17 +695,27 @@ pub async fn add_pull_request(pool: &Pool, pull: OuterPull) -> Result<()> {
     Ok(())
 }
 
-pub async fn get_issues_repos_from_db() -> Result<Vec<(String, String)>> {
+// Summaries waiting to be embedded, with the vector store point of an earlier embedding
+// to overwrite if there is one.
+pub async fn get_issues_repos_from_db() -> Result<Vec<(String, String, Option<u64>)>> {
     let pool = get_pool().await;
     let mut conn = pool.get_conn().await?;
 
-    let query = r"SELECT issue_or_project_id, issue_or_project_summary FROM issues_repos_summarized WHERE indexed=0 limit 50";
+    let query = r"SELECT issue_or_project_id, issue_or_project_summary, vector_point_id FROM issues_repos_summarized WHERE indexed=0 limit 50";
 
-    let entries: Vec<(String, String)> = conn
+    let entries: Vec<(String, String, Option<u64>)> = conn
         .query_map(
             query,
-            |(issue_or_project_id, issue_or_project_summary): (String, String)| {
-                (issue_or_project_id, issue_or_project_summary)
+            |(issue_or_project_id, issue_or_project_summary, vector_point_id): (
+                String,
+                String,
+                Option<u64>,
+            )| {
+                (
+                    issue_or_project_id,
+                    issue_or_project_summary,
+                    vector_point_id,
+                )
             },
         )
         .await?;
@@ -441,44 +744,135 @@ pub async fn get_issues_from_db() -> Result<Vec<(String, String, String, Option<
     Ok(issues)
 }
 
+// System prompt, user input and token budget for summarizing an issue with `prompt` on
+// `model`; the prompt evaluation builds its requests the same way.
+pub fn issue_summary_request(
+    prompt: &PromptTemplate,
+    model: &str,
+    issue_id: &str,
+    issue_title: &str,
+    issue_description: &str,
+) -> (&'static str, String, u16) {
+    let parts: Vec<&str> = issue_id.split('/').collect();
+    let owner = parts.get(3).unwrap_or(&"");
+    let repo = parts.get(4).unwrap_or(&"");
+    let issue_description = clean_markdown(issue_description);
+
+    if issue_description.len() < prompt.short_below {
+        let raw_input_texts = format!(
+                "Here is the input: `{issue_title}` at repository `{repo}` by owner `{owner}`, states: {issue_description}"
+            );
+        (prompt.short_input, raw_input_texts, 180)
+    } else {
+        let raw_input_texts=  format!(
+                "Here is the input: The issue titled `{issue_title}` at repository `{repo}` by owner `{owner}`, states in the body text: {issue_description}"
+            );
+        let budget = input_token_budget(model, prompt.long_input, 250).min(ISSUE_INPUT_TOKENS);
+        (
+            prompt.long_input,
+            truncate_to_tokens(&raw_input_texts, budget, model),
+            250,
+        )
+    }
+}
+
 pub async fn summarize_issue_add_in_db(pool: &Pool, issue: &IssueOpen) -> anyhow::Result<()> {
     let issue_clone = issue.clone();
     let issue_title = issue_clone.issue_title;
     let issue_id = issue_clone.issue_id;
     let issue_description = issue_clone.issue_description;
-    log::info!("Summarizing issue: {}",issue_id);
+    log::info!("Summarizing issue: {}", issue_id);
 
-    let parts: Vec<&str> = issue_id.split('/').collect();
-    let owner = parts[3].to_string();
-    let repo = parts[4].to_string();
+    if let Ok(Some(reason)) = spend_pause_reason(pool).await {
+        log::info!("Not summarizing {}, {}", issue_id, reason);
+        return Ok(());
+    }
 
-    let system_prompt_long_input = r#"
-        Summarize the GitHub issue in one paragraph without mentioning the issue number. Highlight the key problem and any signature information provided. The summary should be concise, informative, and easy to understand, prioritizing clarity and brevity. Additionally, extract high-level keywords that represent broader categories or themes relevant to the issue's purpose, features, and tools used. These keywords should help categorize the issue in a wider context and should not be too literal or specific, avoiding overly long phrases unless absolutely necessary. Expected Output:
-        { \"summary\": \"the_summary_generated, a short paragraph summarizing the issue, including its purpose and features, without referencing the issue number.\",
-          \"keywords\": [\"a list of high-level keywords that encapsulate the broader context, categories, or themes of the issue, excluding specific details and issue numbers.\"] }
-        Ensure you reply in RFC8259-compliant JSON format."#;
+    let content_hash = summary_content_hash(&[&issue_title, &issue_description]);
+    if !classification_is_current(&pool, &issue_id, &content_hash).await?
+        && !parse_failed_recently(&pool, &issue_id, LlmTask::Classification, &content_hash).await?
+    {
+        let provider = provider_for(LlmTask::Classification);
+        match classify_issue(provider.as_ref(), &issue_title, &issue_description).await {
+            Ok(Ok(classification)) => {
+                let _ = add_issue_classification(
+                    &pool,
+                    &issue_id,
+                    &classification,
+                    &content_hash,
+                    &format!("{}/{}", provider.name(), provider.model()),
+                )
+                .await;
+            }
+            Ok(Err(failure)) => {
+                let _ = add_llm_parse_failure(
+                    &pool,
+                    &issue_id,
+                    LlmTask::Classification,
+                    &content_hash,
+                    &failure,
+                )
+                .await;
+            }
+            Err(e) => log::error!("Error classifying {}: {:?}", issue_id, e),
+        }
+    }
 
-        let system_prompt_short_input = r#"
-        Given the limited information available, summarize the GitHub issue in one paragraph without mentioning the issue number. Highlight the key problem and any signature information that can be inferred. The summary should be concise, informative, and easy to understand, prioritizing clarity and brevity even with scant details. Additionally, extract high-level keywords that represent broader categories or themes relevant to the issue's inferred purpose, features, and tools used. These keywords should help categorize the issue in a wider context and should not be too literal or specific, avoiding overly long phrases unless absolutely necessary. Expected Output:
-        { \"summary\": \"The summary generated should be a concise paragraph that highlights any discernible purpose, technologies, or features from the limited information.\",
-          \"keywords\": [\"A list of inferred high-level keywords that broadly categorize the repository based on the scant details available.\"] }
-        Ensure you reply in RFC8259-compliant JSON format."#;
+    if summary_is_current(&pool, &issue_id, &content_hash).await? {
+        log::info!("Summary of {} is up to date", issue_id);
+        return Ok(());
+    }
+    if parse_failed_recently(&pool, &issue_id, LlmTask::Summarization, &content_hash).await? {
+        log::info!(
+            "Summary of {} failed to parse recently, not retrying yet",
+            issue_id
+        );
+        return Ok(());
+    }
 
-    let generated_summary = if issue_description.len() < 200 {
-        let raw_input_texts = format!(
-                "Here is the input: `{issue_title}` at repository `{repo}` by owner `{owner}`, states: {issue_description}"
-            );
-        chat_inner_async(system_prompt_short_input, &raw_input_texts, 180).await?
-    } else {
-        let raw_input_texts=  format!(
-                "Here is the input: The issue titled `{issue_title}` at repository `{repo}` by owner `{owner}`, states in the body text: {issue_description}"
-            ).chars().take(4000).collect::<String>();
-        chat_inner_async(system_prompt_long_input, &raw_input_texts, 250).await?
+    let prompt = active_prompt(ISSUE_SUMMARY);
+    let provider = provider_for(LlmTask::Summarization);
+    let (system_prompt, raw_input_texts, max_tokens) = issue_summary_request(
+        prompt,
+        provider.model(),
+        &issue_id,
+        &issue_title,
+        &issue_description,
+    );
+    let generated_summary = chat_summary(
+        provider.as_ref(),
+        system_prompt,
+        &raw_input_texts,
+        max_tokens,
+    )
+    .await?;
+
+    let output = match generated_summary {
+        Ok(output) => output,
+        Err(failure) => {
+            let _ = add_llm_parse_failure(
+                &pool,
+                &issue_id,
+                LlmTask::Summarization,
+                &content_hash,
+                &failure,
+            )
+            .await;
+            return Ok(());
+        }
     };
-
-    let (summary, keyword_tags) = parse_summary_and_keywords(&generated_summary);
-    log::info!("{}, {:?}", issue_id, keyword_tags.clone());
-    let _ = add_or_update_summary_and_id(&pool, &issue_id, &summary, keyword_tags).await;
+    let keywords = keywords_for_summary(&output.summary, output.keywords).await;
+    log::info!("{}, {:?}", issue_id, keywords);
+    let _ = add_or_update_summary_and_id(
+        &pool,
+        &issue_id,
+        &output.summary,
+        keywords,
+        &content_hash,
+        &prompt.id(),
+        &format!("{}/{}", provider.name(), provider.model()),
+    )
+    .await;
 
     Ok(())
 }
@@ -493,48 +887,106 @@ pub async fn summarize_project_add_in_db(pool: &Pool, repo_data: RepoData) -> an
     let project_readme = repo_data.repo_readme;
     let main_language = repo_data.main_language;
 
+    let content_hash =
+        summary_content_hash(&[&project_descrpition, &project_readme, &main_language]);
+    if summary_is_current(&pool, &repo_data.project_id, &content_hash).await? {
+        log::info!("Summary of {} is up to date", repo_data.project_id);
+        return Ok(());
+    }
+    if parse_failed_recently(
+        &pool,
+        &repo_data.project_id,
+        LlmTask::Summarization,
+        &content_hash,
+    )
+    .await?
+    {
+        log::info!(
+            "Summary of {} failed to parse recently, not retrying yet",
+            repo_data.project_id
+        );
+        return Ok(());
+    }
+    if let Ok(Some(reason)) = spend_pause_reason(pool).await {
+        log::info!("Not summarizing {}, {}", repo_data.project_id, reason);
+        return Ok(());
+    }
+
     let use_lang_str = if main_language.is_empty() {
         String::from("")
     } else {
         format!("mainly uses `{main_language}` in the project")
     };
 
+    let prompt = active_prompt(PROJECT_SUMMARY);
+    let provider = provider_for(LlmTask::Summarization);
+    let model = provider.model().to_string();
+
+    // long READMEs are summarized part by part first, so the summary covers all of it
+    let mut project_readme = prepare_readme(&project_readme, README_CHUNK_TOKENS, &model);
+    if estimate_tokens(&project_readme, &model) > README_CHUNK_TOKENS {
+        project_readme = match condense_long_readme(provider.as_ref(), &project_readme).await {
+            Ok(notes) => notes,
+            Err(e) => {
+                log::error!(
+                    "Error condensing README of {}: {:?}",
+                    repo_data.project_id,
+                    e
+                );
+                truncate_to_tokens(&project_readme, README_CHUNK_TOKENS, &model)
+            }
+        };
+    }
+
     let project_readme_str = match project_readme.is_empty() {
         false => format!("states in readme: {project_readme}"),
         true => String::from(""),
     };
+    let system_prompt = prompt.system_prompt(project_readme.len());
 
-    let system_prompt_long_input = r#"
-    Summarize the GitHub repository's README or description in one detailed paragraph, focusing solely on the essential aspects such as the project's purpose, technologies used, and notable features. Do not include non-essential elements like personal appeals or donation links. Extract high-level keywords that represent broader categories or themes relevant to the project. These keywords should categorize the project in a wider context and not be overly specific or literal. Expected Output:
-    { \"summary\": \"A comprehensive paragraph that succinctly summarizes the repository, highlighting its purpose, technologies, and key features, without including extraneous details.\",
-      \"keywords\": [\"A list of high-level keywords that encapsulate the broader context, categories, or themes of the repository, focusing on essential aspects only.\"] }
-    Ensure your reply is in RFC8259-compliant JSON format.
-    "#;
-
-    let system_prompt_short_input = r#"When summarizing a GitHub repository's README or description, concentrate on the core content. Provide a concise paragraph that captures the primary purpose, technologies used, and notable features. Avoid mentioning non-essential elements such as donation links or personal appeals. Deduce and include high-level keywords that broadly categorize the repository, focusing on the technologies, functionality, and scope based on the available information. These keywords should reflect the main themes or categories relevant to the project. Expected Output:
-    { \"summary\": \"The summary generated should be a concise paragraph that highlights any discernible purpose, technologies, or features from the limited information.\",
-      \"keywords\": [\"A list of inferred high-level keywords that broadly categorize the repository based on the scant details available.\"] }
-    Ensure you reply in RFC8259-compliant JSON format."#;
-
-    let generated_summary = if project_readme.len() < 200 {
+    let generated_summary = if project_readme.len() < prompt.short_below {
         let raw_input_texts = format!(
             "Here is the input: The repository `{repo}` by owner `{owner}` {use_lang_str},`{project_descrpition}`, {project_readme_str}"
         );
 
-        chat_inner_async(system_prompt_short_input, &raw_input_texts, 180).await?
+        chat_summary(provider.as_ref(), system_prompt, &raw_input_texts, 180).await?
     } else {
         let raw_input_texts = format!(
                 "Here is the input: The repository `{repo}`  by owner `{owner}` {use_lang_str}, has a short text description: `{project_descrpition}`, mentioned more details in readme: `{project_readme}`"
-            ).chars().take(4000).collect::<String>();
+            );
+        let budget = input_token_budget(&model, system_prompt, 250);
+        let raw_input_texts = truncate_to_tokens(&raw_input_texts, budget, &model);
 
-        chat_inner_async(system_prompt_long_input, &raw_input_texts, 250).await?
+        chat_summary(provider.as_ref(), system_prompt, &raw_input_texts, 250).await?
     };
     //  log::info!("generated summary: {}", generated_summary.to_string());
 
-    let (summary, keyword_tags) = parse_summary_and_keywords(&generated_summary);
-    //  log::info!("keywords: {:?}", &keyword_tags);
-
-    let _ =
-        add_or_update_summary_and_id(&pool, &repo_data.project_id, &summary, keyword_tags).await;
+    let output = match generated_summary {
+        Ok(output) => output,
+        Err(failure) => {
+            let _ = add_llm_parse_failure(
+                &pool,
+                &repo_data.project_id,
+                LlmTask::Summarization,
+                &content_hash,
+                &failure,
+            )
+            .await;
+            return Ok(());
+        }
+    };
+    //  log::info!("keywords: {:?}", &output.keywords);
+    let keywords = keywords_for_summary(&output.summary, output.keywords).await;
+
+    let _ = add_or_update_summary_and_id(
+        &pool,
+        &repo_data.project_id,
+        &output.summary,
+        keywords,
+        &content_hash,
+        &prompt.id(),
+        &format!("{}/{}", provider.name(), provider.model()),
+    )
+    .await;
     Ok(())
 }
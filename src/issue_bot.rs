@@ -1,19 +1,192 @@
 use crate::issue_tracker::*;
+use crate::{DECLINED_LABEL, FUNDED_LABEL};
+use http_req::request::Method;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
 
-pub async fn comment_on_issue(issue_id: &str, comment: &str) -> anyhow::Result<()> {
-    // let issue_id = "https://github.com/alabulei1/a-test/issues/87";
-    let issue_parts: Vec<&str> = issue_id.rsplitn(5, '/').collect();
-    let issue_number = issue_parts[0].parse::<i32>().unwrap_or(0);
+pub static MAX_SEND_ATTEMPTS: i32 = 6;
+pub static SEND_RETRIES_PER_RUN: i32 = 2;
+// a claimed row still in 'sending' after this long belongs to a run that crashed
+pub static SENDING_TIMEOUT_MINUTES: i32 = 30;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum NotificationKind {
+    BudgetAllocated,
+    IssueDeclined,
+    DistributeFund,
+    StaleReminder,
+    StaleFinalWarning,
+    StaleReleased,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::BudgetAllocated => "budget_allocated",
+            NotificationKind::IssueDeclined => "issue_declined",
+            NotificationKind::DistributeFund => "distribute_fund",
+            NotificationKind::StaleReminder => "stale_reminder",
+            NotificationKind::StaleFinalWarning => "stale_final_warning",
+            NotificationKind::StaleReleased => "stale_released",
+        }
+    }
+}
+
+impl std::str::FromStr for NotificationKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "budget_allocated" => Ok(NotificationKind::BudgetAllocated),
+            "issue_declined" => Ok(NotificationKind::IssueDeclined),
+            "distribute_fund" => Ok(NotificationKind::DistributeFund),
+            "stale_reminder" => Ok(NotificationKind::StaleReminder),
+            "stale_final_warning" => Ok(NotificationKind::StaleFinalWarning),
+            "stale_released" => Ok(NotificationKind::StaleReleased),
+            _ => Err(anyhow::anyhow!("Unknown notification kind: {}", s)),
+        }
+    }
+}
+
+// Splits "https://github.com/{owner}/{repo}/issues/{number}" into its parts.
+pub fn parse_issue_id(issue_id: &str) -> anyhow::Result<(String, String, i32)> {
+    let issue_parts: Vec<&str> = issue_id.trim_end_matches('/').rsplitn(5, '/').collect();
+    if issue_parts.len() < 4 {
+        return Err(anyhow::anyhow!("Malformed issue_id: {}", issue_id));
+    }
+    let issue_number = issue_parts[0]
+        .parse::<i32>()
+        .map_err(|_| anyhow::anyhow!("Malformed issue_id: {}", issue_id))?;
     let (repo, owner) = (issue_parts[2].to_string(), issue_parts[3].to_string());
 
-    let url = format!("https://api.github.com/repos/{owner}/{repo}/issues/{issue_number}/comments");
+    Ok((owner, repo, issue_number))
+}
+
+fn issue_api_url(issue_id: &str) -> anyhow::Result<String> {
+    let (owner, repo, issue_number) = parse_issue_id(issue_id)?;
+    Ok(format!(
+        "https://api.github.com/repos/{owner}/{repo}/issues/{issue_number}"
+    ))
+}
+
+pub async fn comment_on_issue(issue_id: &str, comment: &str) -> anyhow::Result<()> {
+    // let issue_id = "https://github.com/alabulei1/a-test/issues/87";
+    let url = format!("{}/comments", issue_api_url(issue_id)?);
 
     if let Err(e) = github_http_post(&url, comment).await {
         log::error!("Error commenting on issue: {:?}", e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub async fn add_labels_to_issue(issue_id: &str, labels: &[&str]) -> anyhow::Result<()> {
+    let url = format!("{}/labels", issue_api_url(issue_id)?);
+    let body = serde_json::json!({ "labels": labels });
+
+    if let Err(e) = github_http_request_ok(Method::POST, &url, Some(&body)).await {
+        log::error!("Error adding labels {:?} to {}: {:?}", labels, issue_id, e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// GitHub answers 404 when the label is not on the issue, which is treated as done.
+pub async fn remove_label_from_issue(issue_id: &str, label: &str) -> anyhow::Result<()> {
+    let url = format!(
+        "{}/labels/{}",
+        issue_api_url(issue_id)?,
+        encode_path_segment(label)
+    );
+
+    match github_http_request(Method::DELETE, &url, None).await {
+        Ok((200..=299 | 404, _)) => Ok(()),
+        Ok((status, _)) => {
+            log::error!(
+                "Error removing label {} from {}: {}",
+                label,
+                issue_id,
+                status
+            );
+            Err(anyhow::anyhow!("Github http error {:?}", status))
+        }
+        Err(e) => {
+            log::error!("Error removing label {} from {}: {:?}", label, issue_id, e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn assign_users_to_issue(issue_id: &str, logins: &[String]) -> anyhow::Result<()> {
+    let url = format!("{}/assignees", issue_api_url(issue_id)?);
+    let body = serde_json::json!({ "assignees": logins });
+
+    if let Err(e) = github_http_request_ok(Method::POST, &url, Some(&body)).await {
+        log::error!("Error assigning {:?} to {}: {:?}", logins, issue_id, e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub async fn unassign_users_from_issue(issue_id: &str, logins: &[String]) -> anyhow::Result<()> {
+    let url = format!("{}/assignees", issue_api_url(issue_id)?);
+    let body = serde_json::json!({ "assignees": logins });
+
+    if let Err(e) = github_http_request_ok(Method::DELETE, &url, Some(&body)).await {
+        log::error!("Error unassigning {:?} from {}: {:?}", logins, issue_id, e);
+        return Err(e);
     }
     Ok(())
 }
 
+// `state_reason` is "completed" or "not_planned".
+pub async fn close_issue(issue_id: &str, state_reason: &str) -> anyhow::Result<()> {
+    let url = issue_api_url(issue_id)?;
+    let body = serde_json::json!({ "state": "closed", "state_reason": state_reason });
+
+    if let Err(e) = github_http_request_ok(Method::PATCH, &url, Some(&body)).await {
+        log::error!("Error closing issue {}: {:?}", issue_id, e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub async fn reopen_issue(issue_id: &str) -> anyhow::Result<()> {
+    let url = issue_api_url(issue_id)?;
+    let body = serde_json::json!({ "state": "open" });
+
+    if let Err(e) = github_http_request_ok(Method::PATCH, &url, Some(&body)).await {
+        log::error!("Error reopening issue {}: {:?}", issue_id, e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// Keeps the review decision visible on GitHub: exactly one of the funded/declined labels.
+pub async fn label_review_decision(issue_id: &str, approved: bool) -> anyhow::Result<()> {
+    let (add, remove) = if approved {
+        (FUNDED_LABEL, DECLINED_LABEL)
+    } else {
+        (DECLINED_LABEL, FUNDED_LABEL)
+    };
+
+    add_labels_to_issue(issue_id, &[add]).await?;
+    remove_label_from_issue(issue_id, remove).await
+}
+
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
 pub async fn mock_comment_on_issue(issue_number: i32, comment: &str) -> anyhow::Result<()> {
     // let issue_id = "https://github.com/alabulei1/a-test/issues/87";
     // let project_id = "https://github.com/KwickerHub/WebCraftifyAI";
@@ -29,3 +202,142 @@ pub async fn mock_comment_on_issue(issue_number: i32, comment: &str) -> anyhow::
 
     Ok(())
 }
+
+// Queues a comment in the notifications outbox. The table is unique on
+// (issue_id, notification_kind), so a kind already queued or sent for an issue is ignored.
+pub async fn enqueue_notification(
+    pool: &Pool,
+    issue_id: &str,
+    kind: NotificationKind,
+    comment_body: &str,
+) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT IGNORE INTO notifications (issue_id, notification_kind, comment_body)
+                  VALUES (:issue_id, :notification_kind, :comment_body)";
+
+    if let Err(e) = conn
+        .exec_drop(
+            query,
+            params! {
+                "issue_id" => issue_id,
+                "notification_kind" => kind.as_str(),
+                "comment_body" => comment_body,
+            },
+        )
+        .await
+    {
+        log::error!("Error enqueue notification: {:?}", e);
+        return Err(e);
+    };
+
+    Ok(())
+}
+
+// The comment may or may not have been posted before the crash, so a stuck row is not
+// sent again; it is marked failed for someone to check the issue and requeue it by hand.
+pub async fn flag_stuck_notifications(pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    conn.exec_drop(
+        r"UPDATE notifications
+        SET status = 'failed',
+        last_error = CONCAT('stuck in sending since ', COALESCE(date_claimed, 'before claims were dated'), ', check the issue for the comment before requeueing')
+        WHERE status = 'sending'
+        AND (date_claimed IS NULL OR date_claimed < DATE_SUB(NOW(), INTERVAL :timeout MINUTE))",
+        params! {
+            "timeout" => SENDING_TIMEOUT_MINUTES,
+        },
+    )
+    .await?;
+    if conn.affected_rows() > 0 {
+        log::error!(
+            "Notifications stuck in sending marked failed, count: {}",
+            conn.affected_rows()
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn send_pending_notifications(pool: &Pool) -> anyhow::Result<()> {
+    if let Err(e) = flag_stuck_notifications(pool).await {
+        log::error!("Error flagging stuck notifications: {:?}", e);
+    }
+    let mut conn = pool.get_conn().await?;
+
+    let pending: Vec<(i32, String, String, i32)> = conn
+        .exec_map(
+            r"SELECT notification_id, issue_id, comment_body, attempts FROM notifications
+            WHERE status = 'pending' AND attempts < :max_attempts
+            ORDER BY date_created ASC LIMIT 50",
+            params! {
+                "max_attempts" => MAX_SEND_ATTEMPTS,
+            },
+            |(notification_id, issue_id, comment_body, attempts)| {
+                (notification_id, issue_id, comment_body, attempts)
+            },
+        )
+        .await?;
+    log::info!("Pending notifications to send, count: {:?}", pending.len());
+
+    for (notification_id, issue_id, comment_body, attempts) in pending {
+        // claim the row first, a crash after posting leaves it in 'sending' for
+        // flag_stuck_notifications instead of letting the next run post the same comment again
+        conn.exec_drop(
+            r"UPDATE notifications SET status = 'sending', date_claimed = NOW()
+            WHERE notification_id = :notification_id AND status = 'pending'",
+            params! {
+                "notification_id" => notification_id,
+            },
+        )
+        .await?;
+        if conn.affected_rows() == 0 {
+            continue;
+        }
+
+        let mut attempts = attempts;
+        let mut last_error = None;
+        for _ in 0..SEND_RETRIES_PER_RUN {
+            if attempts >= MAX_SEND_ATTEMPTS {
+                break;
+            }
+            attempts += 1;
+            match comment_on_issue(&issue_id, &comment_body).await {
+                Ok(()) => {
+                    last_error = None;
+                    break;
+                }
+                Err(e) => last_error = Some(e.to_string()),
+            }
+        }
+
+        let (status, date_sent_sql) = match &last_error {
+            None => ("sent", "NOW()"),
+            Some(_) if attempts >= MAX_SEND_ATTEMPTS => ("failed", "NULL"),
+            Some(_) => ("pending", "NULL"),
+        };
+
+        if let Err(e) = conn
+            .exec_drop(
+                format!(
+                    r"UPDATE notifications
+                    SET status = :status, attempts = :attempts, last_error = :last_error, date_sent = {}
+                    WHERE notification_id = :notification_id",
+                    date_sent_sql
+                ),
+                params! {
+                    "status" => status,
+                    "attempts" => attempts,
+                    "last_error" => &last_error,
+                    "notification_id" => notification_id,
+                },
+            )
+            .await
+        {
+            log::error!("Error updating notification status: {:?}", e);
+        }
+    }
+
+    Ok(())
+}
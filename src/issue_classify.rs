@@ -0,0 +1,266 @@
+use crate::llm_provider::LlmProvider;
+use crate::llm_utils::{extract_json_object, SummaryFailure};
+use crate::prompts::ISSUE_CLASSIFY_PROMPT;
+use crate::text_prep::*;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+
+pub static DIFFICULTIES: [&str; 3] = ["good-first-issue", "intermediate", "advanced"];
+pub static ISSUE_TYPES: [&str; 4] = ["bug", "feature", "docs", "test"];
+pub static MAX_SKILLS: usize = 6;
+// a week of full-time work, longer estimates are not meaningful for one issue
+pub static MAX_EFFORT_HOURS: i32 = 40;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IssueClassification {
+    pub difficulty: Option<String>,
+    pub effort_hours: Option<i32>,
+    pub skills: Vec<String>,
+    pub issue_type: Option<String>,
+}
+
+fn normalize_difficulty(value: &str) -> Option<String> {
+    let value = value.trim().to_lowercase().replace(['_', ' '], "-");
+    let difficulty = match value.as_str() {
+        "good-first-issue" | "beginner" | "easy" => "good-first-issue",
+        "intermediate" | "medium" => "intermediate",
+        "advanced" | "hard" | "expert" => "advanced",
+        _ => return None,
+    };
+    Some(difficulty.to_string())
+}
+
+fn normalize_issue_type(value: &str) -> Option<String> {
+    let issue_type = match value.trim().to_lowercase().as_str() {
+        "bug" | "bugfix" | "fix" => "bug",
+        "feature" | "enhancement" => "feature",
+        "docs" | "doc" | "documentation" => "docs",
+        "test" | "tests" | "testing" => "test",
+        _ => return None,
+    };
+    Some(issue_type.to_string())
+}
+
+// A number, or a string starting with one like "4-6 hours", rounded into 1..=MAX_EFFORT_HOURS.
+fn parse_effort_hours(value: &serde_json::Value) -> Option<i32> {
+    let hours = match value {
+        serde_json::Value::Number(n) => n.as_f64()?,
+        serde_json::Value::String(s) => {
+            let digits: String = s
+                .trim()
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            digits.parse::<f64>().ok()?
+        }
+        _ => return None,
+    };
+    if !hours.is_finite() || hours <= 0.0 {
+        return None;
+    }
+    Some((hours.round() as i32).clamp(1, MAX_EFFORT_HOURS))
+}
+
+// Reads the JSON object in the reply. Values outside the known sets become None
+// instead of failing the whole reply, skills are lowercased and deduplicated.
+pub fn parse_issue_classification(reply: &str) -> anyhow::Result<IssueClassification> {
+    #[derive(Deserialize)]
+    struct RawClassification {
+        difficulty: Option<String>,
+        effort_hours: Option<serde_json::Value>,
+        #[serde(default)]
+        skills: Vec<String>,
+        issue_type: Option<String>,
+    }
+
+    let json_str =
+        extract_json_object(reply).ok_or_else(|| anyhow::anyhow!("no JSON object in the reply"))?;
+    let raw: RawClassification = serde_json::from_str(json_str)?;
+
+    let mut skills: Vec<String> = Vec::new();
+    for skill in raw.skills {
+        let skill = skill.trim().to_lowercase();
+        if !skill.is_empty() && !skills.contains(&skill) {
+            skills.push(skill);
+        }
+    }
+    skills.truncate(MAX_SKILLS);
+
+    Ok(IssueClassification {
+        difficulty: raw.difficulty.as_deref().and_then(normalize_difficulty),
+        effort_hours: raw.effort_hours.as_ref().and_then(parse_effort_hours),
+        skills,
+        issue_type: raw.issue_type.as_deref().and_then(normalize_issue_type),
+    })
+}
+
+// Replies that do not parse come back as a SummaryFailure for the caller to record.
+pub async fn classify_issue(
+    provider: &dyn LlmProvider,
+    issue_title: &str,
+    issue_description: &str,
+) -> anyhow::Result<std::result::Result<IssueClassification, SummaryFailure>> {
+    let description = truncate_to_tokens(
+        &clean_markdown(issue_description),
+        ISSUE_INPUT_TOKENS,
+        provider.model(),
+    );
+    let input = format!("Title: {}\n\n{}", issue_title, description);
+    let reply = provider
+        .chat_json(ISSUE_CLASSIFY_PROMPT, &input, 120)
+        .await?;
+
+    match parse_issue_classification(&reply) {
+        Ok(classification) => Ok(Ok(classification)),
+        Err(e) => Ok(Err(SummaryFailure {
+            reply,
+            repaired_reply: String::new(),
+            error: e.to_string(),
+        })),
+    }
+}
+
+// Whether the stored classification was made from the same texts as the summary hash.
+pub async fn classification_is_current(
+    pool: &Pool,
+    issue_id: &str,
+    content_hash: &str,
+) -> Result<bool> {
+    let mut conn = pool.get_conn().await?;
+
+    let stored: Option<Option<String>> = conn
+        .exec_first(
+            r"SELECT content_hash FROM issue_classifications WHERE issue_id = :issue_id",
+            params! {
+                "issue_id" => issue_id,
+            },
+        )
+        .await?;
+
+    Ok(stored.flatten().as_deref() == Some(content_hash))
+}
+
+// Issues are classified as they are summarized, some before they reach issues_master,
+// so the result is kept here and copied over by classification_master.
+pub async fn add_issue_classification(
+    pool: &Pool,
+    issue_id: &str,
+    classification: &IssueClassification,
+    content_hash: &str,
+    llm_model: &str,
+) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT INTO issue_classifications (issue_id, difficulty, effort_hours, skills, issue_type, content_hash, llm_model)
+    VALUES (:issue_id, :difficulty, :effort_hours, :skills, :issue_type, :content_hash, :llm_model)
+    ON DUPLICATE KEY UPDATE
+    difficulty = VALUES(difficulty),
+    effort_hours = VALUES(effort_hours),
+    skills = VALUES(skills),
+    issue_type = VALUES(issue_type),
+    content_hash = VALUES(content_hash),
+    llm_model = VALUES(llm_model),
+    date_classified = NOW();";
+
+    if let Err(e) = conn
+        .exec_drop(
+            query,
+            params! {
+                "issue_id" => issue_id,
+                "difficulty" => &classification.difficulty,
+                "effort_hours" => classification.effort_hours,
+                "skills" => serde_json::json!(classification.skills).to_string(),
+                "issue_type" => &classification.issue_type,
+                "content_hash" => content_hash,
+                "llm_model" => llm_model,
+            },
+        )
+        .await
+    {
+        log::error!("Error saving classification of {}: {:?}", issue_id, e);
+        return Err(e);
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn known_difficulty_spellings_are_normalized() {
+        assert_eq!(
+            normalize_difficulty("Good First Issue").as_deref(),
+            Some("good-first-issue")
+        );
+        assert_eq!(
+            normalize_difficulty("good_first_issue").as_deref(),
+            Some("good-first-issue")
+        );
+        assert_eq!(
+            normalize_difficulty(" Medium ").as_deref(),
+            Some("intermediate")
+        );
+        assert_eq!(normalize_difficulty("EXPERT").as_deref(), Some("advanced"));
+    }
+
+    #[test]
+    fn unknown_difficulty_is_dropped() {
+        assert_eq!(normalize_difficulty("trivial"), None);
+        assert_eq!(normalize_difficulty(""), None);
+
+        let parsed = parse_issue_classification(
+            r#"{"difficulty": "moderate", "effort_hours": 3, "skills": [], "issue_type": "bug"}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.difficulty, None);
+        assert_eq!(parsed.effort_hours, Some(3));
+        assert_eq!(parsed.issue_type.as_deref(), Some("bug"));
+    }
+
+    #[test]
+    fn effort_as_number_range_or_text() {
+        assert_eq!(parse_effort_hours(&json!(2.4)), Some(2));
+        assert_eq!(parse_effort_hours(&json!(0.3)), Some(1));
+        assert_eq!(parse_effort_hours(&json!(500)), Some(MAX_EFFORT_HOURS));
+        // a range keeps its lower end
+        assert_eq!(parse_effort_hours(&json!("4-6 hours")), Some(4));
+        assert_eq!(parse_effort_hours(&json!("2.5h")), Some(3));
+        assert_eq!(parse_effort_hours(&json!("a couple of days")), None);
+        assert_eq!(parse_effort_hours(&json!("0 hours")), None);
+        assert_eq!(parse_effort_hours(&json!(-2)), None);
+        assert_eq!(parse_effort_hours(&json!(null)), None);
+        assert_eq!(parse_effort_hours(&json!([4, 6])), None);
+    }
+
+    #[test]
+    fn reply_is_read_from_surrounding_text() {
+        let reply = "Here is the classification:\n```json\n{\"difficulty\": \"easy\", \"effort_hours\": \"8 to 10\", \"skills\": [\"Rust\", \"rust\", \" SQL \", \"\"], \"issue_type\": \"Enhancement\"}\n```";
+        let parsed = parse_issue_classification(reply).unwrap();
+        assert_eq!(parsed.difficulty.as_deref(), Some("good-first-issue"));
+        assert_eq!(parsed.effort_hours, Some(8));
+        assert_eq!(parsed.skills, vec!["rust", "sql"]);
+        assert_eq!(parsed.issue_type.as_deref(), Some("feature"));
+    }
+
+    #[test]
+    fn skills_are_capped() {
+        let parsed =
+            parse_issue_classification(r#"{"skills": ["a", "b", "c", "d", "e", "f", "g", "h"]}"#)
+                .unwrap();
+        assert_eq!(parsed.skills.len(), MAX_SKILLS);
+        assert_eq!(parsed.difficulty, None);
+        assert_eq!(parsed.effort_hours, None);
+    }
+
+    #[test]
+    fn malformed_replies_are_errors() {
+        assert!(parse_issue_classification("I cannot classify this issue.").is_err());
+        assert!(parse_issue_classification(r#"{"difficulty": "easy", "effort_hours": }"#).is_err());
+        assert!(parse_issue_classification(r#"{"skills": "rust"}"#).is_err());
+        assert!(parse_issue_classification("} {").is_err());
+    }
+}
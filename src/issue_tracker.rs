@@ -1,3 +1,6 @@
+use crate::budget_extract::*;
+use crate::issue_bot::parse_issue_id;
+use crate::pr_linking::*;
 use anyhow::anyhow;
 use chrono::{DateTime, Duration, ParseError, Utc};
 use http_req::{
@@ -7,7 +10,7 @@ use http_req::{
 use serde::{Deserialize, Serialize};
 use std::env;
 
-fn convert_datetime(merged_at: &str) -> Result<String, ParseError> {
+pub fn convert_datetime(merged_at: &str) -> Result<String, ParseError> {
     let datetime: DateTime<Utc> = merged_at.parse()?;
     Ok(datetime.format("%Y-%m-%d %H:%M:%S").to_string())
 }
@@ -70,6 +73,56 @@ pub async fn github_http_post(url: &str, body: &str) -> anyhow::Result<Vec<u8>>
     }
 }
 
+// Generic REST call for the issue actions, `body` is sent as JSON when present. Returns the
+// status code with the response body, error statuses included, so callers can tell a 404
+// apart; only failing to reach GitHub is an error.
+pub async fn github_http_request(
+    method: Method,
+    url: &str,
+    body: Option<&serde_json::Value>,
+) -> anyhow::Result<(u16, Vec<u8>)> {
+    let token = env::var("GITHUB_TOKEN").map_err(|_| anyhow!("github_token is required"))?;
+    let mut writer = Vec::new();
+
+    let uri = Uri::try_from(url).map_err(|e| anyhow!("failed to parse url {}: {:?}", url, e))?;
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let token_header = format!("Bearer {}", token);
+
+    let mut request = Request::new(&uri);
+    request
+        .method(method)
+        .header("User-Agent", "flows-network connector")
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/vnd.github+json")
+        .header("Authorization", &token_header)
+        .header("Content-Length", &body.len());
+    if !body.is_empty() {
+        request.body(body.as_bytes());
+    }
+
+    match request.send(&mut writer) {
+        Ok(res) => Ok((u16::from(res.status_code()), writer)),
+        Err(_e) => {
+            log::error!("Error getting response from Github: {:?}", _e);
+            Err(anyhow::anyhow!(_e))
+        }
+    }
+}
+
+// github_http_request for callers that only accept a 2xx answer.
+pub async fn github_http_request_ok(
+    method: Method,
+    url: &str,
+    body: Option<&serde_json::Value>,
+) -> anyhow::Result<Vec<u8>> {
+    let (status, res) = github_http_request(method, url, body).await?;
+    if !(200..300).contains(&status) {
+        log::error!("Github http error {:?}", status);
+        return Err(anyhow::anyhow!("Github http error {:?}", status));
+    }
+    Ok(res)
+}
+
 pub async fn github_http_post_gql(query: &str) -> anyhow::Result<Vec<u8>> {
     let token = env::var("GITHUB_TOKEN").expect("github_token is required");
     let base_url = Uri::try_from("https://api.github.com/graphql").unwrap();
@@ -102,6 +155,7 @@ pub async fn github_http_post_gql(query: &str) -> anyhow::Result<Vec<u8>> {
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct RepoData {
     pub project_id: String,
+    pub project_node_id: Option<String>,
     pub repo_description: String,
     pub repo_readme: String,
     pub repo_stars: i64,
@@ -140,6 +194,7 @@ pub async fn search_repos_in_batch(query: &str) -> anyhow::Result<Vec<RepoData>>
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Repo {
+        id: Option<String>,
         url: String,
         description: Option<String>,
         readme: Option<Readme>,
@@ -172,6 +227,7 @@ pub async fn search_repos_in_batch(query: &str) -> anyhow::Result<Vec<RepoData>>
                     repositoryCount
                     nodes {{
                         ... on Repository {{
+                            id
                             url
                             description
                             stargazers {{
@@ -211,6 +267,7 @@ pub async fn search_repos_in_batch(query: &str) -> anyhow::Result<Vec<RepoData>>
                 for repo in nodes {
                     all_repos.push(RepoData {
                         project_id: repo.url.clone(),
+                        project_node_id: repo.id.clone(),
                         repo_description: repo.description.clone().unwrap_or_default(),
                         repo_readme: repo.readme.and_then(|r| r.text).unwrap_or_default(),
                         main_language: repo
@@ -230,11 +287,27 @@ pub async fn search_repos_in_batch(query: &str) -> anyhow::Result<Vec<RepoData>>
     Ok(all_repos)
 }
 
+// A GitHub user who worked on an issue; login is what mentions and payouts use,
+// database_id stays the same when the login changes.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Contributor {
+    pub login: String,
+    pub database_id: Option<i64>,
+    pub node_id: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct IssueAssigned {
     pub issue_id: String, // url of an issue
+    pub issue_node_id: Option<String>,
+    pub event_node_id: Option<String>,
+    pub event_type: String, // "assigned" or "unassigned"
     pub issue_assignee: String,
-    pub date_assigned: String,
+    pub assignee_node_id: Option<String>,
+    pub assignee_profile: Option<Contributor>,
+    pub event_actor: Option<String>,
+    pub date_assigned: String, // date of the event
 }
 
 pub async fn search_issues_assigned(query: &str) -> anyhow::Result<Vec<IssueAssigned>> {
@@ -266,13 +339,18 @@ pub async fn search_issues_assigned(query: &str) -> anyhow::Result<Vec<IssueAssi
     #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct IssueNode {
+        id: Option<String>,
         url: Option<String>,
         timelineItems: Option<TimelineItems>,
     }
 
+    #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Assignee {
+        id: Option<String>,
         login: Option<String>,
+        databaseId: Option<i64>,
+        avatarUrl: Option<String>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
@@ -280,9 +358,17 @@ pub async fn search_issues_assigned(query: &str) -> anyhow::Result<Vec<IssueAssi
         nodes: Option<Vec<AssignedEvent>>,
     }
 
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Actor {
+        login: Option<String>,
+    }
+
     #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct AssignedEvent {
+        __typename: Option<String>,
+        id: Option<String>,
+        actor: Option<Actor>,
         assignee: Option<Assignee>,
         createdAt: Option<String>,
     }
@@ -290,6 +376,7 @@ pub async fn search_issues_assigned(query: &str) -> anyhow::Result<Vec<IssueAssi
     let mut all_issues = Vec::new();
     let mut after_cursor: Option<String> = None;
 
+    // the latest 100 assignment events are plenty to tell who is assigned now
     for _ in 0..10 {
         let query_str = format!(
             r#"
@@ -298,19 +385,43 @@ pub async fn search_issues_assigned(query: &str) -> anyhow::Result<Vec<IssueAssi
                         issueCount
                         nodes {{
                             ... on Issue {{
+                                id
                                 url
-                                timelineItems(first: 1, itemTypes: [ASSIGNED_EVENT]) {{
+                                timelineItems(last: 100, itemTypes: [ASSIGNED_EVENT, UNASSIGNED_EVENT]) {{
                                     nodes {{
+                                      __typename
                                       ... on AssignedEvent {{
+                                        id
+                                        actor {{
+                                          login
+                                        }}
                                         assignee {{
                                           ... on User {{
+                                            id
                                             login
+                                            databaseId
+                                            avatarUrl
+                                          }}
+                                        }}
+                                        createdAt
+                                      }}
+                                      ... on UnassignedEvent {{
+                                        id
+                                        actor {{
+                                          login
+                                        }}
+                                        assignee {{
+                                          ... on User {{
+                                            id
+                                            login
+                                            databaseId
+                                            avatarUrl
                                           }}
                                         }}
                                         createdAt
                                       }}
                                     }}
-                                }}   
+                                }}
                             }}
                         }}
                         pageInfo {{
@@ -349,9 +460,32 @@ pub async fn search_issues_assigned(query: &str) -> anyhow::Result<Vec<IssueAssi
 
                                     let date_assigned =
                                         convert_datetime(&created_at).unwrap_or_default();
+                                    let event_type = match node.__typename.as_deref() {
+                                        Some("UnassignedEvent") => "unassigned",
+                                        _ => "assigned",
+                                    };
                                     all_issues.push(IssueAssigned {
                                         issue_id: issue.url.clone().unwrap_or_default(),
+                                        issue_node_id: issue.id.clone(),
+                                        event_node_id: node.id.clone(),
+                                        event_type: event_type.to_string(),
                                         issue_assignee: assignee,
+                                        assignee_node_id: node
+                                            .assignee
+                                            .as_ref()
+                                            .and_then(|a| a.id.clone()),
+                                        assignee_profile: node.assignee.as_ref().and_then(|a| {
+                                            a.login.clone().map(|login| Contributor {
+                                                login,
+                                                database_id: a.databaseId,
+                                                node_id: a.id.clone(),
+                                                avatar_url: a.avatarUrl.clone(),
+                                            })
+                                        }),
+                                        event_actor: node
+                                            .actor
+                                            .as_ref()
+                                            .and_then(|a| a.login.clone()),
                                         date_assigned,
                                     });
                                 }
@@ -377,11 +511,15 @@ pub async fn search_issues_assigned(query: &str) -> anyhow::Result<Vec<IssueAssi
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct IssueOpen {
     pub issue_title: String,
-    pub issue_id: String,          // url of an issue
+    pub issue_id: String, // url of an issue
+    pub issue_node_id: Option<String>,
     pub issue_creator: String,     // url of an issue
     pub issue_budget: i32,         // url of an issue
     pub issue_description: String, // description of the issue, could be truncated body text
     pub project_id: String,        // url of the repo
+    pub project_node_id: Option<String>,
+    pub issue_creator_node_id: Option<String>,
+    pub issue_budget_evidence: Option<BudgetCandidate>, // where issue_budget was read from
 }
 
 pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
@@ -412,17 +550,37 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Issue {
+        id: Option<String>,
         title: String,
         url: String,
         body: Option<String>,
         author: Option<Author>,
+        labels: Option<LabelNodes>,
+        repository: Option<Repository>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Author {
+        id: Option<String>,
         login: Option<String>,
     }
 
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Repository {
+        id: Option<String>,
+        url: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct LabelNodes {
+        nodes: Option<Vec<Label>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Label {
+        name: Option<String>,
+    }
+
     let mut all_issues = Vec::new();
     let mut after_cursor: Option<String> = None;
 
@@ -434,11 +592,24 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
                     issueCount
                     nodes {{
                         ... on Issue {{
+                            id
                             title
                             url
                             body
                             author {{
                                 login
+                                ... on Node {{
+                                    id
+                                }}
+                            }}
+                            labels(first: 10) {{
+                                nodes {{
+                                    name
+                                }}
+                            }}
+                            repository {{
+                                id
+                                url
                             }}
                         }}
                     }}
@@ -478,25 +649,47 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
                             .chars()
                             .take(8000)
                             .collect::<String>();
+                        // the repository url is authoritative, the issue url may be
+                        // an old one after a rename or transfer
                         let project_id = issue
-                            .url
-                            .rsplitn(3, '/')
-                            .nth(2)
-                            .unwrap_or("wrong_project_id")
-                            .to_string();
+                            .repository
+                            .as_ref()
+                            .and_then(|r| r.url.clone())
+                            .unwrap_or_else(|| {
+                                issue
+                                    .url
+                                    .rsplitn(3, '/')
+                                    .nth(2)
+                                    .unwrap_or("wrong_project_id")
+                                    .to_string()
+                            });
                         let issue_creator = issue
                             .author
                             .as_ref()
                             .and_then(|author| author.login.clone())
                             .unwrap_or_default();
-                        let issue_budget = extract_budget(&issue_description);
+                        let issue_labels = issue.labels.as_ref().map_or(Vec::new(), |labels| {
+                            labels.nodes.as_ref().map_or(Vec::new(), |nodes| {
+                                nodes
+                                    .iter()
+                                    .filter_map(|label| label.name.clone())
+                                    .collect::<Vec<String>>()
+                            })
+                        });
+                        let issue_budget_evidence =
+                            extract_budget_with_fallback(&issue_description, &issue_labels).await;
+                        let issue_budget = issue_budget_evidence.as_ref().map_or(0, |c| c.amount);
                         all_issues.push(IssueOpen {
                             issue_title: issue.title,
                             issue_id: issue.url, // Assuming issue.url is the issue_id
+                            issue_node_id: issue.id,
                             issue_creator,
                             issue_description,
                             issue_budget,
                             project_id,
+                            project_node_id: issue.repository.and_then(|r| r.id),
+                            issue_creator_node_id: issue.author.and_then(|a| a.id),
+                            issue_budget_evidence,
                         });
                     }
                 }
@@ -517,8 +710,11 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct IssueComment {
-    pub issue_id: String,        // url of an issue
+    pub issue_id: String, // url of an issue
+    pub issue_node_id: Option<String>,
+    pub comment_node_id: Option<String>,
     pub comment_creator: String, // url of an issue
+    pub comment_creator_node_id: Option<String>,
     pub comment_date: String,
     pub comment_body: String,
 }
@@ -551,6 +747,7 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Issue {
+        id: Option<String>,
         url: String,
         comments: Option<CommentNodes>,
     }
@@ -563,6 +760,7 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
     #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Comment {
+        id: Option<String>,
         author: Option<Author>,
         body: Option<String>,
         updatedAt: Option<String>,
@@ -570,6 +768,7 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Author {
+        id: Option<String>,
         login: Option<String>,
     }
 
@@ -586,11 +785,16 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
                     issueCount
                     nodes {{
                         ... on Issue {{
+                            id
                             url
                             comments (first: 100, orderBy: {{field: UPDATED_AT, direction: DESC}}) {{
                                 nodes {{
+                                  id
                                   author {{
                                     login
+                                    ... on Node {{
+                                      id
+                                    }}
                                   }}
                                   body
                                   updatedAt
@@ -643,7 +847,13 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
                                                 .unwrap_or_default();
                                             inner_comments_vec.push(IssueComment {
                                                 issue_id: issue.url.clone(),
+                                                issue_node_id: issue.id.clone(),
+                                                comment_node_id: comment.id.clone(),
                                                 comment_creator,
+                                                comment_creator_node_id: comment
+                                                    .author
+                                                    .as_ref()
+                                                    .and_then(|author| author.id.clone()),
                                                 comment_date: updated_at
                                                     .format("%Y-%m-%d %H:%M:%S")
                                                     .to_string(),
@@ -676,24 +886,19 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
 }
 
 pub fn extract_budget(body: &str) -> i32 {
-    let re = regex::Regex::new(r"(?i)budget:?\s*(\d{2,3})").unwrap();
-    for cap in re.captures_iter(body) {
-        if let Some(match_) = cap.get(1) {
-            if let Ok(b) = match_.as_str().parse::<i32>() {
-                if b >= 10 && b <= 999 {
-                    return b; // Returns the first match found
-                }
-            }
-        }
-    }
-    0
+    best_budget_candidate(body, &[]).map_or(0, |c| c.amount)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct IssueClosed {
     pub issue_id: String, // url of an issue
-    pub issue_assignees: Option<Vec<String>>,
+    pub issue_node_id: Option<String>,
+    pub issue_assignees: Option<Vec<String>>, // logins
+    pub assignee_profiles: Vec<Contributor>,
     pub issue_linked_pr: Option<String>,
+    pub issue_linked_pr_node_id: Option<String>,
+    pub issue_linked_pr_method: Option<String>,
+    pub issue_linked_pr_confidence: Option<f32>,
 }
 
 pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed>> {
@@ -725,10 +930,11 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
     #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Issue {
+        id: Option<String>,
         url: Option<String>,
         labels: Option<LabelNodes>,
         assignees: Option<AssigneeNodes>,
-        timelineItems: Option<TimelineItems>,
+        timelineItems: Option<LinkTimelineItems>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
@@ -746,33 +952,13 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
         nodes: Option<Vec<Assignee>>,
     }
 
-    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
-    struct Assignee {
-        name: Option<String>,
-    }
-
-    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
-    struct TimelineItems {
-        nodes: Option<Vec<ClosedEvent>>,
-    }
-
     #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
-    struct ClosedEvent {
-        stateReason: Option<String>,
-        closer: Option<Closer>,
-    }
-
-    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
-    struct Closer {
-        title: Option<String>,
-        url: Option<String>,
-        author: Option<Author>,
-    }
-
-    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
-    struct Author {
+    struct Assignee {
+        id: Option<String>,
         login: Option<String>,
+        databaseId: Option<i64>,
+        avatarUrl: Option<String>,
     }
 
     let mut all_issues = Vec::new();
@@ -786,33 +972,22 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
                     issueCount
                     nodes {{
                         ... on Issue {{
+                            id
                             url
                             labels(first: 10) {{
                                 nodes {{
                                     name
                                 }}
                             }}
-                            assignees(first: 5) {{
-                                nodes {{
-                                    name
-                                }}
-                            }}
-                            timelineItems(first: 1, itemTypes: [CLOSED_EVENT]) {{
+                            assignees(first: 10) {{
                                 nodes {{
-                                    ... on ClosedEvent {{
-                                        stateReason
-                                        closer {{
-                                            ... on PullRequest {{
-                                                title
-                                                url
-                                                author {{
-                                                    login
-                                                }}
-                                            }}
-                                        }}
-                                    }}
+                                    id
+                                    login
+                                    databaseId
+                                    avatarUrl
                                 }}
                             }}
+                            {}
                         }}
                     }}
                     pageInfo {{
@@ -826,6 +1001,7 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
             after_cursor
                 .as_ref()
                 .map_or(String::from("null"), |c| format!("\"{}\"", c)),
+            LINK_TIMELINE_ITEMS,
         );
 
         let response_body = github_http_post_gql(&query_str)
@@ -853,61 +1029,53 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
                             })
                         });
 
-                        let mut issue_assignees = issue.assignees.as_ref().and_then(|assignees| {
-                            assignees.nodes.as_ref().map(|nodes| {
-                                nodes
-                                    .iter()
-                                    .filter_map(|assignee| assignee.name.clone())
-                                    .collect::<Vec<_>>()
-                            })
-                        });
-
-                        if let Some(assignees) = &issue_assignees {
-                            if assignees.is_empty() {
-                                issue_assignees = None;
-                            }
-                        }
-
-                        let (_close_reason, close_pull_request, _close_pr_title, _closer_login) =
-                            issue.timelineItems.as_ref().map_or(
-                                (None, None, None, None),
-                                |items| {
-                                    items
-                                        .nodes
-                                        .as_ref()
-                                        .map_or((None, None, None, None), |nodes| {
-                                            nodes
-                                                .iter()
-                                                .filter_map(|event| {
-                                                    if let Some(closer) = &event.closer {
-                                                        Some((
-                                                            event.stateReason.clone(),
-                                                            closer.url.clone(),
-                                                            closer.title.clone(),
-                                                            closer
-                                                                .author
-                                                                .as_ref()
-                                                                .map(|author| author.login.clone()),
-                                                        ))
-                                                    } else {
-                                                        Some((None, None, None, None))
-                                                    }
-                                                })
-                                                .next()
-                                                .unwrap_or((None, None, None, None))
+                        let assignee_profiles =
+                            issue.assignees.as_ref().map_or(Vec::new(), |assignees| {
+                                assignees.nodes.as_ref().map_or(Vec::new(), |nodes| {
+                                    nodes
+                                        .iter()
+                                        .filter_map(|assignee| {
+                                            assignee.login.clone().map(|login| Contributor {
+                                                login,
+                                                database_id: assignee.databaseId,
+                                                node_id: assignee.id.clone(),
+                                                avatar_url: assignee.avatarUrl.clone(),
+                                            })
                                         })
-                                },
-                            );
+                                        .collect::<Vec<_>>()
+                                })
+                            });
+
+                        let issue_assignees = if assignee_profiles.is_empty() {
+                            None
+                        } else {
+                            Some(assignee_profiles.iter().map(|c| c.login.clone()).collect())
+                        };
 
                         let issue_id = match issue.url {
                             Some(u) => u.to_string(),
                             None => continue,
                         };
 
+                        let events = issue
+                            .timelineItems
+                            .and_then(|t| t.nodes)
+                            .unwrap_or_default();
+                        let linked_pr = linked_pr_from_timeline(&issue_id, &events);
+
                         all_issues.push(IssueClosed {
                             issue_id: issue_id,
+                            issue_node_id: issue.id,
                             issue_assignees,
-                            issue_linked_pr: close_pull_request,
+                            assignee_profiles,
+                            issue_linked_pr: linked_pr.as_ref().map(|l| l.pull_id.clone()),
+                            issue_linked_pr_node_id: linked_pr
+                                .as_ref()
+                                .and_then(|l| l.pull_node_id.clone()),
+                            issue_linked_pr_method: linked_pr
+                                .as_ref()
+                                .map(|l| l.method.as_str().to_string()),
+                            issue_linked_pr_confidence: linked_pr.as_ref().map(|l| l.confidence),
                         });
                     }
                 }
@@ -925,13 +1093,221 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
     Ok(all_issues)
 }
 
+// The pull request that resolved a single issue, for callers that only know the issue url.
+pub async fn get_issue_linked_pr(issue_id: &str) -> anyhow::Result<Option<LinkedPr>> {
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct GraphQLResponse {
+        data: Option<Data>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Data {
+        resource: Option<Issue>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Issue {
+        timelineItems: Option<LinkTimelineItems>,
+    }
+
+    let query_str = format!(
+        r#"
+        query {{
+            resource(url: "{}") {{
+                ... on Issue {{
+                    {}
+                }}
+            }}
+        }}
+        "#,
+        issue_id.replace("\"", "\\\""),
+        LINK_TIMELINE_ITEMS
+    );
+
+    let response_body = github_http_post_gql(&query_str)
+        .await
+        .map_err(|e| anyhow!("Failed to post GraphQL query: {}", e))?;
+    let response: GraphQLResponse = serde_json::from_slice(&response_body)
+        .map_err(|e| anyhow!("Failed to deserialize response: {}", e))?;
+
+    let events = response
+        .data
+        .and_then(|d| d.resource)
+        .and_then(|i| i.timelineItems)
+        .and_then(|t| t.nodes)
+        .unwrap_or_default();
+
+    Ok(linked_pr_from_timeline(issue_id, &events))
+}
+
+// The latest assigned or unassigned event of `assignee` on an issue, as (event node id, date),
+// for webhook deliveries, which carry neither.
+pub async fn get_assignment_event(
+    issue_id: &str,
+    assignee: &str,
+    event_type: &str,
+) -> anyhow::Result<Option<(String, String)>> {
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct GraphQLResponse {
+        data: Option<Data>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Data {
+        resource: Option<Issue>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Issue {
+        timelineItems: Option<TimelineItems>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct TimelineItems {
+        nodes: Option<Vec<AssignmentEvent>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Assignee {
+        login: Option<String>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct AssignmentEvent {
+        id: Option<String>,
+        assignee: Option<Assignee>,
+        createdAt: Option<String>,
+    }
+
+    let (item_type, fragment) = match event_type {
+        "unassigned" => ("UNASSIGNED_EVENT", "UnassignedEvent"),
+        _ => ("ASSIGNED_EVENT", "AssignedEvent"),
+    };
+    let query_str = format!(
+        r#"
+        query {{
+            resource(url: "{}") {{
+                ... on Issue {{
+                    timelineItems(last: 20, itemTypes: [{}]) {{
+                        nodes {{
+                            ... on {} {{
+                                id
+                                assignee {{
+                                    ... on User {{
+                                        login
+                                    }}
+                                }}
+                                createdAt
+                            }}
+                        }}
+                    }}
+                }}
+            }}
+        }}
+        "#,
+        issue_id.replace("\"", "\\\""),
+        item_type,
+        fragment
+    );
+
+    let response_body = github_http_post_gql(&query_str)
+        .await
+        .map_err(|e| anyhow!("Failed to post GraphQL query: {}", e))?;
+    let response: GraphQLResponse = serde_json::from_slice(&response_body)
+        .map_err(|e| anyhow!("Failed to deserialize response: {}", e))?;
+
+    let event = response
+        .data
+        .and_then(|d| d.resource)
+        .and_then(|i| i.timelineItems)
+        .and_then(|t| t.nodes)
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+        .find(|e| {
+            e.assignee
+                .as_ref()
+                .and_then(|a| a.login.as_deref())
+                .map_or(false, |l| l.eq_ignore_ascii_case(assignee))
+        });
+
+    Ok(event.and_then(|e| {
+        let date = convert_datetime(e.createdAt.as_deref()?).ok()?;
+        Some((e.id?, date))
+    }))
+}
+
+// Follows the REST redirects GitHub answers with for transferred issues and renamed repos,
+// returns the node id and current url of the issue, or None when it is gone.
+pub async fn resolve_moved_issue(issue_id: &str) -> anyhow::Result<Option<(String, String)>> {
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct RestIssue {
+        node_id: String,
+        html_url: String,
+    }
+
+    let token = env::var("GITHUB_TOKEN").map_err(|_| anyhow!("github_token is required"))?;
+    let token_header = format!("Bearer {}", token);
+    let (owner, repo, issue_number) = parse_issue_id(issue_id)?;
+    let mut url = format!("https://api.github.com/repos/{owner}/{repo}/issues/{issue_number}");
+
+    for _ in 0..3 {
+        let mut writer = Vec::new();
+        let uri =
+            Uri::try_from(url.as_str()).map_err(|e| anyhow!("failed to parse url: {:?}", e))?;
+        let res = Request::new(&uri)
+            .method(Method::GET)
+            .header("User-Agent", "flows-network connector")
+            .header("Accept", "application/vnd.github+json")
+            .header("Authorization", &token_header)
+            .header("CONNECTION", "close")
+            .send(&mut writer)
+            .map_err(|e| anyhow!("Error getting response from Github: {:?}", e))?;
+
+        let status = res.status_code();
+        if status.is_success() {
+            let issue: RestIssue = serde_json::from_slice(&writer)?;
+            return Ok(Some((issue.node_id, issue.html_url)));
+        }
+        if status.is_redirect() {
+            match res.headers().get("Location") {
+                Some(location) => {
+                    url = location.clone();
+                    continue;
+                }
+                None => break,
+            }
+        }
+        // 404 for deleted or hidden issues, 410 for deleted ones
+        if u16::from(status) == 404 || u16::from(status) == 410 {
+            return Ok(None);
+        }
+        return Err(anyhow!("Github http error {:?}", status));
+    }
+
+    Err(anyhow!("Too many redirects resolving {}", issue_id))
+}
+
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct OuterPull {
     pub pull_id: String, // url of pull_request
+    pub pull_node_id: Option<String>,
     pub pull_title: String,
     pub pull_author: Option<String>,
+    pub pull_author_node_id: Option<String>,
     pub project_id: String,
+    pub project_node_id: Option<String>,
+    pub created_at: String,
     pub merged_at: String,
+    pub additions: Option<i32>,
+    pub deletions: Option<i32>,
+    pub changed_files: Option<i32>,
+    pub approvers: Option<Vec<String>>, // None when the source has no reviews
+    pub ci_status: Option<String>,      // SUCCESS, FAILURE, PENDING, ERROR or EXPECTED
+    pub pull_labels: Vec<String>,
 }
 
 pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>> {
@@ -956,19 +1332,33 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
     #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct PullRequest {
+        id: Option<String>,
         title: Option<String>,
         url: Option<String>,
         author: Option<Author>,
         labels: Option<Labels>,
         reviews: Option<Reviews>,
+        createdAt: Option<String>,
         mergedAt: Option<String>,
+        additions: Option<i32>,
+        deletions: Option<i32>,
+        changedFiles: Option<i32>,
+        commits: Option<Commits>,
+        repository: Option<Repository>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Author {
+        id: Option<String>,
         login: Option<String>,
     }
 
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Repository {
+        id: Option<String>,
+        url: Option<String>,
+    }
+
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Labels {
         nodes: Option<Vec<Label>>,
@@ -990,6 +1380,27 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
         state: Option<String>,
     }
 
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Commits {
+        nodes: Option<Vec<CommitNode>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct CommitNode {
+        commit: Option<Commit>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Commit {
+        statusCheckRollup: Option<StatusCheckRollup>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct StatusCheckRollup {
+        state: Option<String>,
+    }
+
     #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct PageInfo {
@@ -1008,17 +1419,25 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
                     issueCount
                     nodes {{
                         ... on PullRequest {{
+                            id
                             title
                             url
                             author {{
                                 login
+                                ... on Node {{
+                                    id
+                                }}
+                            }}
+                            repository {{
+                                id
+                                url
                             }}
                             labels(first: 10) {{
                                 nodes {{
                                     name
                                 }}
                             }}
-                            reviews(first: 5, states: [APPROVED]) {{
+                            reviews(first: 20, states: [APPROVED]) {{
                                 nodes {{
                                     author {{
                                         login
@@ -1026,7 +1445,20 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
                                     state
                                 }}
                             }}
+                            createdAt
                             mergedAt
+                            additions
+                            deletions
+                            changedFiles
+                            commits(last: 1) {{
+                                nodes {{
+                                    commit {{
+                                        statusCheckRollup {{
+                                            state
+                                        }}
+                                    }}
+                                }}
+                            }}
                         }}
                     }}
                     pageInfo {{
@@ -1055,24 +1487,70 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
                 if let Some(nodes) = search.nodes {
                     for node in nodes {
                         let pull_id = node.url.clone().unwrap_or_default();
-                        let project_id = pull_id
-                            .clone()
-                            .rsplitn(3, '/')
-                            .nth(2)
-                            .unwrap_or("failed_to_get_project_id")
-                            .to_string();
+                        let project_id = node
+                            .repository
+                            .as_ref()
+                            .and_then(|r| r.url.clone())
+                            .unwrap_or_else(|| {
+                                pull_id
+                                    .clone()
+                                    .rsplitn(3, '/')
+                                    .nth(2)
+                                    .unwrap_or("failed_to_get_project_id")
+                                    .to_string()
+                            });
                         let pull_title = node.title.clone().unwrap_or_default();
                         let pull_author =
                             node.author.as_ref().and_then(|author| author.login.clone());
+                        let created_at = node.createdAt.clone().unwrap_or_default();
+                        let created_at = convert_datetime(&created_at).unwrap_or_default();
                         let merged_at = node.mergedAt.unwrap_or_default();
                         let merged_at = convert_datetime(&merged_at).unwrap_or_default();
 
+                        let mut approvers = node
+                            .reviews
+                            .as_ref()
+                            .and_then(|r| r.nodes.as_ref())
+                            .map_or(Vec::new(), |reviews| {
+                                reviews
+                                    .iter()
+                                    .filter_map(|r| r.author.as_ref().and_then(|a| a.login.clone()))
+                                    .collect::<Vec<String>>()
+                            });
+                        approvers.sort();
+                        approvers.dedup();
+                        let ci_status = node
+                            .commits
+                            .as_ref()
+                            .and_then(|c| c.nodes.as_ref())
+                            .and_then(|nodes| nodes.last())
+                            .and_then(|n| n.commit.as_ref())
+                            .and_then(|c| c.statusCheckRollup.as_ref())
+                            .and_then(|s| s.state.clone());
+                        let pull_labels = node
+                            .labels
+                            .as_ref()
+                            .and_then(|l| l.nodes.as_ref())
+                            .map_or(Vec::new(), |labels| {
+                                labels.iter().filter_map(|l| l.name.clone()).collect()
+                            });
+
                         all_pulls.push(OuterPull {
                             pull_id,
+                            pull_node_id: node.id.clone(),
                             pull_title,
                             pull_author,
+                            pull_author_node_id: node.author.as_ref().and_then(|a| a.id.clone()),
                             project_id,
+                            project_node_id: node.repository.as_ref().and_then(|r| r.id.clone()),
+                            created_at,
                             merged_at,
+                            additions: node.additions,
+                            deletions: node.deletions,
+                            changed_files: node.changedFiles,
+                            approvers: Some(approvers),
+                            ci_status,
+                            pull_labels,
                         });
                     }
 
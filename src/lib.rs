@@ -1,13 +1,28 @@
+pub mod bot_templates;
+pub mod budget_extract;
+pub mod budget_recommend;
 pub mod db_join;
 pub mod db_manipulate;
 pub mod db_populate;
 pub mod issue_bot;
+pub mod issue_classify;
 pub mod issue_tracker;
+pub mod llm_provider;
+pub mod llm_usage;
 pub mod llm_utils;
-pub mod llm_utils_together;
+pub mod pr_linking;
+pub mod pr_matcher;
+pub mod prompt_eval;
+pub mod prompts;
+pub mod reconcile;
+pub mod spam_score;
+pub mod stale_assignments;
+pub mod tag_taxonomy;
+pub mod text_prep;
 pub mod the_paced_runner;
 pub mod the_runner;
 pub mod vector_search;
+pub mod webhook_ingest;
 // pub mod issue_paced_tracker;
 use chrono::{NaiveDate, Timelike, Utc};
 use lazy_static::lazy_static;
@@ -17,6 +32,11 @@ pub static ISSUE_LABEL: &str = "hacktoberfest";
 pub static PR_LABEL: &str = "hacktoberfest-accepted";
 pub static START_DATE: &str = "2023-10-01";
 pub static END_DATE: &str = "2023-10-30";
+pub static CAMPAIGN: &str = "hacktoberfest-2023";
+pub static BOT_LOCALE: &str = "en";
+pub static FUNDED_LABEL: &str = "gosim-funded";
+pub static DECLINED_LABEL: &str = "gosim-declined";
+pub static RECONCILE_HOUR: u32 = 3;
 
 lazy_static! {
     pub static ref THIS_HOUR: String = {
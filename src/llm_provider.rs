@@ -0,0 +1,454 @@
+use crate::llm_usage::record_chat;
+use async_trait::async_trait;
+use openai_flows::{
+    chat::{ChatModel, ChatOptions},
+    OpenAIFlows,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
+    ClientBuilder,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatResponse {
+    pub choices: Vec<ChatChoice>,
+    pub model: String,
+    pub usage: Option<TokenUsage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatMessage {
+    pub content: Option<String>,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatChoice {
+    pub finish_reason: Option<String>,
+    pub index: u32,
+    pub message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub enum Role {
+    #[serde(rename = "system")]
+    System,
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "assistant")]
+    Assistant,
+    #[serde(rename = "tool")]
+    Tool,
+    #[serde(rename = "function")]
+    Function,
+}
+
+// What a completion is used for, each task can run on its own provider and model.
+// Keywords come with the summary, in the same call, unless the keywords task has a
+// provider or model of its own, see keywords_for_summary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LlmTask {
+    Summarization,
+    Keywords,
+    Classification,
+}
+
+impl LlmTask {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LlmTask::Summarization => "summarization",
+            LlmTask::Keywords => "keywords",
+            LlmTask::Classification => "classification",
+        }
+    }
+
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            LlmTask::Summarization => "LLM_SUMMARIZATION",
+            LlmTask::Keywords => "LLM_KEYWORDS",
+            LlmTask::Classification => "LLM_CLASSIFICATION",
+        }
+    }
+
+    // Whether LLM_<TASK>_PROVIDER or LLM_<TASK>_MODEL is set, not just the shared settings.
+    pub fn has_own_settings(&self) -> bool {
+        ["PROVIDER", "MODEL"].iter().any(|name| {
+            std::env::var(format!("{}_{}", self.env_prefix(), name))
+                .map_or(false, |v| !v.trim().is_empty())
+        })
+    }
+}
+
+#[async_trait(?Send)]
+pub trait LlmProvider {
+    fn name(&self) -> &str;
+    fn model(&self) -> &str;
+    async fn chat(
+        &self,
+        system_prompt: &str,
+        user_input: &str,
+        max_tokens: u16,
+    ) -> anyhow::Result<String>;
+
+    // Asks for a reply that is a single JSON object, for providers that can enforce it.
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        user_input: &str,
+        max_tokens: u16,
+    ) -> anyhow::Result<String> {
+        self.chat(system_prompt, user_input, max_tokens).await
+    }
+}
+
+// Any server speaking the OpenAI chat completions API: Together, Azure serverless
+// endpoints, or a llama.cpp / Ollama server on the local network.
+pub struct OpenAiCompatible {
+    pub name: String,
+    pub base_url: String, // up to and including "/v1"
+    pub api_key: Option<String>,
+    pub model: String,
+    pub temperature: f32,
+    pub task: LlmTask,
+}
+
+impl OpenAiCompatible {
+    pub fn together(task: LlmTask, model: Option<String>) -> Self {
+        OpenAiCompatible {
+            name: String::from("together"),
+            base_url: String::from("https://api.together.xyz/v1"),
+            api_key: std::env::var("TOGETHER_API_KEY").ok(),
+            model: model.unwrap_or(String::from("meta-llama/Llama-3-8b-chat-hf")),
+            temperature: 0.7,
+            task,
+        }
+    }
+
+    pub fn azure(task: LlmTask, model: Option<String>) -> Self {
+        OpenAiCompatible {
+            name: String::from("azure"),
+            base_url: std::env::var("AZURE_LLM_URL").unwrap_or(String::from(
+                "https://Meta-Llama-3-8B-Instruct-ttskb-serverless.eastus2.inference.ai.azure.com/v1",
+            )),
+            api_key: std::env::var("AZURE_API_TOKEN").ok(),
+            model: model.unwrap_or(String::from("Meta-Llama-3-8B-Instruct")),
+            temperature: 0.7,
+            task,
+        }
+    }
+
+    pub fn local(task: LlmTask, model: Option<String>) -> Self {
+        OpenAiCompatible {
+            name: String::from("local"),
+            base_url: std::env::var("LOCAL_LLM_URL")
+                .unwrap_or(String::from("http://localhost:8080/v1")),
+            api_key: std::env::var("LOCAL_LLM_API_KEY").ok(),
+            model: model.unwrap_or(String::from("llama3")),
+            temperature: 0.7,
+            task,
+        }
+    }
+
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        user_input: &str,
+        max_tokens: u16,
+        json_mode: bool,
+    ) -> anyhow::Result<String> {
+        let started = Instant::now();
+        let result = self
+            .request(system_prompt, user_input, max_tokens, json_mode)
+            .await;
+        let (reply, reported_tokens) = match &result {
+            Ok((reply, usage)) => (
+                Some(reply.as_str()),
+                usage
+                    .as_ref()
+                    .map(|u| (u.prompt_tokens, u.completion_tokens)),
+            ),
+            Err(_) => (None, None),
+        };
+        record_chat(
+            &self.name,
+            &self.model,
+            self.task.as_str(),
+            &[system_prompt, user_input],
+            reply,
+            reported_tokens,
+            started,
+        );
+
+        result.map(|(reply, _)| reply)
+    }
+
+    // A failed request is sent once more before giving up.
+    async fn request(
+        &self,
+        system_prompt: &str,
+        user_input: &str,
+        max_tokens: u16,
+        json_mode: bool,
+    ) -> anyhow::Result<(String, Option<TokenUsage>)> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(USER_AGENT, HeaderValue::from_static("MyClient/1.0.0"));
+        if let Some(api_key) = &self.api_key {
+            let bearer_token = format!("Bearer {}", api_key);
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&bearer_token)?);
+        }
+        let input_head = user_input.chars().take(150).collect::<String>();
+
+        let uri = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let mut request = serde_json::json!({
+            "temperature": self.temperature,
+            "max_tokens": max_tokens,
+            "model": self.model,
+            "messages": [
+                {"role": "system", "content": system_prompt},
+                {"role": "user", "content": user_input}
+            ],
+        });
+        if json_mode {
+            request["response_format"] = serde_json::json!({"type": "json_object"});
+        }
+        let body = serde_json::to_vec(&request)?;
+
+        let client = ClientBuilder::new().default_headers(headers).build()?;
+        let mut response = client.post(&uri).body(body.clone()).send().await?;
+        if !response.status().is_success() {
+            log::error!("{} chat error {:?}, retrying", self.name, response.status());
+            response = client.post(&uri).body(body).send().await?;
+        }
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "{} chat error {:?}",
+                self.name,
+                response.status()
+            ));
+        }
+
+        let response_body = response.text().await?;
+        let chat_response = serde_json::from_str::<ChatResponse>(&response_body)
+            .map_err(|_e| anyhow::anyhow!("error deserialize ChatResponse: {_e}"))?;
+        let usage = chat_response.usage;
+        let choice = chat_response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or(anyhow::anyhow!("{} returned no choices", self.name))?;
+        log::info!(
+            "input: {}, finish_reason: {}",
+            input_head,
+            choice
+                .finish_reason
+                .unwrap_or("no finish reason found".to_string())
+        );
+
+        Ok((choice.message.content.unwrap_or_default(), usage))
+    }
+}
+
+#[async_trait(?Send)]
+impl LlmProvider for OpenAiCompatible {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn chat(
+        &self,
+        system_prompt: &str,
+        user_input: &str,
+        max_tokens: u16,
+    ) -> anyhow::Result<String> {
+        self.complete(system_prompt, user_input, max_tokens, false)
+            .await
+    }
+
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        user_input: &str,
+        max_tokens: u16,
+    ) -> anyhow::Result<String> {
+        self.complete(system_prompt, user_input, max_tokens, true)
+            .await
+    }
+}
+
+// OpenAI through the flows.network integration, the key is configured on the platform.
+pub struct OpenAiFlowsProvider {
+    pub model: String,
+    pub task: LlmTask,
+}
+
+impl OpenAiFlowsProvider {
+    fn chat_model(&self) -> ChatModel {
+        match self.model.as_str() {
+            "gpt-4" => ChatModel::GPT4,
+            "gpt-4-32k" => ChatModel::GPT4_32K,
+            "gpt-4-1106-preview" => ChatModel::GPT4Turbo,
+            "gpt-3.5-turbo-16k" => ChatModel::GPT35Turbo16K,
+            _ => ChatModel::GPT35Turbo,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl LlmProvider for OpenAiFlowsProvider {
+    fn name(&self) -> &str {
+        "openai_flows"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn chat(
+        &self,
+        system_prompt: &str,
+        user_input: &str,
+        max_tokens: u16,
+    ) -> anyhow::Result<String> {
+        let mut openai = OpenAIFlows::new();
+        openai.set_retry_times(2);
+
+        let co = ChatOptions {
+            model: self.chat_model(),
+            restart: true,
+            system_prompt: Some(system_prompt),
+            max_tokens: Some(max_tokens),
+            ..Default::default()
+        };
+
+        // the integration does not report token counts, they are estimated
+        let started = Instant::now();
+        let result = openai.chat_completion("summarizer", user_input, &co).await;
+        record_chat(
+            self.name(),
+            &self.model,
+            self.task.as_str(),
+            &[system_prompt, user_input],
+            result.as_ref().ok().map(|r| r.choice.as_str()),
+            None,
+            started,
+        );
+
+        match result {
+            Ok(r) => Ok(r.choice),
+            Err(_e) => Err(anyhow::anyhow!("openai generation error, inner: {_e}")),
+        }
+    }
+}
+
+// Replies without any network call, for running the pipeline in tests and dry runs.
+// LLM_MOCK_REPLY sets the reply, otherwise it is a fixed JSON shape per task.
+pub struct MockProvider {
+    pub task: LlmTask,
+    pub reply: Option<String>,
+}
+
+#[async_trait(?Send)]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    async fn chat(
+        &self,
+        _system_prompt: &str,
+        user_input: &str,
+        _max_tokens: u16,
+    ) -> anyhow::Result<String> {
+        if let Some(reply) = &self.reply {
+            return Ok(reply.clone());
+        }
+
+        let head = user_input.chars().take(200).collect::<String>();
+        let reply = match self.task {
+            LlmTask::Summarization => serde_json::json!({ "summary": head, "keywords": [] }),
+            LlmTask::Keywords => serde_json::json!({ "keywords": [] }),
+            // spam verdicts, budgets, issue classification and tag merges all run as
+            // classification, each reads its own fields from this one reply
+            LlmTask::Classification => serde_json::json!({
+                "verdict": "genuine",
+                "budget": null,
+                "quote": null,
+                "difficulty": "intermediate",
+                "effort_hours": 1,
+                "skills": [],
+                "issue_type": "feature",
+                "same": false,
+            }),
+        };
+        Ok(reply.to_string())
+    }
+}
+
+// LLM_<TASK>_PROVIDER and LLM_<TASK>_MODEL pick the provider for one task, e.g.
+// LLM_CLASSIFICATION_PROVIDER=local; LLM_PROVIDER and LLM_MODEL apply to every task
+// without its own setting. Providers: together (default), azure, local, openai_flows, mock.
+pub fn provider_for(task: LlmTask) -> Box<dyn LlmProvider> {
+    let read = |name: &str| {
+        std::env::var(format!("{}_{}", task.env_prefix(), name))
+            .or_else(|_| std::env::var(format!("LLM_{}", name)))
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let model = read("MODEL");
+
+    match read("PROVIDER").as_deref() {
+        None | Some("together") => Box::new(OpenAiCompatible::together(task, model)),
+        Some("azure") => Box::new(OpenAiCompatible::azure(task, model)),
+        Some("local") => Box::new(OpenAiCompatible::local(task, model)),
+        Some("openai_flows") => Box::new(OpenAiFlowsProvider {
+            model: model.unwrap_or(String::from("gpt-3.5-turbo")),
+            task,
+        }),
+        Some("mock") => Box::new(MockProvider {
+            task,
+            reply: std::env::var("LLM_MOCK_REPLY").ok(),
+        }),
+        Some(other) => {
+            log::error!(
+                "Unknown llm provider {} for {}, using together",
+                other,
+                task.as_str()
+            );
+            Box::new(OpenAiCompatible::together(task, model))
+        }
+    }
+}
+
+pub async fn chat_for_task(
+    task: LlmTask,
+    system_prompt: &str,
+    user_input: &str,
+    max_tokens: u16,
+) -> anyhow::Result<String> {
+    let provider = provider_for(task);
+    log::info!(
+        "{} with {} / {}",
+        task.as_str(),
+        provider.name(),
+        provider.model()
+    );
+
+    provider.chat(system_prompt, user_input, max_tokens).await
+}
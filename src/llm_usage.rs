@@ -0,0 +1,342 @@
+use crate::text_prep::estimate_tokens;
+use crate::CAMPAIGN;
+use lazy_static::lazy_static;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+// the model openai_flows embeds with, it does not report one
+pub static EMBEDDING_MODEL: &str = "text-embedding-ada-002";
+
+// USD per million prompt and completion tokens, the first model name containing the key
+// wins. Models not listed are priced high on purpose so the spend limits still hold.
+static MODEL_PRICES: [(&str, f64, f64); 10] = [
+    ("text-embedding-ada-002", 0.1, 0.0),
+    ("gpt-4-1106", 10.0, 30.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4-32k", 60.0, 120.0),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-3.5-turbo-16k", 3.0, 4.0),
+    ("gpt-3.5", 0.5, 1.5),
+    ("llama-3-70b", 0.9, 0.9),
+    ("llama-3-8b", 0.2, 0.2),
+    ("llama3", 0.2, 0.2),
+];
+static UNKNOWN_MODEL_PRICE: (f64, f64) = (1.0, 2.0);
+// providers that run on our own hardware or not at all
+static FREE_PROVIDERS: [&str; 2] = ["local", "mock"];
+
+lazy_static! {
+    // calls are kept in memory and written in one batch by flush_llm_usage, so a run does
+    // not open a connection per completion
+    static ref PENDING_USAGE: Mutex<Vec<LlmUsage>> = Mutex::new(Vec::new());
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LlmUsage {
+    pub provider: String,
+    pub model: String,
+    pub task: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub tokens_estimated: bool, // the provider did not report token counts
+    pub latency_ms: u64,
+    pub estimated_cost: f64,
+    pub succeeded: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UsageRow {
+    pub day: String,
+    pub provider: String,
+    pub model: String,
+    pub task: String,
+    pub calls: i64,
+    pub failed_calls: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub avg_latency_ms: f64,
+    pub estimated_cost: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LlmUsageStats {
+    pub campaign: String,
+    pub spent_today: f64,
+    pub spent_campaign: f64,
+    pub daily_limit: Option<f64>,
+    pub campaign_limit: Option<f64>,
+    pub paused: Option<String>,
+    pub usage: Vec<UsageRow>,
+}
+
+pub fn estimate_cost(
+    provider: &str,
+    model: &str,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+) -> f64 {
+    if FREE_PROVIDERS.contains(&provider) {
+        return 0.0;
+    }
+    let model = model.to_lowercase();
+    let (prompt_price, completion_price) = MODEL_PRICES
+        .iter()
+        .find(|(key, _, _)| model.contains(key))
+        .map(|(_, p, c)| (*p, *c))
+        .unwrap_or(UNKNOWN_MODEL_PRICE);
+
+    (prompt_tokens as f64 * prompt_price + completion_tokens as f64 * completion_price)
+        / 1_000_000.0
+}
+
+pub fn record_usage(usage: LlmUsage) {
+    match PENDING_USAGE.lock() {
+        Ok(mut pending) => pending.push(usage),
+        Err(e) => log::error!("Error recording llm usage: {:?}", e),
+    }
+}
+
+// One chat completion of the system prompt and user input in `prompt`; `reply` is None
+// when it failed. Token counts the provider did not report are estimated from the texts.
+// Failed calls are recorded without cost.
+pub fn record_chat(
+    provider: &str,
+    model: &str,
+    task: &str,
+    prompt: &[&str],
+    reply: Option<&str>,
+    reported_tokens: Option<(u32, u32)>,
+    started: Instant,
+) {
+    let (prompt_tokens, completion_tokens) = reported_tokens.unwrap_or_else(|| {
+        (
+            prompt
+                .iter()
+                .map(|t| estimate_tokens(t, model))
+                .sum::<usize>() as u32,
+            reply.map_or(0, |r| estimate_tokens(r, model)) as u32,
+        )
+    });
+    let succeeded = reply.is_some();
+
+    record_usage(LlmUsage {
+        provider: provider.to_string(),
+        model: model.to_string(),
+        task: task.to_string(),
+        prompt_tokens,
+        completion_tokens,
+        tokens_estimated: reported_tokens.is_none(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        estimated_cost: match succeeded {
+            true => estimate_cost(provider, model, prompt_tokens, completion_tokens),
+            false => 0.0,
+        },
+        succeeded,
+    });
+}
+
+// One embeddings request for `texts`, through openai_flows.
+pub fn record_embedding(texts: &[&str], succeeded: bool, started: Instant) {
+    let prompt_tokens = texts
+        .iter()
+        .map(|t| estimate_tokens(t, EMBEDDING_MODEL))
+        .sum::<usize>() as u32;
+
+    record_usage(LlmUsage {
+        provider: String::from("openai_flows"),
+        model: EMBEDDING_MODEL.to_string(),
+        task: String::from("embedding"),
+        prompt_tokens,
+        completion_tokens: 0,
+        tokens_estimated: true,
+        latency_ms: started.elapsed().as_millis() as u64,
+        estimated_cost: match succeeded {
+            true => estimate_cost("openai_flows", EMBEDDING_MODEL, prompt_tokens, 0),
+            false => 0.0,
+        },
+        succeeded,
+    });
+}
+
+fn pending_cost() -> f64 {
+    PENDING_USAGE
+        .lock()
+        .map(|pending| pending.iter().map(|u| u.estimated_cost).sum())
+        .unwrap_or(0.0)
+}
+
+// Writes the recorded calls to llm_usage. They stay in memory for the next flush when the
+// insert fails.
+pub async fn flush_llm_usage(pool: &Pool) -> anyhow::Result<()> {
+    let pending: Vec<LlmUsage> = match PENDING_USAGE.lock() {
+        Ok(mut pending) => std::mem::take(&mut *pending),
+        Err(e) => return Err(anyhow::anyhow!("llm usage buffer poisoned: {:?}", e)),
+    };
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let mut conn = pool.get_conn().await?;
+
+    let query = r"INSERT INTO llm_usage (provider, model, task, campaign, prompt_tokens, completion_tokens, tokens_estimated, latency_ms, estimated_cost, succeeded)
+                  VALUES (:provider, :model, :task, :campaign, :prompt_tokens, :completion_tokens, :tokens_estimated, :latency_ms, :estimated_cost, :succeeded)";
+
+    if let Err(e) = conn
+        .exec_batch(
+            query,
+            pending.iter().map(|u| {
+                params! {
+                    "provider" => &u.provider,
+                    "model" => &u.model,
+                    "task" => &u.task,
+                    "campaign" => CAMPAIGN,
+                    "prompt_tokens" => u.prompt_tokens,
+                    "completion_tokens" => u.completion_tokens,
+                    "tokens_estimated" => u.tokens_estimated,
+                    "latency_ms" => u.latency_ms,
+                    "estimated_cost" => u.estimated_cost,
+                    "succeeded" => u.succeeded,
+                }
+            }),
+        )
+        .await
+    {
+        log::error!("Error saving llm usage: {:?}", e);
+        if let Ok(mut buffer) = PENDING_USAGE.lock() {
+            buffer.extend(pending);
+        }
+        return Err(e.into());
+    }
+    log::info!("Saved llm usage, calls: {}", pending.len());
+
+    Ok(())
+}
+
+fn spend_limit(name: &str) -> Option<f64> {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| *v > 0.0)
+}
+
+// LLM_DAILY_SPEND_LIMIT and LLM_CAMPAIGN_SPEND_LIMIT, in USD; unset means no limit.
+pub fn spend_limits() -> (Option<f64>, Option<f64>) {
+    (
+        spend_limit("LLM_DAILY_SPEND_LIMIT"),
+        spend_limit("LLM_CAMPAIGN_SPEND_LIMIT"),
+    )
+}
+
+// Estimated spend today and over the campaign, including calls not flushed yet.
+pub async fn get_spend(pool: &Pool) -> Result<(f64, f64)> {
+    let mut conn = pool.get_conn().await?;
+
+    let spend: Option<(Option<f64>, Option<f64>)> = conn
+        .exec_first(
+            r"SELECT
+                CAST(SUM(CASE WHEN date_created >= CURDATE() THEN estimated_cost ELSE 0 END) AS DOUBLE),
+                CAST(SUM(estimated_cost) AS DOUBLE)
+            FROM llm_usage WHERE campaign = :campaign",
+            params! {
+                "campaign" => CAMPAIGN,
+            },
+        )
+        .await?;
+    let (today, campaign) = spend.unwrap_or((None, None));
+    let pending = pending_cost();
+
+    Ok((
+        today.unwrap_or(0.0) + pending,
+        campaign.unwrap_or(0.0) + pending,
+    ))
+}
+
+// Why summarization and indexing should wait, if a spend limit is reached.
+pub async fn spend_pause_reason(pool: &Pool) -> anyhow::Result<Option<String>> {
+    let (daily_limit, campaign_limit) = spend_limits();
+    if daily_limit.is_none() && campaign_limit.is_none() {
+        return Ok(None);
+    }
+    let (spent_today, spent_campaign) = get_spend(pool).await?;
+
+    if let Some(limit) = daily_limit.filter(|l| spent_today >= *l) {
+        return Ok(Some(format!(
+            "daily llm spend ${:.2} reached the ${:.2} limit",
+            spent_today, limit
+        )));
+    }
+    if let Some(limit) = campaign_limit.filter(|l| spent_campaign >= *l) {
+        return Ok(Some(format!(
+            "{} llm spend ${:.2} reached the ${:.2} limit",
+            CAMPAIGN, spent_campaign, limit
+        )));
+    }
+
+    Ok(None)
+}
+
+// Usage per day, provider, model and task over the last `days` days, newest first.
+pub async fn get_llm_usage_stats(pool: &Pool, days: u32) -> anyhow::Result<LlmUsageStats> {
+    let mut conn = pool.get_conn().await?;
+
+    let usage: Vec<UsageRow> = conn
+        .exec_map(
+            r"SELECT DATE_FORMAT(date_created, '%Y-%m-%d') AS day, provider, model, task,
+                COUNT(*) AS calls,
+                CAST(SUM(succeeded = 0) AS SIGNED) AS failed_calls,
+                CAST(SUM(prompt_tokens) AS SIGNED) AS prompt_tokens,
+                CAST(SUM(completion_tokens) AS SIGNED) AS completion_tokens,
+                CAST(AVG(latency_ms) AS DOUBLE) AS avg_latency_ms,
+                CAST(SUM(estimated_cost) AS DOUBLE) AS estimated_cost
+            FROM llm_usage
+            WHERE campaign = :campaign
+            AND date_created >= DATE_SUB(CURDATE(), INTERVAL :days DAY)
+            GROUP BY day, provider, model, task
+            ORDER BY day DESC, estimated_cost DESC",
+            params! {
+                "campaign" => CAMPAIGN,
+                "days" => days.saturating_sub(1),
+            },
+            |(
+                day,
+                provider,
+                model,
+                task,
+                calls,
+                failed_calls,
+                prompt_tokens,
+                completion_tokens,
+                avg_latency_ms,
+                estimated_cost,
+            ): (String, String, String, String, i64, i64, i64, i64, f64, f64)| {
+                UsageRow {
+                    day,
+                    provider,
+                    model,
+                    task,
+                    calls,
+                    failed_calls,
+                    prompt_tokens,
+                    completion_tokens,
+                    avg_latency_ms,
+                    estimated_cost,
+                }
+            },
+        )
+        .await?;
+
+    let (daily_limit, campaign_limit) = spend_limits();
+    let (spent_today, spent_campaign) = get_spend(pool).await?;
+
+    Ok(LlmUsageStats {
+        campaign: CAMPAIGN.to_string(),
+        spent_today,
+        spent_campaign,
+        daily_limit,
+        campaign_limit,
+        paused: spend_pause_reason(pool).await?,
+        usage,
+    })
+}
@@ -1,8 +1,12 @@
+use crate::llm_provider::{provider_for, LlmProvider, LlmTask};
+use crate::prompts::{KEYWORDS_PROMPT, README_CHUNK_PROMPT};
+use crate::text_prep::*;
 use openai_flows::{
     chat::{ChatModel, ChatOptions},
     OpenAIFlows,
 };
-use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 pub async fn chain_of_chat(
     sys_prompt_1: &str,
@@ -55,61 +59,175 @@ pub async fn chain_of_chat(
     }
 }
 
-pub async fn chat_inner_async(
+// The reply every summary prompt asks for; `keywords` must be a JSON array of strings.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SummaryOutput {
+    pub summary: String,
+    pub keywords: Vec<String>,
+}
+
+// A summary reply that could not be parsed even after the repair attempt.
+#[derive(Clone, Debug, Default)]
+pub struct SummaryFailure {
+    pub reply: String,
+    pub repaired_reply: String,
+    pub error: String,
+}
+
+// The JSON object in a model reply, from its first "{" to its last "}"; models like to wrap
+// it in ```json fences or a sentence of introduction.
+pub fn extract_json_object(reply: &str) -> Option<&str> {
+    match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => Some(&reply[start..=end]),
+        _ => None,
+    }
+}
+
+// An empty summary is an error, blank keywords are dropped.
+pub fn parse_summary_output(reply: &str) -> anyhow::Result<SummaryOutput> {
+    let json_str =
+        extract_json_object(reply).ok_or_else(|| anyhow::anyhow!("no JSON object in the reply"))?;
+    let mut output: SummaryOutput = serde_json::from_str(json_str)?;
+
+    output.summary = output.summary.trim().to_string();
+    if output.summary.is_empty() {
+        return Err(anyhow::anyhow!("summary is empty"));
+    }
+    output.keywords = clean_keywords(output.keywords);
+
+    Ok(output)
+}
+
+fn clean_keywords(keywords: Vec<String>) -> Vec<String> {
+    let mut keywords: Vec<String> = keywords
+        .iter()
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .collect();
+    keywords.dedup();
+    keywords
+}
+
+pub fn parse_keywords_output(reply: &str) -> anyhow::Result<Vec<String>> {
+    #[derive(Deserialize)]
+    struct KeywordsOutput {
+        keywords: Vec<String>,
+    }
+
+    let json_str =
+        extract_json_object(reply).ok_or_else(|| anyhow::anyhow!("no JSON object in the reply"))?;
+    let output: KeywordsOutput = serde_json::from_str(json_str)?;
+
+    Ok(clean_keywords(output.keywords))
+}
+
+// The keywords to store with a summary: the ones that came with it, or, when the keywords
+// task has a provider or model of its own, the ones that provider gives for the summary.
+// The summary's own keywords are kept if that call fails.
+pub async fn keywords_for_summary(summary: &str, keywords: Vec<String>) -> Vec<String> {
+    if !LlmTask::Keywords.has_own_settings() {
+        return keywords;
+    }
+    let provider = provider_for(LlmTask::Keywords);
+    let reply = provider.chat_json(KEYWORDS_PROMPT, summary, 60).await;
+
+    match reply.and_then(|r| parse_keywords_output(&r)) {
+        Ok(own) if !own.is_empty() => own,
+        Ok(_) => keywords,
+        Err(e) => {
+            log::error!("Error getting keywords, keeping the summary's: {:?}", e);
+            keywords
+        }
+    }
+}
+
+// Hex sha256 of the texts a summary prompt is built from, stored next to the summary
+// so unchanged items are not sent to the model again.
+pub fn summary_content_hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        // keeps ("ab", "c") and ("a", "bc") apart
+        hasher.update([0u8]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+static SUMMARY_REPAIR_PROMPT: &str = r#"
+    You fix replies that were supposed to be JSON. Rewrite the reply you are given into exactly this shape, keeping its content:
+    { "summary": "the summary paragraph as one string", "keywords": ["keyword", "keyword"] }
+    Reply with the RFC8259-compliant JSON object only."#;
+
+// Asks for a JSON reply and, when it does not parse, hands the reply back to the model
+// once with the parse error to fix it. Transport errors are returned as errors, replies
+// that never parse come back as a SummaryFailure for the caller to record.
+pub async fn chat_summary(
+    provider: &dyn LlmProvider,
     system_prompt: &str,
     user_input: &str,
-    max_token: u16,
-) -> anyhow::Result<String> {
-    let mut openai = OpenAIFlows::new();
-    openai.set_retry_times(2);
-
-    let co = ChatOptions {
-        model: ChatModel::GPT35Turbo,
-        restart: true,
-        system_prompt: Some(system_prompt),
-        max_tokens: Some(max_token),
-        ..Default::default()
+    max_tokens: u16,
+) -> anyhow::Result<std::result::Result<SummaryOutput, SummaryFailure>> {
+    let reply = provider
+        .chat_json(system_prompt, user_input, max_tokens)
+        .await?;
+    let error = match parse_summary_output(&reply) {
+        Ok(output) => return Ok(Ok(output)),
+        Err(e) => e,
     };
+    log::error!("Unparsable summary reply, repairing: {:?}", error);
 
-    let head = user_input.chars().take(100).collect::<String>();
-    log::info!("inside chat_inner: {:?}", head);
+    let repair_input = format!(
+        "The reply failed to parse with: {}\nHere is the reply:\n{}",
+        error, reply
+    );
+    let repaired = provider
+        .chat_json(SUMMARY_REPAIR_PROMPT, &repair_input, max_tokens)
+        .await?;
 
-    match openai.chat_completion("summarizer", user_input, &co).await {
-        Ok(r) => {
-            log::info!("one step summarizer: {:?}", r.choice.clone());
-            return Ok(r.choice);
-        }
-        Err(_e) => {
-            return Err(anyhow::anyhow!("openai generation error, inner: {_e}"));
-        }
+    match parse_summary_output(&repaired) {
+        Ok(output) => Ok(Ok(output)),
+        Err(e) => Ok(Err(SummaryFailure {
+            reply,
+            repaired_reply: repaired,
+            error: e.to_string(),
+        })),
     }
 }
 
-pub fn parse_summary_and_keywords(input: &str) -> (String, Vec<String>) {
-    let summary_regex = Regex::new(r#""summary":\s*"([^"]*)""#).unwrap();
-    let keywords_regex = Regex::new(r#""keywords":\s*\[?([^}\]]*)\]?"#).unwrap();
-
-    let summary = summary_regex
-        .captures(input)
-        .and_then(|cap| cap.get(1))
-        .map_or(String::new(), |m| m.as_str().to_string());
-
-    let keywords = keywords_regex
-        .captures(input)
-        .and_then(|cap| cap.get(1))
-        .map_or(Vec::new(), |m| {
-            m.as_str()
-                .split(',')
-                .map(|s| {
-                    s.trim()
-                        .trim_matches(|c: char| c == '"' || c == '}' || c == '\n')
-                        .to_string()
-                })
-                .filter(|s| !s.is_empty()) // Filter out empty strings after splitting.
-                .collect()
-        });
-
-    (summary, keywords)
+// Notes on each part of a README too long for one request, in place of the README for
+// the final summary. Parts past MAX_README_CHUNKS are left out.
+pub async fn condense_long_readme(
+    provider: &dyn LlmProvider,
+    readme: &str,
+) -> anyhow::Result<String> {
+    let chunks = split_into_chunks(readme, README_CHUNK_TOKENS, provider.model());
+    let total = chunks.len();
+    if total > MAX_README_CHUNKS {
+        log::info!(
+            "README has {} parts, condensing the first {}",
+            total,
+            MAX_README_CHUNKS
+        );
+    }
+
+    let mut notes = Vec::new();
+    for (n, chunk) in chunks.iter().take(MAX_README_CHUNKS).enumerate() {
+        let input = format!("Part {} of {} of the README:\n{}", n + 1, total, chunk);
+        match provider.chat(README_CHUNK_PROMPT, &input, 200).await {
+            Ok(note) if !note.trim().is_empty() => notes.push(note.trim().to_string()),
+            Ok(_) => {}
+            Err(e) => log::error!("Error condensing part {} of README: {:?}", n + 1, e),
+        }
+    }
+    if notes.is_empty() {
+        return Err(anyhow::anyhow!("no part of the README could be condensed"));
+    }
+
+    Ok(notes.join("\n\n"))
 }
 
 pub fn extract_summary_from_answer(input: &str) -> String {
@@ -128,3 +246,61 @@ pub fn extract_summary_from_answer(input: &str) -> String {
             .unwrap_or_else(|| "No summary located".to_string()) // Provide a default message
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_object_is_found_in_fences_and_prose() {
+        assert_eq!(
+            extract_json_object("```json\n{\"summary\": \"a\"}\n```"),
+            Some("{\"summary\": \"a\"}")
+        );
+        assert_eq!(
+            extract_json_object("Here is the JSON you asked for: {\"same\": true}. Hope it helps!"),
+            Some("{\"same\": true}")
+        );
+        // nested objects are kept whole
+        assert_eq!(
+            extract_json_object("{\"a\": {\"b\": 1}}"),
+            Some("{\"a\": {\"b\": 1}}")
+        );
+    }
+
+    #[test]
+    fn no_json_object() {
+        assert_eq!(extract_json_object(""), None);
+        assert_eq!(extract_json_object("no braces at all"), None);
+        assert_eq!(extract_json_object("} backwards {"), None);
+        assert_eq!(extract_json_object("{ never closed"), None);
+    }
+
+    #[test]
+    fn summary_reply_is_cleaned() {
+        let output = parse_summary_output(
+            "Sure!\n```json\n{ \"summary\": \"  Adds a dark mode. \", \"keywords\": [\" web ui \", \"\", \"web ui\", \"css\"] }\n```",
+        )
+        .unwrap();
+        assert_eq!(output.summary, "Adds a dark mode.");
+        assert_eq!(output.keywords, vec!["web ui", "css"]);
+    }
+
+    #[test]
+    fn bad_summary_replies_are_errors() {
+        assert!(parse_summary_output("{ \"summary\": \"  \", \"keywords\": [\"rust\"] }").is_err());
+        assert!(parse_summary_output("{ \"keywords\": [\"rust\"] }").is_err());
+        assert!(parse_summary_output("{ \"summary\": \"a\", \"keywords\": \"rust\" }").is_err());
+        assert!(parse_summary_output("I could not summarize this issue.").is_err());
+    }
+
+    #[test]
+    fn keywords_reply() {
+        assert_eq!(
+            parse_keywords_output("```json\n{\"keywords\": [\"docs \", \"ci pipeline\"]}\n```")
+                .unwrap(),
+            vec!["docs", "ci pipeline"]
+        );
+        assert!(parse_keywords_output("docs, ci pipeline").is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum LinkMethod {
+    ClosingEvent,
+    ConnectedEvent,
+    ClosingReference,
+    BodyReference,
+    CrossReference,
+    AssigneePull,
+    ReviewedMatch,
+}
+
+impl LinkMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkMethod::ClosingEvent => "closing_event",
+            LinkMethod::ConnectedEvent => "connected_event",
+            LinkMethod::ClosingReference => "closing_reference",
+            LinkMethod::BodyReference => "body_reference",
+            LinkMethod::CrossReference => "cross_reference",
+            LinkMethod::AssigneePull => "assignee_pull",
+            LinkMethod::ReviewedMatch => "reviewed_match",
+        }
+    }
+
+    // How sure a link found this way is: GitHub closing the issue from the PR, or an admin
+    // accepting a proposed match, is certain; a PR by the assignee in the same repo is a guess.
+    pub fn confidence(&self) -> f32 {
+        match self {
+            LinkMethod::ClosingEvent | LinkMethod::ReviewedMatch => 1.0,
+            LinkMethod::ConnectedEvent => 0.9,
+            LinkMethod::ClosingReference => 0.9,
+            LinkMethod::BodyReference => 0.8,
+            LinkMethod::AssigneePull => 0.6,
+            LinkMethod::CrossReference => 0.5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LinkedPr {
+    pub pull_id: String, // url of pull_request
+    pub pull_node_id: Option<String>,
+    pub method: LinkMethod,
+    pub confidence: f32,
+}
+
+// Timeline items of an issue that can point at the PR which resolved it, the GraphQL
+// fragment below and these structs go together.
+pub const LINK_TIMELINE_ITEMS: &str = r#"
+    timelineItems(last: 50, itemTypes: [CLOSED_EVENT, CONNECTED_EVENT, CROSS_REFERENCED_EVENT]) {
+        nodes {
+            __typename
+            ... on ClosedEvent {
+                closer {
+                    ... on PullRequest { id url body merged }
+                }
+            }
+            ... on ConnectedEvent {
+                subject {
+                    ... on PullRequest { id url body merged }
+                }
+            }
+            ... on CrossReferencedEvent {
+                willCloseTarget
+                source {
+                    ... on PullRequest { id url body merged }
+                }
+            }
+        }
+    }"#;
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct LinkTimelineItems {
+    pub nodes: Option<Vec<LinkTimelineEvent>>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct LinkTimelineEvent {
+    pub __typename: Option<String>,
+    pub closer: Option<PullRef>,
+    pub subject: Option<PullRef>,
+    pub source: Option<PullRef>,
+    pub willCloseTarget: Option<bool>,
+}
+
+// Empty when the closer, subject or source is a commit or an issue.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct PullRef {
+    pub id: Option<String>,
+    pub url: Option<String>,
+    pub body: Option<String>,
+    pub merged: Option<bool>,
+}
+
+lazy_static! {
+    // "fixes #12", "Closes owner/repo#12", "resolved: https://github.com/owner/repo/issues/12"
+    static ref CLOSING_KEYWORD: Regex = Regex::new(
+        r"(?i)\b(?:close[sd]?|fix(?:e[sd])?|resolve[sd]?)\s*:?\s+(?:https://github\.com/([\w.-]+)/([\w.-]+)/issues/|([\w.-]+)/([\w.-]+)#|#)(\d+)\b"
+    )
+    .unwrap();
+}
+
+// owner, repo and number of an issue or pull request url
+fn split_github_url(url: &str) -> Option<(String, String, String)> {
+    let parts: Vec<&str> = url.trim_end_matches('/').rsplitn(5, '/').collect();
+    if parts.len() < 4 {
+        return None;
+    }
+    Some((
+        parts[3].to_lowercase(),
+        parts[2].to_lowercase(),
+        parts[0].to_string(),
+    ))
+}
+
+// Whether the PR description uses a closing keyword on the issue; a bare "#N" refers to
+// the PR's own repository.
+pub fn body_closes_issue(body: &str, pull_id: &str, issue_id: &str) -> bool {
+    let (issue_owner, issue_repo, issue_number) = match split_github_url(issue_id) {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let (pull_owner, pull_repo, _) = match split_github_url(pull_id) {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    CLOSING_KEYWORD.captures_iter(body).any(|cap| {
+        let owner = cap.get(1).or(cap.get(3)).map(|m| m.as_str().to_lowercase());
+        let repo = cap.get(2).or(cap.get(4)).map(|m| m.as_str().to_lowercase());
+        let owner = owner.unwrap_or_else(|| pull_owner.clone());
+        let repo = repo.unwrap_or_else(|| pull_repo.clone());
+
+        owner == issue_owner && repo == issue_repo && cap[5] == issue_number
+    })
+}
+
+fn candidate(pull: &PullRef, method: LinkMethod) -> Option<LinkedPr> {
+    Some(LinkedPr {
+        pull_id: pull.url.clone()?,
+        pull_node_id: pull.id.clone(),
+        confidence: method.confidence(),
+        method,
+    })
+}
+
+// The most certain PR found in the issue's timeline. Only the closing event may name an
+// unmerged PR, any other mention counts once the PR is merged.
+pub fn linked_pr_from_timeline(issue_id: &str, events: &[LinkTimelineEvent]) -> Option<LinkedPr> {
+    let mut candidates = Vec::new();
+
+    for event in events {
+        match event.__typename.as_deref() {
+            Some("ClosedEvent") => {
+                if let Some(pull) = &event.closer {
+                    candidates.extend(candidate(pull, LinkMethod::ClosingEvent));
+                }
+            }
+            Some("ConnectedEvent") => {
+                if let Some(pull) = event.subject.as_ref().filter(|p| p.merged == Some(true)) {
+                    candidates.extend(candidate(pull, LinkMethod::ConnectedEvent));
+                }
+            }
+            Some("CrossReferencedEvent") => {
+                let pull = match event.source.as_ref().filter(|p| p.merged == Some(true)) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let method = if event.willCloseTarget == Some(true) {
+                    LinkMethod::ClosingReference
+                } else if body_closes_issue(
+                    pull.body.as_deref().unwrap_or_default(),
+                    pull.url.as_deref().unwrap_or_default(),
+                    issue_id,
+                ) {
+                    LinkMethod::BodyReference
+                } else {
+                    LinkMethod::CrossReference
+                };
+                candidates.extend(candidate(pull, method));
+            }
+            _ => {}
+        }
+    }
+
+    // later events win a tie, the timeline is oldest first
+    candidates
+        .into_iter()
+        .rev()
+        .fold(None, |best: Option<LinkedPr>, c| match best {
+            Some(b) if b.confidence >= c.confidence => Some(b),
+            _ => Some(c),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISSUE: &str = "https://github.com/owner/repo/issues/12";
+    const PULL: &str = "https://github.com/owner/repo/pull/40";
+
+    fn pull(url: &str, body: &str, merged: bool) -> Option<PullRef> {
+        Some(PullRef {
+            id: Some(format!("PR_{}", url.rsplit('/').next().unwrap())),
+            url: Some(url.to_string()),
+            body: Some(body.to_string()),
+            merged: Some(merged),
+        })
+    }
+
+    fn event(typename: &str, pr: Option<PullRef>, will_close: bool) -> LinkTimelineEvent {
+        let mut event = LinkTimelineEvent {
+            __typename: Some(typename.to_string()),
+            willCloseTarget: Some(will_close),
+            ..Default::default()
+        };
+        match typename {
+            "ClosedEvent" => event.closer = pr,
+            "ConnectedEvent" => event.subject = pr,
+            _ => event.source = pr,
+        }
+        event
+    }
+
+    #[test]
+    fn closing_keywords_on_the_issue() {
+        assert!(body_closes_issue("Fixes #12", PULL, ISSUE));
+        assert!(body_closes_issue("this closes: #12.", PULL, ISSUE));
+        assert!(body_closes_issue("Resolved #12", PULL, ISSUE));
+        assert!(body_closes_issue("closes Owner/Repo#12", PULL, ISSUE));
+        assert!(body_closes_issue(
+            "fix https://github.com/owner/repo/issues/12",
+            PULL,
+            ISSUE
+        ));
+    }
+
+    #[test]
+    fn other_issues_do_not_match() {
+        assert!(!body_closes_issue("fixes #123", PULL, ISSUE));
+        assert!(!body_closes_issue("fixes #1", PULL, ISSUE));
+        assert!(!body_closes_issue("closes other/repo#12", PULL, ISSUE));
+        assert!(!body_closes_issue(
+            "fixes https://github.com/owner/repo/issues/120",
+            PULL,
+            ISSUE
+        ));
+        // a bare number refers to the PR's own repository
+        assert!(!body_closes_issue(
+            "fixes #12",
+            "https://github.com/fork/repo/pull/3",
+            ISSUE
+        ));
+    }
+
+    #[test]
+    fn keyword_must_be_a_whole_word() {
+        assert!(!body_closes_issue("prefixes #12", PULL, ISSUE));
+        assert!(!body_closes_issue("unresolved #12", PULL, ISSUE));
+        assert!(!body_closes_issue("see #12", PULL, ISSUE));
+    }
+
+    #[test]
+    fn closing_event_beats_other_mentions() {
+        let events = vec![
+            event(
+                "ClosedEvent",
+                pull("https://github.com/owner/repo/pull/1", "", false),
+                false,
+            ),
+            event(
+                "CrossReferencedEvent",
+                pull("https://github.com/owner/repo/pull/2", "", true),
+                true,
+            ),
+        ];
+        let linked = linked_pr_from_timeline(ISSUE, &events).unwrap();
+        assert_eq!(linked.pull_id, "https://github.com/owner/repo/pull/1");
+        assert_eq!(linked.method, LinkMethod::ClosingEvent);
+        assert_eq!(linked.confidence, 1.0);
+    }
+
+    #[test]
+    fn cross_reference_method_follows_the_pr() {
+        let method = |pr: Option<PullRef>, will_close: bool| {
+            linked_pr_from_timeline(ISSUE, &[event("CrossReferencedEvent", pr, will_close)])
+                .map(|l| l.method)
+        };
+
+        assert_eq!(
+            method(pull(PULL, "", true), true),
+            Some(LinkMethod::ClosingReference)
+        );
+        assert_eq!(
+            method(pull(PULL, "fixes #12", true), false),
+            Some(LinkMethod::BodyReference)
+        );
+        assert_eq!(
+            method(pull(PULL, "related to #12", true), false),
+            Some(LinkMethod::CrossReference)
+        );
+        // only the closing event may name an unmerged PR
+        assert_eq!(method(pull(PULL, "fixes #12", false), true), None);
+    }
+
+    #[test]
+    fn higher_confidence_wins_and_later_event_breaks_a_tie() {
+        let body_ref = event(
+            "CrossReferencedEvent",
+            pull("https://github.com/owner/repo/pull/1", "fixes #12", true),
+            false,
+        );
+        let connected = event(
+            "ConnectedEvent",
+            pull("https://github.com/owner/repo/pull/2", "", true),
+            false,
+        );
+        let closing_ref = event(
+            "CrossReferencedEvent",
+            pull("https://github.com/owner/repo/pull/3", "", true),
+            true,
+        );
+
+        let linked =
+            linked_pr_from_timeline(ISSUE, &[connected.clone(), body_ref.clone()]).unwrap();
+        assert_eq!(linked.method, LinkMethod::ConnectedEvent);
+
+        // connected event and closing reference are both 0.9
+        let linked = linked_pr_from_timeline(
+            ISSUE,
+            &[connected.clone(), closing_ref.clone(), body_ref.clone()],
+        )
+        .unwrap();
+        assert_eq!(linked.method, LinkMethod::ClosingReference);
+        let linked = linked_pr_from_timeline(ISSUE, &[closing_ref, connected, body_ref]).unwrap();
+        assert_eq!(linked.method, LinkMethod::ConnectedEvent);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::llm_usage::spend_pause_reason;
+use crate::pr_linking::LinkMethod;
+use crate::vector_search::*;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+
+// candidates below this are not worth a reviewer's time
+pub static MIN_MATCH_SCORE: f32 = 0.4;
+pub static MAX_CANDIDATES_PER_PULL: usize = 3;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MatchSignals {
+    pub same_repo: bool,
+    pub author_assigned: bool,
+    pub days_after_assignment: Option<i64>,
+    pub title_similarity: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MatchCandidate {
+    pub candidate_id: u32,
+    pub pull_id: String,
+    pub pull_title: String,
+    pub pull_author: Option<String>,
+    pub issue_id: String,
+    pub issue_title: String,
+    pub score: f32,
+    pub signals: Option<MatchSignals>,
+    pub status: String,
+}
+
+// A review that can't be applied because the candidate was already reviewed or the issue
+// got linked to another PR in the meantime.
+#[derive(Debug)]
+pub struct MatchConflict(pub String);
+
+impl std::fmt::Display for MatchConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MatchConflict {}
+
+// Weighted sum of the signals, 0 to 1. A PR merged long before the issue was assigned,
+// or more than a month after, gets nothing for timing.
+pub fn score_candidate(signals: &MatchSignals) -> f32 {
+    let mut score = 0.0;
+    if signals.same_repo {
+        score += 0.35;
+    }
+    if signals.author_assigned {
+        score += 0.3;
+    }
+    if let Some(days) = signals.days_after_assignment {
+        if days >= -1 {
+            score += 0.15 * (1.0 - days.max(0) as f32 / 30.0).max(0.0);
+        }
+    }
+    if let Some(similarity) = signals.title_similarity {
+        // unrelated short titles already score around 0.7 with the embedding model
+        score += 0.2 * ((similarity - 0.7) / 0.3).clamp(0.0, 1.0);
+    }
+    score
+}
+
+// Proposes issues for merged PRs that no issue links to, each PR is looked at once it was
+// matched with its title embeddings.
+// Waits while a spend limit is reached, so PRs aren't matched without their title embeddings.
+pub async fn match_orphan_pulls(pool: &Pool) -> anyhow::Result<()> {
+    if let Some(reason) = spend_pause_reason(pool).await? {
+        log::info!("Not matching orphan pull requests, {}", reason);
+        return Ok(());
+    }
+    let mut conn = pool.get_conn().await?;
+
+    let orphans: Vec<(String, String, Option<String>, String)> = conn
+        .query_map(
+            r"SELECT pr.pull_id, pr.pull_title, pr.pull_author, pr.project_id
+            FROM pull_requests pr
+            WHERE pr.issue_id IS NULL
+            AND pr.date_match_checked IS NULL
+            AND NOT EXISTS (SELECT 1 FROM issues_master im WHERE im.issue_linked_pr = pr.pull_id)
+            LIMIT 20",
+            |(pull_id, pull_title, pull_author, project_id): (
+                String,
+                String,
+                Option<String>,
+                String,
+            )| (pull_id, pull_title, pull_author, project_id),
+        )
+        .await?;
+    log::info!("Orphan pull requests to match, count: {:?}", orphans.len());
+
+    for (pull_id, pull_title, pull_author, project_id) in orphans {
+        let issues: Vec<(String, String, bool, bool, Option<i64>)> = conn
+            .exec_map(
+                r"SELECT im.issue_id, im.issue_title,
+                    im.project_id = :project_id,
+                    COALESCE(JSON_CONTAINS(im.issue_assignees, JSON_QUOTE(:pull_author)), 0),
+                    TIMESTAMPDIFF(DAY, im.date_issue_assigned, pr.date_merged)
+                FROM issues_master im
+                JOIN pull_requests pr ON pr.pull_id = :pull_id
+                WHERE im.issue_linked_pr IS NULL
+                AND (im.project_id = :project_id
+                    OR JSON_CONTAINS(im.issue_assignees, JSON_QUOTE(:pull_author)))
+                LIMIT 50",
+                params! {
+                    "pull_id" => &pull_id,
+                    "pull_author" => &pull_author,
+                    "project_id" => &project_id,
+                },
+                |(issue_id, issue_title, same_repo, author_assigned, days): (
+                    String,
+                    String,
+                    bool,
+                    bool,
+                    Option<i64>,
+                )| (issue_id, issue_title, same_repo, author_assigned, days),
+            )
+            .await?;
+
+        let mut candidates = Vec::new();
+        if !issues.is_empty() {
+            let mut texts = vec![pull_title.clone()];
+            texts.extend(issues.iter().map(|i| i.1.clone()));
+            // the PR stays unchecked and is matched again on the next run
+            let embeddings = match embed_texts(texts).await {
+                Ok(e) => e,
+                Err(e) => {
+                    log::error!("Error embedding titles for {}: {:?}", pull_id, e);
+                    continue;
+                }
+            };
+
+            for (n, (issue_id, _, same_repo, author_assigned, days)) in issues.iter().enumerate() {
+                let signals = MatchSignals {
+                    same_repo: *same_repo,
+                    author_assigned: *author_assigned,
+                    days_after_assignment: *days,
+                    title_similarity: embeddings
+                        .get(n + 1)
+                        .map(|e| cosine_similarity(&embeddings[0], e)),
+                };
+                let score = score_candidate(&signals);
+                if score >= MIN_MATCH_SCORE {
+                    candidates.push((issue_id.clone(), score, signals));
+                }
+            }
+            candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+            candidates.truncate(MAX_CANDIDATES_PER_PULL);
+        }
+
+        if !candidates.is_empty() {
+            r"INSERT IGNORE INTO pull_match_candidates (pull_id, issue_id, score, signals)
+            VALUES (:pull_id, :issue_id, :score, :signals)"
+                .with(candidates.iter().map(|(issue_id, score, signals)| {
+                    params! {
+                        "pull_id" => &pull_id,
+                        "issue_id" => issue_id,
+                        "score" => *score,
+                        "signals" => serde_json::json!(signals).to_string(),
+                    }
+                }))
+                .batch(&mut conn)
+                .await?;
+        }
+
+        conn.exec_drop(
+            r"UPDATE pull_requests SET date_match_checked = NOW() WHERE pull_id = :pull_id",
+            params! {
+                "pull_id" => &pull_id,
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_match_candidates(
+    pool: &Pool,
+    status: &str,
+    page: usize,
+    page_size: usize,
+) -> Result<Vec<MatchCandidate>> {
+    let mut conn = pool.get_conn().await?;
+    let offset = (page - 1) * page_size;
+
+    let out: Vec<MatchCandidate> = conn
+        .exec_map(
+            format!(
+                "SELECT c.candidate_id, c.pull_id, pr.pull_title, pr.pull_author, c.issue_id, im.issue_title, c.score, c.signals, c.status
+                FROM pull_match_candidates c
+                JOIN pull_requests pr ON pr.pull_id = c.pull_id
+                JOIN issues_master im ON im.issue_id = c.issue_id
+                WHERE c.status = :status
+                ORDER BY c.pull_id ASC, c.score DESC
+                LIMIT {} OFFSET {}",
+                page_size, offset
+            ),
+            params! {
+                "status" => status,
+            },
+            |(
+                candidate_id,
+                pull_id,
+                pull_title,
+                pull_author,
+                issue_id,
+                issue_title,
+                score,
+                signals,
+                status,
+            ): (
+                u32,
+                String,
+                String,
+                Option<String>,
+                String,
+                String,
+                f32,
+                Option<String>,
+                String,
+            )| MatchCandidate {
+                candidate_id,
+                pull_id,
+                pull_title,
+                pull_author,
+                issue_id,
+                issue_title,
+                score,
+                signals: signals.and_then(|s| serde_json::from_str(&s).ok()),
+                status,
+            },
+        )
+        .await?;
+
+    Ok(out)
+}
+
+// Accepting links the PR to the issue and drops the PR's other pending candidates;
+// rejecting only closes the candidate. An issue linked to a PR in the meantime is not
+// relinked, accepting then fails with the existing link.
+pub async fn review_match_candidate(
+    pool: &Pool,
+    candidate_id: u32,
+    accept: bool,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let candidate: Option<(String, String)> = conn
+        .exec_first(
+            r"SELECT pull_id, issue_id FROM pull_match_candidates
+            WHERE candidate_id = :candidate_id AND status = 'pending'",
+            params! {
+                "candidate_id" => candidate_id,
+            },
+        )
+        .await?;
+    let (pull_id, issue_id) = match candidate {
+        Some(c) => c,
+        None => {
+            return Err(
+                MatchConflict(format!("No pending match candidate {}", candidate_id)).into(),
+            )
+        }
+    };
+
+    let mut tx = conn.start_transaction(TxOpts::default()).await?;
+    if accept {
+        tx.exec_drop(
+            r"UPDATE issues_master im
+            JOIN pull_requests pr ON pr.pull_id = :pull_id
+            SET im.issue_linked_pr = pr.pull_id,
+                im.issue_linked_pr_node_id = pr.pull_node_id,
+                im.issue_linked_pr_method = :method,
+                im.issue_linked_pr_confidence = :confidence
+            WHERE im.issue_id = :issue_id AND im.issue_linked_pr IS NULL",
+            params! {
+                "pull_id" => &pull_id,
+                "issue_id" => &issue_id,
+                "method" => LinkMethod::ReviewedMatch.as_str(),
+                "confidence" => LinkMethod::ReviewedMatch.confidence(),
+            },
+        )
+        .await?;
+        if tx.affected_rows() == 0 {
+            let linked: Option<Option<String>> = tx
+                .exec_first(
+                    r"SELECT issue_linked_pr FROM issues_master WHERE issue_id = :issue_id",
+                    params! {
+                        "issue_id" => &issue_id,
+                    },
+                )
+                .await?;
+            // dropping the transaction rolls it back
+            return Err(match linked.flatten() {
+                Some(linked_pr) => MatchConflict(format!(
+                    "{} is already linked to {}, not linking {}",
+                    issue_id, linked_pr, pull_id
+                ))
+                .into(),
+                None => anyhow::anyhow!("{} or {} is no longer tracked", issue_id, pull_id),
+            });
+        }
+        tx.exec_drop(
+            r"UPDATE pull_requests SET issue_id = :issue_id WHERE pull_id = :pull_id",
+            params! {
+                "pull_id" => &pull_id,
+                "issue_id" => &issue_id,
+            },
+        )
+        .await?;
+        tx.exec_drop(
+            r"UPDATE pull_match_candidates SET status = 'rejected', date_reviewed = NOW()
+            WHERE status = 'pending' AND candidate_id <> :candidate_id
+            AND (pull_id = :pull_id OR issue_id = :issue_id)",
+            params! {
+                "candidate_id" => candidate_id,
+                "pull_id" => &pull_id,
+                "issue_id" => &issue_id,
+            },
+        )
+        .await?;
+    }
+    tx.exec_drop(
+        r"UPDATE pull_match_candidates SET status = :status, date_reviewed = NOW()
+        WHERE candidate_id = :candidate_id",
+        params! {
+            "candidate_id" => candidate_id,
+            "status" => if accept { "accepted" } else { "rejected" },
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signals(same_repo: bool, author_assigned: bool) -> MatchSignals {
+        MatchSignals {
+            same_repo,
+            author_assigned,
+            days_after_assignment: None,
+            title_similarity: None,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn scores_repo_and_assignee() {
+        assert!(close(score_candidate(&signals(false, false)), 0.0));
+        assert!(close(score_candidate(&signals(true, false)), 0.35));
+        assert!(close(score_candidate(&signals(false, true)), 0.3));
+        assert!(close(score_candidate(&signals(true, true)), 0.65));
+    }
+
+    #[test]
+    fn timing_fades_over_a_month() {
+        let at = |days| {
+            score_candidate(&MatchSignals {
+                days_after_assignment: Some(days),
+                ..signals(false, false)
+            })
+        };
+        assert!(close(at(0), 0.15));
+        // merged the day before the assignment still counts as on time
+        assert!(close(at(-1), 0.15));
+        assert!(close(at(15), 0.075));
+        assert!(close(at(30), 0.0));
+        assert!(close(at(90), 0.0));
+        assert!(close(at(-10), 0.0));
+    }
+
+    #[test]
+    fn title_similarity_counts_above_the_baseline() {
+        let at = |similarity| {
+            score_candidate(&MatchSignals {
+                title_similarity: Some(similarity),
+                ..signals(false, false)
+            })
+        };
+        assert!(close(at(0.5), 0.0));
+        assert!(close(at(0.7), 0.0));
+        assert!(close(at(0.85), 0.1));
+        assert!(close(at(1.0), 0.2));
+    }
+
+    #[test]
+    fn strong_match_stays_within_one() {
+        let score = score_candidate(&MatchSignals {
+            same_repo: true,
+            author_assigned: true,
+            days_after_assignment: Some(0),
+            title_similarity: Some(1.0),
+        });
+        assert!(close(score, 1.0));
+        assert!(score_candidate(&signals(true, false)) < MIN_MATCH_SCORE);
+        assert!(score_candidate(&signals(true, true)) >= MIN_MATCH_SCORE);
+    }
+}
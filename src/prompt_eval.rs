@@ -0,0 +1,172 @@
+use crate::db_populate::issue_summary_request;
+use crate::llm_provider::{provider_for, LlmTask};
+use crate::llm_utils::{chat_summary, SummaryOutput};
+use crate::prompts::*;
+use serde::{Deserialize, Serialize};
+
+// A fixed set of issues so two prompt versions are always compared on the same input.
+static SAMPLE_ISSUES: &str = include_str!("eval_samples/issues.json");
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SampleIssue {
+    pub issue_id: String,
+    pub issue_title: String,
+    pub issue_description: String,
+    pub expected_keywords: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct VersionStats {
+    pub version: String,
+    pub summarized: usize,
+    pub parse_failures: usize,
+    pub avg_summary_words: f32,
+    pub avg_keywords: f32,
+    pub avg_expected_overlap: f32, // keyword overlap with the hand-picked keywords of the sample
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EvalReport {
+    pub prompt: String,
+    pub model: String,
+    pub sample_size: usize,
+    pub a: VersionStats,
+    pub b: VersionStats,
+    pub avg_ab_keyword_overlap: f32, // over the issues both versions summarized
+}
+
+pub fn sample_issues() -> anyhow::Result<Vec<SampleIssue>> {
+    Ok(serde_json::from_str(SAMPLE_ISSUES)?)
+}
+
+// Jaccard index of two keyword lists, compared in lowercase.
+pub fn keyword_overlap(a: &[String], b: &[String]) -> f32 {
+    let a: Vec<String> = a.iter().map(|k| k.trim().to_lowercase()).collect();
+    let b: Vec<String> = b.iter().map(|k| k.trim().to_lowercase()).collect();
+
+    let mut union: Vec<&String> = Vec::new();
+    for k in a.iter().chain(b.iter()) {
+        if !union.contains(&k) {
+            union.push(k);
+        }
+    }
+    if union.is_empty() {
+        return 0.0;
+    }
+    let shared = union
+        .iter()
+        .filter(|k| a.contains(k) && b.contains(k))
+        .count();
+
+    shared as f32 / union.len() as f32
+}
+
+fn mean(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+async fn run_version(
+    prompt: &PromptTemplate,
+    samples: &[SampleIssue],
+) -> anyhow::Result<(VersionStats, Vec<Option<SummaryOutput>>)> {
+    let provider = provider_for(LlmTask::Summarization);
+    let mut outputs = Vec::new();
+    let mut parse_failures = 0;
+
+    for sample in samples {
+        let (system_prompt, user_input, max_tokens) = issue_summary_request(
+            prompt,
+            provider.model(),
+            &sample.issue_id,
+            &sample.issue_title,
+            &sample.issue_description,
+        );
+        match chat_summary(provider.as_ref(), system_prompt, &user_input, max_tokens).await? {
+            Ok(output) => outputs.push(Some(output)),
+            Err(failure) => {
+                log::error!(
+                    "{} failed to parse for {}: {}",
+                    prompt.id(),
+                    sample.issue_id,
+                    failure.error
+                );
+                parse_failures += 1;
+                outputs.push(None);
+            }
+        }
+    }
+
+    let mut words = Vec::new();
+    let mut keywords = Vec::new();
+    let mut overlaps = Vec::new();
+    for (output, sample) in outputs.iter().zip(samples) {
+        if let Some(output) = output {
+            words.push(output.summary.split_whitespace().count() as f32);
+            keywords.push(output.keywords.len() as f32);
+            overlaps.push(keyword_overlap(&output.keywords, &sample.expected_keywords));
+        }
+    }
+
+    let stats = VersionStats {
+        version: prompt.version.to_string(),
+        summarized: words.len(),
+        parse_failures,
+        avg_summary_words: mean(&words),
+        avg_keywords: mean(&keywords),
+        avg_expected_overlap: mean(&overlaps),
+    };
+    Ok((stats, outputs))
+}
+
+// Summarizes the sample issues with both versions of a prompt on the provider configured
+// for summarization. Nothing is written to the database.
+pub async fn evaluate_prompt_versions(
+    name: &str,
+    version_a: &str,
+    version_b: &str,
+) -> anyhow::Result<EvalReport> {
+    let prompt_a = get_prompt(name, version_a).ok_or(anyhow::anyhow!(
+        "no prompt {}@{}, versions: {:?}",
+        name,
+        version_a,
+        prompt_versions(name)
+    ))?;
+    let prompt_b = get_prompt(name, version_b).ok_or(anyhow::anyhow!(
+        "no prompt {}@{}, versions: {:?}",
+        name,
+        version_b,
+        prompt_versions(name)
+    ))?;
+    if name != ISSUE_SUMMARY {
+        return Err(anyhow::anyhow!(
+            "only {} has a sample to evaluate",
+            ISSUE_SUMMARY
+        ));
+    }
+
+    let samples = sample_issues()?;
+    let (a, outputs_a) = run_version(prompt_a, &samples).await?;
+    let (b, outputs_b) = run_version(prompt_b, &samples).await?;
+
+    let ab_overlaps: Vec<f32> = outputs_a
+        .iter()
+        .zip(outputs_b.iter())
+        .filter_map(|pair| match pair {
+            (Some(a), Some(b)) => Some(keyword_overlap(&a.keywords, &b.keywords)),
+            _ => None,
+        })
+        .collect();
+
+    let provider = provider_for(LlmTask::Summarization);
+    Ok(EvalReport {
+        prompt: name.to_string(),
+        model: format!("{}/{}", provider.name(), provider.model()),
+        sample_size: samples.len(),
+        a,
+        b,
+        avg_ab_keyword_overlap: mean(&ab_overlaps),
+    })
+}
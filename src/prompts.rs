@@ -0,0 +1,125 @@
+// System prompts for summarization, kept as versioned templates so a summary can be traced
+// back to the prompt that made it and two versions can be compared before switching.
+#[derive(Debug)]
+pub struct PromptTemplate {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub long_input: &'static str,
+    pub short_input: &'static str,
+    pub short_below: usize, // inputs shorter than this many bytes get the short prompt
+}
+
+impl PromptTemplate {
+    pub fn id(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+
+    pub fn system_prompt(&self, input_len: usize) -> &'static str {
+        if input_len < self.short_below {
+            self.short_input
+        } else {
+            self.long_input
+        }
+    }
+}
+
+pub static ISSUE_SUMMARY: &str = "issue_summary";
+pub static PROJECT_SUMMARY: &str = "project_summary";
+pub static DEFAULT_PROMPT_VERSION: &str = "v1";
+
+pub static ISSUE_CLASSIFY_PROMPT: &str = r#"
+    You sort GitHub issues for contributors looking for work. From the issue title and body, judge:
+    - difficulty: "good-first-issue" when a newcomer to the project can do it with little context, "advanced" when it needs deep knowledge of the codebase or the domain, otherwise "intermediate"
+    - effort_hours: the hours an experienced contributor would need, as a whole number
+    - skills: up to 6 lowercase languages, frameworks or areas the work needs, e.g. "rust", "react", "ci", "technical writing"
+    - issue_type: "bug", "feature", "docs" or "test"
+    Reply with RFC8259-compliant JSON only:
+    { "difficulty": "intermediate", "effort_hours": 4, "skills": ["skill", "skill"], "issue_type": "feature" }"#;
+
+// Keywords asked separately from a finished summary, when the keywords task has a provider
+// or model of its own.
+pub static KEYWORDS_PROMPT: &str = r#"
+    You tag summaries of GitHub issues and repositories for a catalog of open source work. Give 3 to 6 keywords in lowercase, each one to three words, naming the broader area of the work or project (for example "documentation", "web ui", "rust", "ci pipeline") rather than its details. Reply with RFC8259-compliant JSON only:
+    { "keywords": ["keyword", "keyword", "keyword"] }"#;
+
+// Map step for READMEs too long for one request, the notes replace the README in the
+// project summary prompt.
+pub static README_CHUNK_PROMPT: &str = r#"
+    You read one part of a long GitHub README. In at most 100 words, note what this part says about the project's purpose, the technologies it uses and its notable features. Leave out installation steps, badges, license text and contributor lists. If the part has nothing of the kind, reply with an empty line. Reply with plain text notes, not JSON."#;
+
+// Second opinion for keyword tags whose embeddings are close but not close enough to merge.
+pub static TAG_MERGE_PROMPT: &str = r#"
+    You maintain the tag list of a catalog of open source issues and projects. Decide whether the two tags name the same topic, so one could replace the other without losing meaning, e.g. "web development" and "web dev" do, "rust" and "rust async" do not. Reply with RFC8259-compliant JSON only:
+    { "same": true }"#;
+
+static PROMPTS: &[PromptTemplate] = &[
+    PromptTemplate {
+        name: "issue_summary",
+        version: "v1",
+        long_input: r#"
+        Summarize the GitHub issue in one paragraph without mentioning the issue number. Highlight the key problem and any signature information provided. The summary should be concise, informative, and easy to understand, prioritizing clarity and brevity. Additionally, extract high-level keywords that represent broader categories or themes relevant to the issue's purpose, features, and tools used. These keywords should help categorize the issue in a wider context and should not be too literal or specific, avoiding overly long phrases unless absolutely necessary. Expected Output:
+        { \"summary\": \"the_summary_generated, a short paragraph summarizing the issue, including its purpose and features, without referencing the issue number.\",
+          \"keywords\": [\"a list of high-level keywords that encapsulate the broader context, categories, or themes of the issue, excluding specific details and issue numbers.\"] }
+        Ensure you reply in RFC8259-compliant JSON format."#,
+        short_input: r#"
+        Given the limited information available, summarize the GitHub issue in one paragraph without mentioning the issue number. Highlight the key problem and any signature information that can be inferred. The summary should be concise, informative, and easy to understand, prioritizing clarity and brevity even with scant details. Additionally, extract high-level keywords that represent broader categories or themes relevant to the issue's inferred purpose, features, and tools used. These keywords should help categorize the issue in a wider context and should not be too literal or specific, avoiding overly long phrases unless absolutely necessary. Expected Output:
+        { \"summary\": \"The summary generated should be a concise paragraph that highlights any discernible purpose, technologies, or features from the limited information.\",
+          \"keywords\": [\"A list of inferred high-level keywords that broadly categorize the repository based on the scant details available.\"] }
+        Ensure you reply in RFC8259-compliant JSON format."#,
+        short_below: 200,
+    },
+    PromptTemplate {
+        name: "issue_summary",
+        version: "v2",
+        long_input: r#"
+        You summarize GitHub issues for contributors looking for work. In at most 80 words, say what needs to be done and why, naming the languages, frameworks or tools involved. Do not mention the issue number, labels or rewards. Then give 3 to 6 keywords in lowercase, each one to three words, naming the area of the work (for example "documentation", "web ui", "rust", "ci pipeline") rather than details of this issue. Reply with RFC8259-compliant JSON only:
+        { "summary": "the summary", "keywords": ["keyword", "keyword", "keyword"] }"#,
+        short_input: r#"
+        You summarize GitHub issues for contributors looking for work. The issue says little, so in at most 50 words state what is asked and what can be inferred about it from the title and repository, without guessing details. Do not mention the issue number, labels or rewards. Then give 3 to 5 keywords in lowercase, each one to three words, naming the area of the work rather than details of this issue. Reply with RFC8259-compliant JSON only:
+        { "summary": "the summary", "keywords": ["keyword", "keyword", "keyword"] }"#,
+        short_below: 200,
+    },
+    PromptTemplate {
+        name: "project_summary",
+        version: "v1",
+        long_input: r#"
+    Summarize the GitHub repository's README or description in one detailed paragraph, focusing solely on the essential aspects such as the project's purpose, technologies used, and notable features. Do not include non-essential elements like personal appeals or donation links. Extract high-level keywords that represent broader categories or themes relevant to the project. These keywords should categorize the project in a wider context and not be overly specific or literal. Expected Output:
+    { \"summary\": \"A comprehensive paragraph that succinctly summarizes the repository, highlighting its purpose, technologies, and key features, without including extraneous details.\",
+      \"keywords\": [\"A list of high-level keywords that encapsulate the broader context, categories, or themes of the repository, focusing on essential aspects only.\"] }
+    Ensure your reply is in RFC8259-compliant JSON format.
+    "#,
+        short_input: r#"When summarizing a GitHub repository's README or description, concentrate on the core content. Provide a concise paragraph that captures the primary purpose, technologies used, and notable features. Avoid mentioning non-essential elements such as donation links or personal appeals. Deduce and include high-level keywords that broadly categorize the repository, focusing on the technologies, functionality, and scope based on the available information. These keywords should reflect the main themes or categories relevant to the project. Expected Output:
+    { \"summary\": \"The summary generated should be a concise paragraph that highlights any discernible purpose, technologies, or features from the limited information.\",
+      \"keywords\": [\"A list of inferred high-level keywords that broadly categorize the repository based on the scant details available.\"] }
+    Ensure you reply in RFC8259-compliant JSON format."#,
+        short_below: 200,
+    },
+];
+
+pub fn get_prompt(name: &str, version: &str) -> Option<&'static PromptTemplate> {
+    PROMPTS
+        .iter()
+        .find(|p| p.name == name && p.version == version)
+}
+
+pub fn prompt_versions(name: &str) -> Vec<&'static str> {
+    PROMPTS
+        .iter()
+        .filter(|p| p.name == name)
+        .map(|p| p.version)
+        .collect()
+}
+
+// PROMPT_<NAME>_VERSION picks the version in use, e.g. PROMPT_ISSUE_SUMMARY_VERSION=v2;
+// unknown versions fall back to the default one.
+pub fn active_prompt(name: &str) -> &'static PromptTemplate {
+    let env_name = format!("PROMPT_{}_VERSION", name.to_uppercase());
+    if let Ok(version) = std::env::var(&env_name) {
+        match get_prompt(name, version.trim()) {
+            Some(prompt) => return prompt,
+            None => log::error!("Unknown prompt version {} in {}", version, env_name),
+        }
+    }
+
+    get_prompt(name, DEFAULT_PROMPT_VERSION).expect("every prompt has a default version")
+}
@@ -0,0 +1,806 @@
+use crate::db_join::record_assignee_snapshot;
+use crate::issue_tracker::*;
+use crate::pr_linking::LinkMethod;
+use crate::ISSUE_LABEL;
+use anyhow::anyhow;
+use chrono::Utc;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub static RECONCILE_BATCH_SIZE: usize = 50;
+// runs in a row an issue may be missing from GitHub before it is marked deleted, unless
+// GitHub answers 404 or 410 for it
+pub static MISSES_BEFORE_DELETED: i32 = 3;
+
+// An issue as recorded in issues_master, the fields reconciliation compares.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TrackedIssue {
+    pub issue_id: String,
+    pub issue_node_id: Option<String>,
+    pub issue_title: String,
+    pub project_id: String,
+    pub issue_assignees: Vec<String>,
+    pub issue_linked_pr: Option<String>,
+    pub issue_state: String,
+    pub missed_lookups: i32,
+}
+
+// The same issue as GitHub reports it now.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GithubIssueState {
+    pub node_id: String,
+    pub url: String,
+    pub title: String,
+    pub state: String, // "open" or "closed"
+    pub labels: Vec<String>,
+    pub assignees: Vec<String>,
+    pub project_id: String,
+    pub closer: Option<String>,
+    pub last_assigned: Option<String>, // date of the latest assigned event
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Drift {
+    pub issue_id: String,
+    pub drift_field: String,
+    pub db_value: Option<String>,
+    pub github_value: Option<String>,
+    pub corrected: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReconcileReport {
+    pub run_id: String,
+    pub issues_checked: usize,
+    pub issues_drifted: usize,
+    pub drifts: usize,
+}
+
+fn drift(
+    issue_id: &str,
+    drift_field: &str,
+    db_value: Option<String>,
+    github_value: Option<String>,
+    corrected: bool,
+) -> Drift {
+    Drift {
+        issue_id: issue_id.to_string(),
+        drift_field: drift_field.to_string(),
+        db_value,
+        github_value,
+        corrected,
+    }
+}
+
+// Lists the differences between the db and GitHub; `None` means GitHub no longer has the issue.
+// The issue is matched by node id, so a different url means the repo was renamed or transferred.
+pub fn diff_issue(db: &TrackedIssue, github: Option<&GithubIssueState>) -> Vec<Drift> {
+    let id = db.issue_id.as_str();
+    let gh = match github {
+        Some(gh) => gh,
+        None if db.issue_state == "deleted" => return Vec::new(),
+        None => {
+            return vec![drift(
+                id,
+                "issue_state",
+                Some(db.issue_state.clone()),
+                Some(String::from("deleted")),
+                true,
+            )]
+        }
+    };
+
+    let mut out = Vec::new();
+    if db.issue_state != gh.state {
+        out.push(drift(
+            id,
+            "issue_state",
+            Some(db.issue_state.clone()),
+            Some(gh.state.clone()),
+            true,
+        ));
+    }
+    if db.issue_title != gh.title {
+        out.push(drift(
+            id,
+            "issue_title",
+            Some(db.issue_title.clone()),
+            Some(gh.title.clone()),
+            true,
+        ));
+    }
+    if !gh
+        .labels
+        .iter()
+        .any(|l| l.eq_ignore_ascii_case(ISSUE_LABEL))
+    {
+        out.push(drift(
+            id,
+            "issue_label",
+            Some(ISSUE_LABEL.to_string()),
+            Some(gh.labels.join(",")),
+            true,
+        ));
+    }
+
+    let mut db_assignees = db.issue_assignees.clone();
+    let mut gh_assignees = gh.assignees.clone();
+    db_assignees.sort();
+    gh_assignees.sort();
+    if db_assignees != gh_assignees {
+        out.push(drift(
+            id,
+            "issue_assignees",
+            Some(db_assignees.join(",")),
+            Some(gh_assignees.join(",")),
+            true,
+        ));
+    }
+    // a missing closer doesn't clear a link found by other means
+    if gh.closer.is_some() && db.issue_linked_pr != gh.closer {
+        out.push(drift(
+            id,
+            "issue_linked_pr",
+            db.issue_linked_pr.clone(),
+            gh.closer.clone(),
+            true,
+        ));
+    }
+
+    if db.issue_id != gh.url {
+        out.push(drift(
+            id,
+            "issue_url",
+            Some(db.issue_id.clone()),
+            Some(gh.url.clone()),
+            true,
+        ));
+    }
+    if db.project_id != gh.project_id {
+        out.push(drift(
+            id,
+            "project_id",
+            Some(db.project_id.clone()),
+            Some(gh.project_id.clone()),
+            true,
+        ));
+    }
+
+    out
+}
+
+// Issues that can still change: everything not paid out. Deleted issues are checked again,
+// GitHub may have hidden them only for a while.
+pub async fn get_issues_to_reconcile(pool: &Pool) -> Result<Vec<TrackedIssue>> {
+    let mut conn = pool.get_conn().await?;
+
+    let selected_rows: Vec<TrackedIssue> = conn
+        .query_map(
+            r"SELECT issue_id, issue_node_id, issue_title, project_id, issue_assignees, issue_linked_pr, issue_state, missed_lookups
+            FROM issues_master
+            WHERE issue_budget_approved = 0
+            ORDER BY date_reconciled IS NOT NULL, date_reconciled ASC",
+            |(
+                issue_id,
+                issue_node_id,
+                issue_title,
+                project_id,
+                issue_assignees,
+                issue_linked_pr,
+                issue_state,
+                missed_lookups,
+            ): (
+                String,
+                Option<String>,
+                String,
+                String,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<i32>,
+            )| TrackedIssue {
+                issue_id,
+                issue_node_id,
+                issue_title,
+                project_id,
+                issue_assignees: issue_assignees
+                    .and_then(|s| serde_json::from_str::<Vec<Option<String>>>(&s).ok())
+                    .map(|v| v.into_iter().flatten().collect())
+                    .unwrap_or_default(),
+                issue_linked_pr,
+                issue_state: issue_state.unwrap_or_else(|| String::from("open")),
+                missed_lookups: missed_lookups.unwrap_or(0),
+            },
+        )
+        .await?;
+    Ok(selected_rows)
+}
+
+// Looks up node ids for issues recorded before ids were stored. A null resource means the
+// url no longer resolves to an issue.
+pub async fn resolve_issue_node_ids(
+    urls: &[String],
+) -> anyhow::Result<HashMap<String, Option<String>>> {
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct GraphQLResponse {
+        data: Option<HashMap<String, Option<Resource>>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Resource {
+        id: Option<String>,
+    }
+
+    let aliases = urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            format!(
+                r#"i{}: resource(url: "{}") {{ ... on Issue {{ id }} }}"#,
+                i,
+                url.replace("\"", "\\\"")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let query_str = format!("query {{\n{}\n}}", aliases);
+
+    let response_body = github_http_post_gql(&query_str)
+        .await
+        .map_err(|e| anyhow!("Failed to post GraphQL query: {}", e))?;
+    let response: GraphQLResponse = serde_json::from_slice(&response_body)
+        .map_err(|e| anyhow!("Failed to deserialize response: {}", e))?;
+    let data = response
+        .data
+        .ok_or_else(|| anyhow!("No data in node id lookup"))?;
+
+    Ok(urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            let node_id = data
+                .get(&format!("i{}", i))
+                .cloned()
+                .flatten()
+                .and_then(|r| r.id);
+            (url.clone(), node_id)
+        })
+        .collect())
+}
+
+// Reads the current state of issues by node id, ids GitHub doesn't know map to None.
+pub async fn fetch_issue_states(
+    node_ids: &[String],
+) -> anyhow::Result<HashMap<String, Option<GithubIssueState>>> {
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct GraphQLResponse {
+        data: Option<Data>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Data {
+        nodes: Vec<Option<Issue>>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Issue {
+        id: Option<String>,
+        url: Option<String>,
+        title: Option<String>,
+        state: Option<String>,
+        labels: Option<Nodes<Label>>,
+        assignees: Option<Nodes<User>>,
+        repository: Option<Repository>,
+        timelineItems: Option<Nodes<ClosedEvent>>,
+        assignedEvents: Option<Nodes<AssignedEvent>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Nodes<T> {
+        nodes: Option<Vec<T>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Label {
+        name: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct User {
+        login: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Repository {
+        url: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct ClosedEvent {
+        closer: Option<Closer>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Closer {
+        url: Option<String>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct AssignedEvent {
+        createdAt: Option<String>,
+    }
+
+    let query_str = format!(
+        r#"
+        query {{
+            nodes(ids: [{}]) {{
+                ... on Issue {{
+                    id
+                    url
+                    title
+                    state
+                    labels(first: 20) {{
+                        nodes {{
+                            name
+                        }}
+                    }}
+                    assignees(first: 10) {{
+                        nodes {{
+                            login
+                        }}
+                    }}
+                    repository {{
+                        url
+                    }}
+                    timelineItems(last: 1, itemTypes: [CLOSED_EVENT]) {{
+                        nodes {{
+                            ... on ClosedEvent {{
+                                closer {{
+                                    ... on PullRequest {{
+                                        url
+                                    }}
+                                }}
+                            }}
+                        }}
+                    }}
+                    assignedEvents: timelineItems(last: 1, itemTypes: [ASSIGNED_EVENT]) {{
+                        nodes {{
+                            ... on AssignedEvent {{
+                                createdAt
+                            }}
+                        }}
+                    }}
+                }}
+            }}
+        }}
+        "#,
+        node_ids
+            .iter()
+            .map(|id| format!("\"{}\"", id.replace("\"", "")))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+
+    let response_body = github_http_post_gql(&query_str)
+        .await
+        .map_err(|e| anyhow!("Failed to post GraphQL query: {}", e))?;
+    // unknown ids come back as null nodes next to an "errors" list, the data is still usable
+    let response: GraphQLResponse = serde_json::from_slice(&response_body)
+        .map_err(|e| anyhow!("Failed to deserialize response: {}", e))?;
+    let nodes = response
+        .data
+        .ok_or_else(|| anyhow!("No data in issue state lookup"))?
+        .nodes;
+
+    let mut out = HashMap::new();
+    for (node_id, node) in node_ids.iter().zip(nodes.into_iter()) {
+        let state = node.map(|issue| {
+            let names = |n: Option<Vec<String>>| n.unwrap_or_default();
+            GithubIssueState {
+                node_id: issue.id.unwrap_or_else(|| node_id.clone()),
+                url: issue.url.unwrap_or_default(),
+                title: issue.title.unwrap_or_default(),
+                state: issue.state.unwrap_or_default().to_lowercase(),
+                labels: names(
+                    issue
+                        .labels
+                        .and_then(|l| l.nodes)
+                        .map(|nodes| nodes.into_iter().filter_map(|l| l.name).collect()),
+                ),
+                assignees: names(
+                    issue
+                        .assignees
+                        .and_then(|a| a.nodes)
+                        .map(|nodes| nodes.into_iter().filter_map(|u| u.login).collect()),
+                ),
+                project_id: issue.repository.and_then(|r| r.url).unwrap_or_default(),
+                closer: issue
+                    .timelineItems
+                    .and_then(|t| t.nodes)
+                    .and_then(|nodes| nodes.into_iter().find_map(|e| e.closer.and_then(|c| c.url))),
+                last_assigned: issue
+                    .assignedEvents
+                    .and_then(|t| t.nodes)
+                    .and_then(|nodes| nodes.into_iter().find_map(|e| e.createdAt))
+                    .and_then(|d| convert_datetime(&d).ok()),
+            }
+        });
+        out.insert(node_id.clone(), state);
+    }
+
+    Ok(out)
+}
+
+// Moves an issue to its new url in every table keyed by it. A new url that is already
+// tracked as a separate row is left alone, that duplicate has to be merged by hand.
+//
+// Tables holding issue urls: issues_master, issues_open, issues_assigned, issues_closed,
+// issues_comment, issue_assignments, issue_classifications, pull_requests, pull_match_candidates,
+// payouts, notifications, llm_parse_failures, issues_repos_summarized and issues_repos_indexed,
+// all moved here; projects.issues_list is rebuilt from issues_master by master_project, and
+// reconcile_drift keeps the url each drift was found under. A new table with an issue url
+// belongs in this list.
+pub async fn move_issue_url(
+    pool: &Pool,
+    old_issue_id: &str,
+    new_issue_id: &str,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let exists: Option<u8> = conn
+        .exec_first(
+            r"SELECT 1 FROM issues_master WHERE issue_id = :new_issue_id",
+            params! {
+                "new_issue_id" => new_issue_id,
+            },
+        )
+        .await?;
+    if exists.is_some() {
+        return Err(anyhow!(
+            "{} is already tracked, can't move {} to it",
+            new_issue_id,
+            old_issue_id
+        ));
+    }
+
+    let queries = vec![
+        r"UPDATE issues_master SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE IGNORE issues_open SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE IGNORE issues_assigned SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE IGNORE issues_closed SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE issue_assignments SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE pull_requests SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE payouts SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE issues_comment SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE IGNORE notifications SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE IGNORE issue_classifications SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE IGNORE pull_match_candidates SET issue_id = :new_issue_id WHERE issue_id = :old_issue_id",
+        r"UPDATE llm_parse_failures SET issue_or_project_id = :new_issue_id WHERE issue_or_project_id = :old_issue_id",
+        // the vector store keys entries by url, have it indexed again under the new one
+        r"UPDATE IGNORE issues_repos_summarized SET issue_or_project_id = :new_issue_id, indexed = 0
+        WHERE issue_or_project_id = :old_issue_id",
+        r"DELETE FROM issues_repos_indexed WHERE issue_or_project_id = :old_issue_id",
+    ];
+
+    let mut tx = conn.start_transaction(TxOpts::default()).await?;
+    for query in queries {
+        tx.exec_drop(
+            query,
+            params! {
+                "old_issue_id" => old_issue_id,
+                "new_issue_id" => new_issue_id,
+            },
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// Points everything recorded under a repository's old url to its new one.
+pub async fn move_project_url(
+    pool: &Pool,
+    old_project_id: &str,
+    new_project_id: &str,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let queries = vec![
+        r"UPDATE issues_master SET project_id = :new_project_id WHERE project_id = :old_project_id",
+        r"UPDATE pull_requests SET project_id = :new_project_id WHERE project_id = :old_project_id",
+        r"UPDATE IGNORE projects SET project_id = :new_project_id WHERE project_id = :old_project_id",
+        r"UPDATE IGNORE issues_repos_summarized SET issue_or_project_id = :new_project_id, indexed = 0
+        WHERE issue_or_project_id = :old_project_id",
+        r"DELETE FROM issues_repos_indexed WHERE issue_or_project_id = :old_project_id",
+    ];
+
+    let mut tx = conn.start_transaction(TxOpts::default()).await?;
+    for query in queries {
+        tx.exec_drop(
+            query,
+            params! {
+                "old_project_id" => old_project_id,
+                "new_project_id" => new_project_id,
+            },
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn reset_missed_lookups(pool: &Pool, issue_id: &str) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    conn.exec_drop(
+        r"UPDATE issues_master SET missed_lookups = 0 WHERE issue_id = :issue_id",
+        params! {
+            "issue_id" => issue_id,
+        },
+    )
+    .await
+}
+
+// `confirmed_gone` is set when GitHub answered 404 or 410 for an issue missing from the
+// GraphQL lookup; otherwise a missing issue is only marked deleted after repeated misses.
+async fn apply_corrections(
+    pool: &Pool,
+    db: &TrackedIssue,
+    github: Option<&GithubIssueState>,
+    confirmed_gone: bool,
+    drifts: &mut [Drift],
+    run_id: &str,
+) -> anyhow::Result<()> {
+    // follow renames and transfers first, the remaining updates use the current url
+    let mut issue_id = db.issue_id.clone();
+    if let Some(gh) = github {
+        for d in drifts.iter_mut() {
+            let moved = match d.drift_field.as_str() {
+                "issue_url" => move_issue_url(pool, &db.issue_id, &gh.url).await,
+                "project_id" => move_project_url(pool, &db.project_id, &gh.project_id).await,
+                _ => continue,
+            };
+            match moved {
+                Ok(()) if d.drift_field == "issue_url" => issue_id = gh.url.clone(),
+                Ok(()) => (),
+                Err(e) => {
+                    log::error!("Error moving {} of {}: {:?}", d.drift_field, db.issue_id, e);
+                    d.corrected = false;
+                }
+            }
+        }
+    }
+
+    let mut conn = pool.get_conn().await?;
+
+    match github {
+        None => {
+            let deleted = confirmed_gone || db.missed_lookups + 1 >= MISSES_BEFORE_DELETED;
+            for d in drifts.iter_mut() {
+                d.corrected = deleted;
+            }
+            conn.exec_drop(
+                r"UPDATE issues_master
+                SET issue_state = IF(:deleted, 'deleted', issue_state),
+                    missed_lookups = missed_lookups + 1,
+                    date_reconciled = NOW()
+                WHERE issue_id = :issue_id",
+                params! {
+                    "deleted" => deleted,
+                    "issue_id" => &issue_id,
+                },
+            )
+            .await?;
+        }
+        Some(gh) => {
+            let label_removed = drifts.iter().any(|d| d.drift_field == "issue_label");
+
+            conn.exec_drop(
+                r"UPDATE issues_master
+                SET issue_node_id = :issue_node_id,
+                    issue_state = :issue_state,
+                    issue_title = :issue_title,
+                    date_issue_assigned = IF(:has_assignees, COALESCE(date_issue_assigned, :last_assigned), date_issue_assigned),
+                    issue_linked_pr_method = IF(:issue_linked_pr IS NULL, issue_linked_pr_method, :closing_method),
+                    issue_linked_pr_confidence = IF(:issue_linked_pr IS NULL, issue_linked_pr_confidence, :closing_confidence),
+                    issue_linked_pr = COALESCE(:issue_linked_pr, issue_linked_pr),
+                    issue_status = CASE
+                        WHEN :label_removed THEN CONCAT('campaign label removed on GitHub, found ', DATE(NOW()))
+                        ELSE issue_status
+                    END,
+                    missed_lookups = 0,
+                    date_reconciled = NOW()
+                WHERE issue_id = :issue_id",
+                params! {
+                    "issue_node_id" => &gh.node_id,
+                    "issue_state" => &gh.state,
+                    "issue_title" => &gh.title,
+                    "has_assignees" => !gh.assignees.is_empty(),
+                    "last_assigned" => gh.last_assigned.as_deref(),
+                    "issue_linked_pr" => gh.closer.as_deref(),
+                    "closing_method" => LinkMethod::ClosingEvent.as_str(),
+                    "closing_confidence" => LinkMethod::ClosingEvent.confidence(),
+                    "label_removed" => label_removed,
+                    "issue_id" => &issue_id,
+                },
+            )
+            .await?;
+
+            // assignee changes go through the history, which derives issue_assignees
+            if drifts.iter().any(|d| d.drift_field == "issue_assignees") {
+                record_assignee_snapshot(pool, &issue_id, &gh.assignees, "reconciliation").await?;
+            }
+        }
+    }
+
+    if !drifts.is_empty() {
+        r"INSERT INTO reconcile_drift (run_id, issue_id, drift_field, db_value, github_value, corrected)
+        VALUES (:run_id, :issue_id, :drift_field, :db_value, :github_value, :corrected)"
+            .with(drifts.iter().map(|d| {
+                params! {
+                    "run_id" => run_id,
+                    "issue_id" => &d.issue_id,
+                    "drift_field" => &d.drift_field,
+                    "db_value" => &d.db_value,
+                    "github_value" => &d.github_value,
+                    "corrected" => d.corrected,
+                }
+            }))
+            .batch(&mut conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Re-reads every non-terminal issue from GitHub in batches, corrects issues_master and
+// records each difference in reconcile_drift under one run_id.
+pub async fn reconcile_issues(pool: &Pool) -> anyhow::Result<ReconcileReport> {
+    let run_id = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let tracked = get_issues_to_reconcile(pool).await?;
+    let mut report = ReconcileReport {
+        run_id: run_id.clone(),
+        ..Default::default()
+    };
+    log::info!("Issues to reconcile, count: {:?}", tracked.len());
+
+    for batch in tracked.chunks(RECONCILE_BATCH_SIZE) {
+        let mut node_ids: HashMap<String, Option<String>> = batch
+            .iter()
+            .filter_map(|i| {
+                i.issue_node_id
+                    .clone()
+                    .map(|n| (i.issue_id.clone(), Some(n)))
+            })
+            .collect();
+
+        let missing: Vec<String> = batch
+            .iter()
+            .filter(|i| i.issue_node_id.is_none())
+            .map(|i| i.issue_id.clone())
+            .collect();
+        if !missing.is_empty() {
+            match resolve_issue_node_ids(&missing).await {
+                Ok(resolved) => node_ids.extend(resolved),
+                Err(e) => {
+                    log::error!("Error resolving node ids, skipping batch: {:?}", e);
+                    continue;
+                }
+            }
+        }
+
+        // an old url may only resolve through a redirect, try that before calling it deleted
+        let mut lookup_failed = false;
+        let mut gone: Vec<String> = Vec::new();
+        for (issue_id, node_id) in node_ids.iter_mut().filter(|(_, n)| n.is_none()) {
+            match resolve_moved_issue(issue_id).await {
+                Ok(None) => gone.push(issue_id.clone()),
+                Ok(moved) => *node_id = moved.map(|(id, _)| id),
+                Err(e) => {
+                    log::error!("Error following redirects of {}: {:?}", issue_id, e);
+                    lookup_failed = true;
+                }
+            }
+        }
+        if lookup_failed {
+            continue;
+        }
+
+        let ids: Vec<String> = node_ids.values().flatten().cloned().collect();
+        let states = if ids.is_empty() {
+            HashMap::new()
+        } else {
+            match fetch_issue_states(&ids).await {
+                Ok(s) => s,
+                Err(e) => {
+                    log::error!("Error fetching issue states, skipping batch: {:?}", e);
+                    continue;
+                }
+            }
+        };
+
+        for issue in batch {
+            let github = node_ids
+                .get(&issue.issue_id)
+                .cloned()
+                .flatten()
+                .and_then(|node_id| states.get(&node_id).cloned().flatten());
+            let mut drifts = diff_issue(issue, github.as_ref());
+            // a node GraphQL didn't return may be a hiccup, ask the REST api whether it is gone
+            let mut confirmed_gone = gone.contains(&issue.issue_id);
+            if github.is_none() && !confirmed_gone && issue.issue_state != "deleted" {
+                match resolve_moved_issue(&issue.issue_id).await {
+                    Ok(None) => confirmed_gone = true,
+                    // it is there, GraphQL only missed it this time; compare it on the next run
+                    Ok(Some(_)) => {
+                        if let Err(e) = reset_missed_lookups(pool, &issue.issue_id).await {
+                            log::error!("Error resetting misses of {}: {:?}", issue.issue_id, e);
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("Error checking whether {} is gone: {:?}", issue.issue_id, e);
+                        continue;
+                    }
+                }
+            }
+
+            report.issues_checked += 1;
+            if !drifts.is_empty() {
+                report.issues_drifted += 1;
+                report.drifts += drifts.len();
+            }
+            if let Err(e) = apply_corrections(
+                pool,
+                issue,
+                github.as_ref(),
+                confirmed_gone,
+                &mut drifts,
+                &run_id,
+            )
+            .await
+            {
+                log::error!("Error reconciling {}: {:?}", issue.issue_id, e);
+            }
+        }
+    }
+
+    log::info!("Reconciliation finished: {:?}", report);
+    Ok(report)
+}
+
+// Drift found by one run, or by the latest run when no run_id is given.
+pub async fn get_drift_report(pool: &Pool, run_id: Option<&str>) -> Result<Vec<Drift>> {
+    let mut conn = pool.get_conn().await?;
+
+    let selected_rows: Vec<Drift> = conn
+        .exec_map(
+            r"SELECT issue_id, drift_field, db_value, github_value, corrected FROM reconcile_drift
+            WHERE run_id = COALESCE(:run_id, (SELECT MAX(run_id) FROM reconcile_drift))
+            ORDER BY issue_id, drift_field",
+            params! {
+                "run_id" => run_id,
+            },
+            |(issue_id, drift_field, db_value, github_value, corrected): (
+                String,
+                String,
+                Option<String>,
+                Option<String>,
+                bool,
+            )| Drift {
+                issue_id,
+                drift_field,
+                db_value,
+                github_value,
+                corrected,
+            },
+        )
+        .await?;
+    Ok(selected_rows)
+}
@@ -0,0 +1,428 @@
+use crate::db_manipulate::LOW_EFFORT_CHANGES;
+use crate::issue_bot::parse_issue_id;
+use crate::issue_tracker::*;
+use crate::llm_provider::{chat_for_task, LlmTask};
+use crate::llm_utils::extract_json_object;
+use http_req::request::Method;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+
+// issues scoring at or above this are listed by the "spam" filter
+pub static SPAM_THRESHOLD: f32 = 0.5;
+pub static NEW_ACCOUNT_DAYS: i64 = 30;
+// issues by one creator in one repo during a campaign before it counts as bulk creation
+pub static BULK_ISSUE_COUNT: i64 = 5;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SpamSignals {
+    pub creator_is_assignee: bool,
+    pub tiny_diff: bool,
+    pub whitespace_only: bool,
+    pub new_account: bool,
+    pub bulk_issues: bool,
+    pub llm_verdict: Option<String>, // "spam", "low_effort" or "genuine"
+    pub pull_checked: bool,          // false until the linked PR has been looked at
+}
+
+// Weighted sum of the signals, capped at 1. No single signal reaches the threshold
+// except a whitespace-only PR.
+pub fn score_spam(signals: &SpamSignals) -> f32 {
+    let mut score = 0.0;
+    if signals.creator_is_assignee {
+        score += 0.3;
+    }
+    if signals.whitespace_only {
+        score += 0.5;
+    } else if signals.tiny_diff {
+        score += 0.2;
+    }
+    if signals.new_account {
+        score += 0.2;
+    }
+    if signals.bulk_issues {
+        score += 0.25;
+    }
+    match signals.llm_verdict.as_deref() {
+        Some("spam") => score += 0.35,
+        Some("low_effort") => score += 0.15,
+        _ => {}
+    }
+    f32::min(score, 1.0)
+}
+
+// Whether the lines a unified diff removes and adds are the same once whitespace is
+// dropped, in any order. A patch that changes nothing is not whitespace-only. GitHub's
+// per-file patches have no file headers, a "+++" or "---" line is content.
+pub fn is_whitespace_only_patch(patch: &str) -> bool {
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for line in patch.lines() {
+        let (side, text) = match line.chars().next() {
+            Some('+') => (&mut added, &line[1..]),
+            Some('-') => (&mut removed, &line[1..]),
+            _ => continue,
+        };
+        side.push(text.split_whitespace().collect::<String>());
+    }
+    if removed.is_empty() && added.is_empty() {
+        return false;
+    }
+
+    removed.retain(|l| !l.is_empty());
+    added.retain(|l| !l.is_empty());
+    removed.sort();
+    added.sort();
+    removed == added
+}
+
+// Binary files come without a patch and count as real changes. Pages through the files
+// until one has a real change; GitHub lists at most 3000 files of a PR.
+async fn pull_is_whitespace_only(pull_id: &str) -> anyhow::Result<bool> {
+    #[derive(Deserialize)]
+    struct PullFile {
+        patch: Option<String>,
+    }
+
+    let (owner, repo, number) = parse_issue_id(pull_id)?;
+    let mut file_count = 0;
+    for page in 1..=30 {
+        let url = format!(
+            "https://api.github.com/repos/{owner}/{repo}/pulls/{number}/files?per_page=100&page={page}"
+        );
+        let res = github_http_request_ok(Method::GET, &url, None).await?;
+        let files: Vec<PullFile> = serde_json::from_slice(&res)?;
+
+        if !files
+            .iter()
+            .all(|f| f.patch.as_deref().map_or(false, is_whitespace_only_patch))
+        {
+            return Ok(false);
+        }
+        file_count += files.len();
+        if files.len() < 100 {
+            break;
+        }
+    }
+
+    Ok(file_count > 0)
+}
+
+async fn classify_issue_llm(title: &str, description: &str) -> anyhow::Result<Option<String>> {
+    #[derive(Deserialize)]
+    struct LlmVerdict {
+        verdict: String,
+    }
+
+    let system_prompt = r#"
+    You screen GitHub issues opened during a paid open source campaign. Decide whether the issue asks for real work on the project ("genuine"), is a trivial change made up to collect a reward such as fixing a typo or adding one's name to a list ("low_effort"), or is not about the project at all ("spam"). Reply with RFC8259-compliant JSON only:
+    { "verdict": "genuine", "low_effort" or "spam" }"#;
+
+    let input = format!("{}\n\n{}", title, description)
+        .chars()
+        .take(3000)
+        .collect::<String>();
+    let reply = chat_for_task(LlmTask::Classification, system_prompt, &input, 30).await?;
+
+    let json_str = match extract_json_object(&reply) {
+        Some(j) => j,
+        None => return Ok(None),
+    };
+    let parsed: LlmVerdict = serde_json::from_str(json_str)?;
+
+    match parsed.verdict.as_str() {
+        "genuine" | "low_effort" | "spam" => Ok(Some(parsed.verdict)),
+        _ => Ok(None),
+    }
+}
+
+// Looks up when the accounts were created for logins not seen before, one GraphQL
+// request for all of them, and keeps the dates in github_accounts.
+async fn fill_join_dates(pool: &Pool, logins: &[String]) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let known: Vec<String> = conn
+        .exec_map(
+            r"SELECT login FROM github_accounts WHERE JSON_CONTAINS(:logins, JSON_QUOTE(login))",
+            params! {
+                "logins" => serde_json::json!(logins).to_string(),
+            },
+            |login: String| login,
+        )
+        .await?;
+    let missing: Vec<&String> = logins.iter().filter(|l| !known.contains(l)).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let fields = missing
+        .iter()
+        .enumerate()
+        .map(|(n, login)| format!("u{}: user(login: {:?}) {{ login createdAt }}", n, login))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let res = github_http_post_gql(&format!("query {{ {} }}", fields)).await?;
+    let response: serde_json::Value = serde_json::from_slice(&res)?;
+
+    // organizations and deleted users come back as null
+    let joined: Vec<(String, String)> = response["data"]
+        .as_object()
+        .map(|users| {
+            users
+                .values()
+                .filter_map(|u| {
+                    let login = u["login"].as_str()?;
+                    let created_at = convert_datetime(u["createdAt"].as_str()?).ok()?;
+                    Some((login.to_string(), created_at))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    r"INSERT INTO github_accounts (login, date_joined) VALUES (:login, :date_joined)
+    ON DUPLICATE KEY UPDATE date_joined = VALUES(date_joined)"
+        .with(joined.iter().map(|(login, date_joined)| {
+            params! {
+                "login" => login,
+                "date_joined" => date_joined,
+            }
+        }))
+        .batch(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+// Scores new issues, and scores an issue again once its linked PR has been saved to
+// pull_requests and can be looked at.
+// The LLM is only asked when SPAM_LLM_CHECK is set, and only once per issue.
+pub async fn score_issues_for_spam(pool: &Pool) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let rows: Vec<Row> = conn
+        .query(format!(
+            "SELECT im.issue_id, im.issue_title, im.issue_description, im.issue_creator,
+                im.issue_assignees, im.issue_linked_pr, im.spam_signals,
+                COALESCE(JSON_CONTAINS(im.issue_assignees, JSON_QUOTE(im.issue_creator)), 0) AS creator_is_assignee,
+                (SELECT COUNT(*) FROM issues_master b
+                    WHERE b.issue_creator = im.issue_creator AND b.project_id = im.project_id
+                    AND b.campaign <=> im.campaign) >= {} AS bulk_issues,
+                pr.additions + pr.deletions AS pull_changes,
+                pr.pull_id
+            FROM issues_master im
+            LEFT JOIN pull_requests pr ON pr.pull_id = im.issue_linked_pr
+            WHERE im.date_spam_checked IS NULL
+            OR (pr.pull_id IS NOT NULL
+                AND JSON_UNQUOTE(JSON_EXTRACT(im.spam_signals, '$.pull_checked')) = 'false')
+            ORDER BY im.date_spam_checked ASC
+            LIMIT 20",
+            BULK_ISSUE_COUNT
+        ))
+        .await?;
+    log::info!("Issues to score for spam, count: {:?}", rows.len());
+
+    let use_llm = std::env::var("SPAM_LLM_CHECK")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    for row in rows {
+        let issue_id: String = row.get("issue_id").unwrap_or_default();
+        let issue_title: String = row.get("issue_title").unwrap_or_default();
+        let issue_description: String = row.get("issue_description").unwrap_or_default();
+        let issue_creator: String = row.get("issue_creator").unwrap_or_default();
+        let assignees: Vec<String> = row
+            .get::<Option<String>, _>("issue_assignees")
+            .unwrap_or(None)
+            .and_then(|a| serde_json::from_str(&a).ok())
+            .unwrap_or_default();
+        let previous: Option<SpamSignals> = row
+            .get::<Option<String>, _>("spam_signals")
+            .unwrap_or(None)
+            .and_then(|s| serde_json::from_str(&s).ok());
+
+        let mut signals = SpamSignals {
+            creator_is_assignee: row
+                .get::<bool, _>("creator_is_assignee")
+                .unwrap_or_default(),
+            bulk_issues: row.get::<bool, _>("bulk_issues").unwrap_or_default(),
+            ..Default::default()
+        };
+
+        // the linked PR is only scored once it has been saved to pull_requests
+        if let Some(pull_id) = row.get::<Option<String>, _>("pull_id").unwrap_or(None) {
+            let changes: Option<i32> = row.get::<Option<i32>, _>("pull_changes").unwrap_or(None);
+            signals.tiny_diff = changes.map_or(false, |c| c < LOW_EFFORT_CHANGES);
+            signals.whitespace_only = match pull_is_whitespace_only(&pull_id).await {
+                Ok(w) => w,
+                Err(e) => {
+                    log::error!("Error checking diff of {}: {:?}", pull_id, e);
+                    false
+                }
+            };
+            signals.pull_checked = true;
+        }
+
+        let mut logins = assignees.clone();
+        if !logins.contains(&issue_creator) {
+            logins.push(issue_creator.clone());
+        }
+        if let Err(e) = fill_join_dates(pool, &logins).await {
+            log::error!("Error getting account dates for {}: {:?}", issue_id, e);
+        }
+        let new_accounts: Option<i64> = conn
+            .exec_first(
+                r"SELECT COUNT(*) FROM github_accounts ga
+                JOIN issues_master im ON im.issue_id = :issue_id
+                WHERE JSON_CONTAINS(:logins, JSON_QUOTE(ga.login))
+                AND ga.date_joined > DATE_SUB(COALESCE(im.date_issue_assigned, NOW()), INTERVAL :days DAY)",
+                params! {
+                    "issue_id" => &issue_id,
+                    "logins" => serde_json::json!(logins).to_string(),
+                    "days" => NEW_ACCOUNT_DAYS,
+                },
+            )
+            .await?;
+        signals.new_account = new_accounts.unwrap_or(0) > 0;
+
+        signals.llm_verdict = match previous.and_then(|p| p.llm_verdict) {
+            Some(verdict) => Some(verdict),
+            None if use_llm => classify_issue_llm(&issue_title, &issue_description)
+                .await
+                .unwrap_or_else(|e| {
+                    log::error!("Error classifying {} with llm: {:?}", issue_id, e);
+                    None
+                }),
+            None => None,
+        };
+
+        let score = score_spam(&signals);
+        if let Err(e) = conn
+            .exec_drop(
+                r"UPDATE issues_master
+                SET spam_score = :spam_score, spam_signals = :spam_signals, date_spam_checked = NOW()
+                WHERE issue_id = :issue_id",
+                params! {
+                    "issue_id" => &issue_id,
+                    "spam_score" => score,
+                    "spam_signals" => serde_json::json!(signals).to_string(),
+                },
+            )
+            .await
+        {
+            log::error!("Error saving spam score of {}: {:?}", issue_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_signals_stay_below_threshold() {
+        let below = [
+            SpamSignals {
+                creator_is_assignee: true,
+                ..Default::default()
+            },
+            SpamSignals {
+                tiny_diff: true,
+                ..Default::default()
+            },
+            SpamSignals {
+                new_account: true,
+                ..Default::default()
+            },
+            SpamSignals {
+                bulk_issues: true,
+                ..Default::default()
+            },
+            SpamSignals {
+                llm_verdict: Some(String::from("spam")),
+                ..Default::default()
+            },
+        ];
+        for signals in below.iter() {
+            assert!(score_spam(signals) < SPAM_THRESHOLD, "{:?}", signals);
+        }
+
+        let whitespace = SpamSignals {
+            whitespace_only: true,
+            ..Default::default()
+        };
+        assert!(score_spam(&whitespace) >= SPAM_THRESHOLD);
+    }
+
+    #[test]
+    fn score_adds_up_and_is_capped() {
+        let self_assigned_typo = SpamSignals {
+            creator_is_assignee: true,
+            tiny_diff: true,
+            ..Default::default()
+        };
+        assert!(score_spam(&self_assigned_typo) >= SPAM_THRESHOLD);
+
+        // a whitespace-only PR is not counted as tiny as well
+        let whitespace = SpamSignals {
+            whitespace_only: true,
+            tiny_diff: true,
+            ..Default::default()
+        };
+        assert_eq!(score_spam(&whitespace), 0.5);
+
+        let everything = SpamSignals {
+            creator_is_assignee: true,
+            whitespace_only: true,
+            new_account: true,
+            bulk_issues: true,
+            llm_verdict: Some(String::from("spam")),
+            ..Default::default()
+        };
+        assert_eq!(score_spam(&everything), 1.0);
+
+        let genuine = SpamSignals {
+            llm_verdict: Some(String::from("genuine")),
+            ..Default::default()
+        };
+        assert_eq!(score_spam(&genuine), 0.0);
+    }
+
+    #[test]
+    fn whitespace_only_patches() {
+        assert!(is_whitespace_only_patch(
+            "@@ -1,3 +1,3 @@\n fn main() {\n-    let a=1;\n+    let a = 1;\n }"
+        ));
+        // reindented and moved lines, and blank lines added
+        assert!(is_whitespace_only_patch(
+            "@@ -1,2 +1,3 @@\n-a\n-\tb\n+  b\n+\n+a"
+        ));
+        assert!(is_whitespace_only_patch(
+            "@@ -1 +1 @@\n-trailing  \n+trailing"
+        ));
+    }
+
+    #[test]
+    fn real_changes_are_not_whitespace_only() {
+        assert!(!is_whitespace_only_patch(
+            "@@ -1 +1 @@\n-let a = 1;\n+let a = 2;"
+        ));
+        assert!(!is_whitespace_only_patch("@@ -0,0 +1 @@\n+new line"));
+        assert!(!is_whitespace_only_patch("@@ -1 +0,0 @@\n-old line"));
+        // nothing changed at all
+        assert!(!is_whitespace_only_patch("@@ -1 +1 @@\n context"));
+        assert!(!is_whitespace_only_patch(""));
+    }
+
+    #[test]
+    fn marker_like_lines_are_content() {
+        // a removed "-- comment" line and an added "++ counter" line
+        assert!(!is_whitespace_only_patch(
+            "@@ -1,2 +1,2 @@\n--- comment\n+++ counter\n x"
+        ));
+        assert!(is_whitespace_only_patch(
+            "@@ -1 +1 @@\n---  comment\n+-- comment"
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{bot_templates::*, db_manipulate::*, issue_bot::*};
+use chrono::{Duration, Utc};
+use mysql_async::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StaleStage {
+    Active,
+    Reminder,
+    FinalWarning,
+    Release,
+}
+
+// Days of inactivity after which an assignee is reminded, warned, and finally un-assigned.
+#[derive(Clone, Debug)]
+pub struct StalenessPolicy {
+    pub reminder_after_days: i64,
+    pub final_warning_after_days: i64,
+    pub release_after_days: i64,
+}
+
+impl Default for StalenessPolicy {
+    fn default() -> Self {
+        StalenessPolicy {
+            reminder_after_days: 14,
+            final_warning_after_days: 21,
+            release_after_days: 28,
+        }
+    }
+}
+
+impl StalenessPolicy {
+    // Reads STALE_REMINDER_DAYS, STALE_FINAL_WARNING_DAYS and STALE_RELEASE_DAYS,
+    // falling back to the defaults for missing or unparsable values.
+    pub fn from_env() -> Self {
+        let default = StalenessPolicy::default();
+        let read = |name: &str, fallback: i64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse::<i64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(fallback)
+        };
+
+        let policy = StalenessPolicy {
+            reminder_after_days: read("STALE_REMINDER_DAYS", default.reminder_after_days),
+            final_warning_after_days: read(
+                "STALE_FINAL_WARNING_DAYS",
+                default.final_warning_after_days,
+            ),
+            release_after_days: read("STALE_RELEASE_DAYS", default.release_after_days),
+        };
+
+        if policy.reminder_after_days < policy.final_warning_after_days
+            && policy.final_warning_after_days < policy.release_after_days
+        {
+            policy
+        } else {
+            log::error!(
+                "Staleness thresholds must be increasing, got {:?}, using defaults",
+                policy
+            );
+            default
+        }
+    }
+
+    // Days an assignee keeps the issue after the final warning was posted.
+    pub fn grace_days(&self) -> i64 {
+        self.release_after_days - self.final_warning_after_days
+    }
+
+    // `warned_days_ago` is the age of the posted final warning, if any. An assignee is only
+    // released once that warning was posted within the current idle stretch and the grace
+    // period has passed since; until then they stay at the final warning.
+    pub fn stage_for(&self, idle_days: i64, warned_days_ago: Option<i64>) -> StaleStage {
+        if idle_days < self.reminder_after_days {
+            return StaleStage::Active;
+        }
+        if idle_days < self.final_warning_after_days {
+            return StaleStage::Reminder;
+        }
+        match warned_days_ago {
+            Some(warned)
+                if warned <= idle_days
+                    && warned >= self.grace_days()
+                    && idle_days >= self.release_after_days =>
+            {
+                StaleStage::Release
+            }
+            _ => StaleStage::FinalWarning,
+        }
+    }
+
+    // The earliest release date for an issue warned today.
+    pub fn release_in_days(&self, idle_days: i64) -> i64 {
+        (self.release_after_days - idle_days).max(self.grace_days())
+    }
+}
+
+// Reminders go through the notifications outbox, which keeps one row per issue and kind,
+// so running this every hour sends each reminder only once per assignment.
+pub async fn note_stale_assignments(pool: &Pool) -> anyhow::Result<()> {
+    let policy = StalenessPolicy::from_env();
+    let stale = get_stale_assignments(pool, policy.reminder_after_days).await?;
+    log::info!("Stale assignments, count: {:?}", stale.len());
+
+    for (issue_id, idle_days, noticed_days_ago, mut warned_days_ago) in stale {
+        // notices older than the idle stretch belong to an earlier one, the assignee has been
+        // active since and goes through reminder and final warning again
+        if noticed_days_ago.is_some_and(|d| d > idle_days) {
+            if let Err(e) = clear_stale_notifications(pool, &issue_id).await {
+                log::error!("Error resetting stale notices of {}: {:?}", issue_id, e);
+                continue;
+            }
+            warned_days_ago = None;
+        }
+
+        let res = match policy.stage_for(idle_days, warned_days_ago) {
+            StaleStage::Active => Ok(()),
+            StaleStage::Reminder => {
+                queue_bot_message(pool, NotificationKind::StaleReminder, &issue_id).await
+            }
+            StaleStage::FinalWarning => {
+                let release_date =
+                    Utc::now() + Duration::try_days(policy.release_in_days(idle_days)).unwrap();
+                let deadline = release_date.format("%Y-%m-%d").to_string();
+                queue_bot_message_with_vars(
+                    pool,
+                    NotificationKind::StaleFinalWarning,
+                    &issue_id,
+                    vec![("deadline", deadline)],
+                )
+                .await
+            }
+            StaleStage::Release => release_and_notify(pool, &issue_id).await,
+        };
+
+        if let Err(e) = res {
+            log::error!("Error handling stale assignment {}: {:?}", issue_id, e);
+        }
+    }
+    Ok(())
+}
+
+// The message is rendered before the release clears the assignee, and queued after the
+// old stale_* rows of the previous assignment are dropped.
+async fn release_and_notify(pool: &Pool, issue_id: &str) -> anyhow::Result<()> {
+    let comment = render_bot_message(pool, &NotificationKind::StaleReleased, issue_id).await?;
+    // unassign on GitHub first, otherwise the next assignment sync would put them back;
+    // on failure the release is retried in the next run
+    let assignees = get_issue_assignees(pool, issue_id).await?;
+    if !assignees.is_empty() {
+        unassign_users_from_issue(issue_id, &assignees).await?;
+    }
+    release_stale_assignment(pool, issue_id).await?;
+    // drop the notices of this assignment, the next assignee gets their own
+    clear_stale_notifications(pool, issue_id).await?;
+    enqueue_notification(pool, issue_id, NotificationKind::StaleReleased, &comment).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> StalenessPolicy {
+        StalenessPolicy::default()
+    }
+
+    #[test]
+    fn stages_follow_the_thresholds() {
+        let p = policy();
+        assert_eq!(p.stage_for(0, None), StaleStage::Active);
+        assert_eq!(p.stage_for(13, None), StaleStage::Active);
+        assert_eq!(p.stage_for(14, None), StaleStage::Reminder);
+        assert_eq!(p.stage_for(20, None), StaleStage::Reminder);
+        assert_eq!(p.stage_for(21, None), StaleStage::FinalWarning);
+    }
+
+    #[test]
+    fn never_releases_without_a_posted_final_warning() {
+        let p = policy();
+        assert_eq!(p.stage_for(28, None), StaleStage::FinalWarning);
+        assert_eq!(p.stage_for(90, None), StaleStage::FinalWarning);
+    }
+
+    #[test]
+    fn releases_after_the_grace_period() {
+        let p = policy();
+        assert_eq!(p.stage_for(28, Some(7)), StaleStage::Release);
+        assert_eq!(p.stage_for(40, Some(10)), StaleStage::Release);
+        // warned too recently, e.g. the warning was posted late
+        assert_eq!(p.stage_for(28, Some(6)), StaleStage::FinalWarning);
+        assert_eq!(p.stage_for(35, Some(1)), StaleStage::FinalWarning);
+        // warned early but not idle long enough yet
+        let early = StalenessPolicy {
+            reminder_after_days: 2,
+            final_warning_after_days: 3,
+            release_after_days: 10,
+        };
+        assert_eq!(early.stage_for(9, Some(7)), StaleStage::FinalWarning);
+    }
+
+    #[test]
+    fn ignores_warnings_from_an_earlier_idle_stretch() {
+        let p = policy();
+        assert_eq!(p.stage_for(28, Some(40)), StaleStage::FinalWarning);
+    }
+
+    #[test]
+    fn release_date_leaves_the_grace_period() {
+        let p = policy();
+        assert_eq!(p.release_in_days(21), 7);
+        assert_eq!(p.release_in_days(30), 7);
+        let long = StalenessPolicy {
+            reminder_after_days: 2,
+            final_warning_after_days: 3,
+            release_after_days: 10,
+        };
+        assert_eq!(long.release_in_days(3), 7);
+        assert_eq!(long.release_in_days(5), 7);
+    }
+}
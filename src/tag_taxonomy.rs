@@ -0,0 +1,458 @@
+use crate::llm_provider::{chat_for_task, LlmTask};
+use crate::llm_usage::spend_pause_reason;
+use crate::llm_utils::extract_json_object;
+use crate::prompts::TAG_MERGE_PROMPT;
+use crate::vector_search::{cosine_similarity, embed_texts};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// tags at least this close are merged outright, between the two thresholds only when
+// TAG_MERGE_LLM_CHECK is set and the LLM agrees they mean the same
+pub static TAG_MERGE_THRESHOLD: f32 = 0.92;
+pub static TAG_MERGE_LLM_THRESHOLD: f32 = 0.85;
+pub static MAX_TAG_LEN: usize = 100;
+
+// short forms the LLM uses interchangeably with the full word
+static WORD_EXPANSIONS: [(&str, &str); 8] = [
+    ("dev", "development"),
+    ("docs", "documentation"),
+    ("doc", "documentation"),
+    ("js", "javascript"),
+    ("ts", "typescript"),
+    ("ml", "machine learning"),
+    ("db", "database"),
+    ("ui", "user interface"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i32,
+    pub issue_count: i32,
+    pub project_count: i32,
+    pub aliases: Vec<String>,
+}
+
+// "Web-Dev", "web_dev" and " web  dev." all become "web development"; text in brackets
+// is dropped, it is usually the abbreviation of the words before it.
+pub fn normalize_tag(raw: &str) -> String {
+    let mut depth = 0;
+    let mut spaced = String::new();
+    for c in raw.to_lowercase().chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            '-' | '_' | '/' if depth == 0 => spaced.push(' '),
+            _ if depth == 0 => spaced.push(c),
+            _ => {}
+        }
+    }
+
+    let words: Vec<&str> = spaced
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric() && c != '+' && c != '#'))
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            WORD_EXPANSIONS
+                .iter()
+                .find(|(short, _)| *short == w)
+                .map_or(w, |(_, long)| *long)
+        })
+        .collect();
+
+    words.join(" ").chars().take(MAX_TAG_LEN).collect()
+}
+
+// Aliases are looked up by letters and digits only, so "web development" and
+// "webdevelopment" are the same alias.
+pub fn tag_key(tag: &str) -> String {
+    tag.chars()
+        .filter(|c| c.is_alphanumeric() || *c == '+' || *c == '#')
+        .collect()
+}
+
+async fn load_alias_map(conn: &mut Conn) -> Result<HashMap<String, String>> {
+    let aliases: Vec<(String, String)> = conn
+        .query(
+            r"SELECT a.alias_key, t.tag_name FROM tag_aliases a
+            JOIN tags t ON t.tag_id = a.tag_id",
+        )
+        .await?;
+
+    Ok(aliases.into_iter().collect())
+}
+
+// Canonical names for `keywords` without recording anything, unknown ones are only
+// normalized; for looking tags up from a request.
+pub async fn lookup_canonical_tags(pool: &Pool, keywords: &[String]) -> Result<Vec<String>> {
+    let mut conn = pool.get_conn().await?;
+    let alias_map = load_alias_map(&mut conn).await?;
+
+    let mut tags: Vec<String> = Vec::new();
+    for keyword in keywords {
+        let name = normalize_tag(keyword);
+        if name.is_empty() {
+            continue;
+        }
+        let tag = alias_map.get(&tag_key(&name)).cloned().unwrap_or(name);
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    Ok(tags)
+}
+
+// Maps the keywords of summaries not mapped yet to canonical tags, creating a tag for
+// each keyword never seen before. Merges clear canonical_tags of the rows they touch.
+pub async fn canonicalize_summary_tags(pool: &Pool) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+    let mut alias_map = load_alias_map(&mut conn).await?;
+
+    let rows: Vec<(String, Option<String>)> = conn
+        .query(
+            r"SELECT issue_or_project_id, keyword_tags FROM issues_repos_summarized
+            WHERE canonical_tags IS NULL LIMIT 200",
+        )
+        .await?;
+    log::info!(
+        "Summaries to map to canonical tags, count: {:?}",
+        rows.len()
+    );
+
+    for (issue_or_project_id, keyword_tags) in rows {
+        let keywords: Vec<String> = keyword_tags
+            .and_then(|k| serde_json::from_str(&k).ok())
+            .unwrap_or_default();
+
+        let mut tags: Vec<String> = Vec::new();
+        for keyword in keywords {
+            let name = normalize_tag(&keyword);
+            if name.is_empty() {
+                continue;
+            }
+            let key = tag_key(&name);
+
+            let tag = match alias_map.get(&key) {
+                Some(tag) => tag.clone(),
+                None => {
+                    conn.exec_drop(
+                        r"INSERT IGNORE INTO tags (tag_name) VALUES (:tag_name)",
+                        params! { "tag_name" => &name },
+                    )
+                    .await?;
+                    // the collation ignores case and accents, so the insert can be skipped
+                    // for a row spelled differently; that stored spelling is the tag
+                    let stored: Option<(i32, String)> = conn
+                        .exec_first(
+                            r"SELECT tag_id, tag_name FROM tags WHERE tag_name = :tag_name",
+                            params! { "tag_name" => &name },
+                        )
+                        .await?;
+                    let Some((tag_id, tag_name)) = stored else {
+                        log::error!("Tag {} was neither inserted nor found", name);
+                        continue;
+                    };
+                    conn.exec_drop(
+                        r"INSERT IGNORE INTO tag_aliases (alias_key, alias, tag_id, source)
+                        VALUES (:alias_key, :alias, :tag_id, 'new')",
+                        params! {
+                            "alias_key" => &key,
+                            "alias" => &name,
+                            "tag_id" => tag_id,
+                        },
+                    )
+                    .await?;
+                    alias_map.insert(key, tag_name.clone());
+                    tag_name
+                }
+            };
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        if let Err(e) = conn
+            .exec_drop(
+                r"UPDATE issues_repos_summarized SET canonical_tags = :canonical_tags
+                WHERE issue_or_project_id = :issue_or_project_id",
+                params! {
+                    "issue_or_project_id" => &issue_or_project_id,
+                    "canonical_tags" => serde_json::json!(tags).to_string(),
+                },
+            )
+            .await
+        {
+            log::error!(
+                "Error saving canonical tags of {}: {:?}",
+                issue_or_project_id,
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn llm_says_same_tag(a: &str, b: &str) -> anyhow::Result<bool> {
+    #[derive(Deserialize)]
+    struct Verdict {
+        same: bool,
+    }
+
+    let input = format!("Tag A: {}\nTag B: {}", a, b);
+    let reply = chat_for_task(LlmTask::Classification, TAG_MERGE_PROMPT, &input, 20).await?;
+    let json_str = match extract_json_object(&reply) {
+        Some(j) => j,
+        None => return Ok(false),
+    };
+
+    Ok(serde_json::from_str::<Verdict>(json_str)?.same)
+}
+
+// Points every alias of `from` at `into` and drops `from`; summaries carrying `from` are
+// mapped again on the next canonicalize_summary_tags.
+async fn merge_tag(
+    conn: &mut Conn,
+    from: &(i32, String),
+    into: &(i32, String),
+    source: &str,
+    similarity: f32,
+) -> Result<()> {
+    let queries = vec![
+        r"UPDATE tag_aliases SET tag_id = :into_id, source = :source, similarity = :similarity
+        WHERE tag_id = :from_id",
+        r"DELETE FROM tags WHERE tag_id = :from_id",
+        r"UPDATE issues_repos_summarized SET canonical_tags = NULL
+        WHERE JSON_CONTAINS(canonical_tags, JSON_QUOTE(:from_name))",
+    ];
+
+    let mut tx = conn.start_transaction(TxOpts::default()).await?;
+    for query in queries {
+        tx.exec_drop(
+            query,
+            params! {
+                "from_id" => from.0,
+                "from_name" => &from.1,
+                "into_id" => into.0,
+                "source" => source,
+                "similarity" => similarity,
+            },
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    log::info!("Merged tag {} into {} ({:.2})", from.1, into.1, similarity);
+
+    Ok(())
+}
+
+// Embeds tags created since the last run and merges each into its nearest existing tag
+// when they are close enough. The tag used by fewer summaries goes into the other one.
+// Waits while a spend limit is reached, the new tags are picked up once it clears.
+pub async fn merge_similar_tags(pool: &Pool) -> anyhow::Result<()> {
+    if let Some(reason) = spend_pause_reason(pool).await? {
+        log::info!("Not merging tags, {}", reason);
+        return Ok(());
+    }
+    let mut conn = pool.get_conn().await?;
+
+    let pending: Vec<(i32, String)> = conn
+        .query("SELECT tag_id, tag_name FROM tags WHERE embedding IS NULL LIMIT 100")
+        .await?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let embeddings = embed_texts(pending.iter().map(|(_, name)| name.clone()).collect()).await?;
+    for ((tag_id, _), embedding) in pending.iter().zip(embeddings.iter()) {
+        conn.exec_drop(
+            r"UPDATE tags SET embedding = :embedding WHERE tag_id = :tag_id",
+            params! {
+                "tag_id" => tag_id,
+                "embedding" => serde_json::json!(embedding).to_string(),
+            },
+        )
+        .await?;
+    }
+
+    let rows: Vec<(i32, String, String)> = conn
+        .query(
+            r"SELECT tag_id, tag_name, embedding FROM tags
+            WHERE embedding IS NOT NULL ORDER BY tag_id",
+        )
+        .await?;
+    let mut tags: Vec<((i32, String), Vec<f32>)> = rows
+        .into_iter()
+        .filter_map(|(id, name, embedding)| {
+            Some(((id, name), serde_json::from_str(&embedding).ok()?))
+        })
+        .collect();
+
+    let usage: HashMap<String, i32> = conn
+        .query(
+            r"SELECT tag, COUNT(*) FROM issues_repos_summarized,
+            JSON_TABLE(canonical_tags, '$[*]' COLUMNS(tag VARCHAR(100) PATH '$')) AS t
+            GROUP BY tag",
+        )
+        .await?
+        .into_iter()
+        .collect();
+
+    let use_llm = std::env::var("TAG_MERGE_LLM_CHECK")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    for (pending_id, _) in &pending {
+        let Some(pos) = tags.iter().position(|((id, _), _)| id == pending_id) else {
+            continue;
+        };
+        let best = tags
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != pos)
+            .map(|(i, (_, embedding))| (i, cosine_similarity(&tags[pos].1, embedding)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((other, similarity)) = best else {
+            continue;
+        };
+
+        let (tag, other_tag) = (&tags[pos].0, &tags[other].0);
+        let source = if similarity >= TAG_MERGE_THRESHOLD {
+            "embedding"
+        } else if use_llm && similarity >= TAG_MERGE_LLM_THRESHOLD {
+            match llm_says_same_tag(&tag.1, &other_tag.1).await {
+                Ok(true) => "llm",
+                Ok(false) => continue,
+                Err(e) => {
+                    log::error!(
+                        "Error asking llm about {} and {}: {:?}",
+                        tag.1,
+                        other_tag.1,
+                        e
+                    );
+                    continue;
+                }
+            }
+        } else {
+            continue;
+        };
+
+        // ties go to the older tag
+        let uses = |t: &(i32, String)| usage.get(&t.1).copied().unwrap_or(0);
+        let (from, into) = if (uses(tag), -tag.0) > (uses(other_tag), -other_tag.0) {
+            (other, pos)
+        } else {
+            (pos, other)
+        };
+        merge_tag(&mut conn, &tags[from].0, &tags[into].0, source, similarity).await?;
+        tags.remove(from);
+    }
+
+    Ok(())
+}
+
+pub async fn update_tag_taxonomy(pool: &Pool) -> anyhow::Result<()> {
+    canonicalize_summary_tags(pool).await?;
+    merge_similar_tags(pool).await?;
+    canonicalize_summary_tags(pool).await?;
+
+    Ok(())
+}
+
+pub async fn list_tags(pool: &Pool, limit: usize) -> Result<Vec<TagCount>> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = format!(
+        r"SELECT t.tag, COUNT(*) AS tag_count,
+            SUM(irs.issue_or_project_id LIKE '%/issues/%') AS issue_count
+        FROM issues_repos_summarized irs,
+        JSON_TABLE(irs.canonical_tags, '$[*]' COLUMNS(tag VARCHAR(100) PATH '$')) AS t
+        GROUP BY t.tag
+        ORDER BY tag_count DESC, t.tag ASC
+        LIMIT {}",
+        limit
+    );
+    let counts: Vec<(String, i32, i32)> = conn.query(query).await?;
+
+    let aliases: Vec<(String, String)> = conn
+        .query(
+            r"SELECT t.tag_name, a.alias FROM tag_aliases a
+            JOIN tags t ON t.tag_id = a.tag_id
+            WHERE a.alias <> t.tag_name
+            ORDER BY a.alias",
+        )
+        .await?;
+    let mut alias_map: HashMap<String, Vec<String>> = HashMap::new();
+    for (tag, alias) in aliases {
+        alias_map.entry(tag).or_default().push(alias);
+    }
+
+    Ok(counts
+        .into_iter()
+        .map(|(tag, count, issue_count)| TagCount {
+            aliases: alias_map.remove(&tag).unwrap_or_default(),
+            tag,
+            count,
+            issue_count,
+            project_count: count - issue_count,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separators_and_case_are_normalized() {
+        assert_eq!(normalize_tag("Web-Dev"), "web development");
+        assert_eq!(normalize_tag("web_dev"), "web development");
+        assert_eq!(normalize_tag(" web  dev."), "web development");
+        assert_eq!(normalize_tag("CI/CD"), "ci cd");
+    }
+
+    #[test]
+    fn bracketed_text_is_dropped() {
+        assert_eq!(normalize_tag("Machine Learning (ML)"), "machine learning");
+        assert_eq!(
+            normalize_tag("Natural Language Processing [NLP] tools"),
+            "natural language processing tools"
+        );
+        // a stray closing bracket does not swallow the rest
+        assert_eq!(normalize_tag("rust) async"), "rust async");
+        assert_eq!(normalize_tag("(only brackets)"), "");
+    }
+
+    #[test]
+    fn abbreviations_are_expanded_as_whole_words() {
+        assert_eq!(normalize_tag("JS"), "javascript");
+        assert_eq!(normalize_tag("ML ops"), "machine learning ops");
+        assert_eq!(normalize_tag("docs"), "documentation");
+        assert_eq!(normalize_tag("devops"), "devops");
+    }
+
+    #[test]
+    fn symbols_in_language_names_are_kept() {
+        assert_eq!(normalize_tag("C++"), "c++");
+        assert_eq!(normalize_tag("C#."), "c#");
+        assert_eq!(normalize_tag("\"Rust!\""), "rust");
+        assert_eq!(normalize_tag("Café"), "café");
+    }
+
+    #[test]
+    fn long_tags_are_cut_to_the_column_size() {
+        let tag = normalize_tag(&"é".repeat(MAX_TAG_LEN + 20));
+        assert_eq!(tag.chars().count(), MAX_TAG_LEN);
+    }
+
+    #[test]
+    fn alias_keys_ignore_spacing_and_punctuation() {
+        assert_eq!(tag_key("web development"), tag_key("webdevelopment"));
+        assert_eq!(tag_key("machine learning"), "machinelearning");
+        assert_eq!(tag_key("c++"), "c++");
+        assert_ne!(tag_key("c++"), tag_key("c#"));
+        assert_eq!(tag_key("node.js"), "nodejs");
+    }
+}
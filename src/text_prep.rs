@@ -0,0 +1,388 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// READMEs above this many tokens are summarized part by part and the notes summarized
+// again, at most MAX_README_CHUNKS parts; the rest of the document is cut.
+pub static README_CHUNK_TOKENS: usize = 1500;
+pub static MAX_README_CHUNKS: usize = 8;
+// issue bodies past this are cut, about the 4000 characters they were cut at before
+pub static ISSUE_INPUT_TOKENS: usize = 1000;
+// kept free in the context window besides the system prompt and the reply
+static CONTEXT_MARGIN_TOKENS: usize = 100;
+
+lazy_static! {
+    // [![build](https://img.shields.io/...)](https://ci...), logos and badges linked somewhere
+    static ref LINKED_IMAGE: Regex = Regex::new(r"\[!\[[^\]]*\]\([^)]*\)\]\([^)]*\)").unwrap();
+    static ref BADGE_IMAGE: Regex = Regex::new(
+        r"(?i)!\[[^\]]*\]\([^)]*(?:shields\.io|badge|badgen|travis-ci|codecov|circleci)[^)]*\)"
+    )
+    .unwrap();
+    static ref IMAGE: Regex = Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap();
+    static ref HTML_COMMENT: Regex = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    static ref HTML_TAG: Regex = Regex::new(r"</?[a-zA-Z][^>]*>").unwrap();
+    static ref BLANK_LINES: Regex = Regex::new(r"\n[ \t]*(?:\n[ \t]*)+").unwrap();
+}
+
+// Rough characters per token for the tokenizer a model family uses, measured on English
+// markdown. Unknown models get the lowest ratio so estimates err on the long side.
+fn chars_per_token(model: &str) -> f32 {
+    let model = model.to_lowercase();
+    if model.contains("llama-3") || model.contains("llama3") {
+        4.2
+    } else if model.starts_with("gpt-4") || model.starts_with("gpt-3.5") {
+        4.0
+    } else if model.contains("llama-2") || model.contains("mistral") || model.contains("mixtral") {
+        3.6
+    } else {
+        3.5
+    }
+}
+
+pub fn context_window(model: &str) -> usize {
+    let model = model.to_lowercase();
+    if model.contains("gpt-4-1106") || model.contains("gpt-4-turbo") {
+        128_000
+    } else if model.contains("32k") {
+        32_768
+    } else if model.contains("16k") {
+        16_384
+    } else if model.starts_with("gpt-4") || model.contains("llama-3") || model.contains("llama3") {
+        8_192
+    } else {
+        4_096
+    }
+}
+
+// An estimate from the character count and chars_per_token, not a tokenizer: code, URLs
+// and non-English text can take noticeably more tokens than this says.
+pub fn estimate_tokens(text: &str, model: &str) -> usize {
+    (text.chars().count() as f32 / chars_per_token(model)).ceil() as usize
+}
+
+// Tokens left for the user input once the system prompt and the reply are accounted for.
+pub fn input_token_budget(model: &str, system_prompt: &str, max_tokens: u16) -> usize {
+    context_window(model).saturating_sub(
+        estimate_tokens(system_prompt, model) + max_tokens as usize + CONTEXT_MARGIN_TOKENS,
+    )
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+// Splits markdown into (is_code, text) runs, an unclosed fence runs to the end.
+fn code_segments(text: &str) -> Vec<(bool, String)> {
+    let mut segments: Vec<(bool, String)> = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        let fence = is_fence(line);
+        let opens = fence && !in_code;
+        if opens {
+            in_code = true;
+        }
+        match segments.last_mut() {
+            Some((is_code, seg)) if *is_code == in_code && !opens => {
+                seg.push('\n');
+                seg.push_str(line);
+            }
+            _ => segments.push((in_code, line.to_string())),
+        }
+        if fence && !opens {
+            in_code = false;
+        }
+    }
+    segments
+}
+
+// Drops badges, HTML comments and tags and the extra blank lines they leave, outside of
+// code blocks. Other images are replaced by their alt text.
+pub fn clean_markdown(text: &str) -> String {
+    let cleaned = code_segments(text)
+        .into_iter()
+        .map(|(is_code, seg)| {
+            if is_code {
+                return seg;
+            }
+            let seg = HTML_COMMENT.replace_all(&seg, "");
+            let seg = LINKED_IMAGE.replace_all(&seg, "");
+            let seg = BADGE_IMAGE.replace_all(&seg, "");
+            let seg = IMAGE.replace_all(&seg, "$1");
+            HTML_TAG.replace_all(&seg, "").to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    BLANK_LINES.replace_all(&cleaned, "\n\n").trim().to_string()
+}
+
+pub fn strip_code_fences(text: &str) -> String {
+    let stripped = code_segments(text)
+        .into_iter()
+        .filter(|(is_code, _)| !is_code)
+        .map(|(_, seg)| seg)
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    BLANK_LINES
+        .replace_all(&stripped, "\n\n")
+        .trim()
+        .to_string()
+}
+
+// Cleans a README for the LLM, and leaves out the code blocks when it is still over
+// `max_tokens`; examples say less about a project than the prose around them.
+pub fn prepare_readme(readme: &str, max_tokens: usize, model: &str) -> String {
+    let cleaned = clean_markdown(readme);
+    if estimate_tokens(&cleaned, model) <= max_tokens {
+        return cleaned;
+    }
+    strip_code_fences(&cleaned)
+}
+
+// Byte offset to cut `text` at so it keeps at most `max_chars`, at the last paragraph,
+// line or word break, and whether the kept part ends inside a code block.
+fn boundary_cut(text: &str, max_chars: usize, starts_in_code: bool) -> (usize, bool) {
+    let end = match text.char_indices().nth(max_chars) {
+        Some((pos, _)) => pos,
+        None => return (text.len(), false),
+    };
+    let head = &text[..end];
+    let half = head.len() / 2;
+
+    let cut = [head.rfind("\n\n"), head.rfind('\n'), head.rfind(' ')]
+        .into_iter()
+        .flatten()
+        .find(|&pos| pos > half)
+        .unwrap_or(head.len());
+    let kept = &head[..cut];
+
+    let fences = kept.lines().filter(|l| is_fence(l)).count();
+    if (fences + starts_in_code as usize) % 2 == 1 {
+        let open_at = kept
+            .rmatch_indices('\n')
+            .map(|(pos, _)| pos)
+            .find(|&pos| is_fence(&kept[pos + 1..]))
+            .unwrap_or(0);
+        if open_at > half {
+            return (open_at, false);
+        }
+        return (cut, true);
+    }
+    (cut, false)
+}
+
+// Never cuts inside a code block: a block left open is cut before its fence, or closed
+// when that would lose more than half of the text.
+pub fn truncate_at_boundary(text: &str, max_chars: usize) -> String {
+    let (cut, in_code) = boundary_cut(text, max_chars, false);
+    let mut out = text[..cut].trim_end().to_string();
+    if in_code {
+        out.push_str("\n```");
+    }
+    out
+}
+
+pub fn truncate_to_tokens(text: &str, max_tokens: usize, model: &str) -> String {
+    let max_chars = (max_tokens as f32 * chars_per_token(model)) as usize;
+    truncate_at_boundary(text, max_chars)
+}
+
+// Packs paragraphs into chunks of at most `max_tokens`, keeping each code block in one
+// paragraph. A paragraph too long for a chunk is cut into pieces of its own.
+pub fn split_into_chunks(text: &str, max_tokens: usize, model: &str) -> Vec<String> {
+    let max_chars = ((max_tokens as f32 * chars_per_token(model)) as usize).max(1);
+
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if is_fence(line) {
+            in_code = !in_code;
+        }
+        if line.trim().is_empty() && !in_code {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+
+    let mut chunks: Vec<String> = Vec::new();
+    let mut chunk = String::new();
+    for paragraph in paragraphs {
+        let len = paragraph.chars().count();
+        if !chunk.is_empty() && chunk.chars().count() + 2 + len > max_chars {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        if len <= max_chars {
+            if !chunk.is_empty() {
+                chunk.push_str("\n\n");
+            }
+            chunk.push_str(&paragraph);
+            continue;
+        }
+
+        // a code block cut in two is closed in one piece and opened again in the next
+        let mut rest = paragraph.as_str();
+        let mut reopen = false;
+        while !rest.is_empty() {
+            let (cut, in_code) = boundary_cut(rest, max_chars, reopen);
+            let mut piece = String::new();
+            if reopen {
+                piece.push_str("```\n");
+            }
+            piece.push_str(rest[..cut].trim_end());
+            if in_code {
+                piece.push_str("\n```");
+            }
+            chunks.push(piece);
+            reopen = in_code;
+            rest = rest[cut..].trim_start();
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fence_count(text: &str) -> usize {
+        text.lines().filter(|l| is_fence(l)).count()
+    }
+
+    #[test]
+    fn badges_and_html_are_stripped() {
+        let readme = "<!-- generated -->\n<h1 align=\"center\">Tool</h1>\n\n[![build](https://ci.example.com/badge.svg)](https://ci.example.com)\n![coverage](https://img.shields.io/codecov/c/x)\n\n\n\nA <b>fast</b> tool.\n\n![diagram of the pipeline](docs/pipeline.png)";
+        assert_eq!(
+            clean_markdown(readme),
+            "Tool\n\nA fast tool.\n\ndiagram of the pipeline"
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_left_alone() {
+        let readme = "Run it:\n\n```html\n<div>![x](https://img.shields.io/x)</div>\n```\n<br>";
+        assert_eq!(
+            clean_markdown(readme),
+            "Run it:\n\n```html\n<div>![x](https://img.shields.io/x)</div>\n```"
+        );
+        assert_eq!(strip_code_fences(readme), "Run it:\n\n<br>");
+    }
+
+    #[test]
+    fn truncation_cuts_at_a_break() {
+        assert_eq!(truncate_at_boundary("short text", 50), "short text");
+        assert_eq!(
+            truncate_at_boundary("first paragraph here\n\nsecond paragraph", 30),
+            "first paragraph here"
+        );
+        assert_eq!(
+            truncate_at_boundary("one two three four five", 16),
+            "one two three"
+        );
+    }
+
+    #[test]
+    fn truncation_keeps_multibyte_characters_whole() {
+        let text = "héllo wörld ünïcödé 東京 タワー 🦀🦀🦀";
+        for max_chars in 1..text.chars().count() {
+            let out = truncate_at_boundary(text, max_chars);
+            assert!(out.chars().count() <= max_chars, "{}: {}", max_chars, out);
+            assert!(text.starts_with(&out));
+        }
+        // no break at all, the cut falls on the last whole character that fits
+        assert_eq!(truncate_at_boundary("ééééééééé", 4), "éééé");
+        assert_eq!(truncate_at_boundary("🦀🦀🦀🦀", 3), "🦀🦀🦀");
+    }
+
+    #[test]
+    fn truncation_never_ends_inside_a_code_block() {
+        // the fence opens late, the block is dropped
+        let text = format!("{}\n```\n{}\n```", "word ".repeat(20), "code ".repeat(20));
+        let out = truncate_at_boundary(&text, 120);
+        assert_eq!(out, "word ".repeat(20).trim_end());
+
+        // the fence opens early, the block is closed where it is cut
+        let text = format!("intro\n```\n{}\n```", "code ".repeat(40));
+        let out = truncate_at_boundary(&text, 60);
+        assert!(out.starts_with("intro\n```\ncode"));
+        assert!(out.ends_with("\n```"));
+        assert_eq!(fence_count(&out) % 2, 0);
+    }
+
+    #[test]
+    fn truncate_to_tokens_stays_in_budget() {
+        let text = "lorem ipsum dolor sit amet ".repeat(100);
+        let out = truncate_to_tokens(&text, 50, "gpt-4");
+        assert!(estimate_tokens(&out, "gpt-4") <= 50);
+        assert!(out.len() > 150);
+    }
+
+    #[test]
+    fn chunks_pack_paragraphs() {
+        let text = "aaaa aaaa\n\nbbbb bbbb\n\ncccc cccc\n\ndddd dddd";
+        // 6 tokens of gpt-4 are 24 characters, two paragraphs and the blank line fit
+        assert_eq!(
+            split_into_chunks(text, 6, "gpt-4"),
+            vec!["aaaa aaaa\n\nbbbb bbbb", "cccc cccc\n\ndddd dddd"]
+        );
+        assert!(split_into_chunks("", 6, "gpt-4").is_empty());
+    }
+
+    #[test]
+    fn code_block_is_closed_and_reopened_across_chunks() {
+        let lines: Vec<String> = (0..30).map(|n| format!("let x{} = {};", n, n)).collect();
+        let text = format!("Example:\n\n```rust\n{}\n```\n\nThe end.", lines.join("\n"));
+        let chunks = split_into_chunks(&text, 40, "gpt-4");
+
+        assert!(chunks.len() > 2);
+        for chunk in &chunks {
+            assert_eq!(
+                fence_count(chunk) % 2,
+                0,
+                "unbalanced fences in {:?}",
+                chunk
+            );
+        }
+        let code_chunks: Vec<&String> = chunks.iter().filter(|c| c.contains("let x")).collect();
+        assert!(code_chunks.len() > 1);
+        for chunk in &code_chunks[1..] {
+            assert!(chunk.starts_with("```\n"), "{:?}", chunk);
+        }
+        // every line of the block ends up in some chunk
+        for line in &lines {
+            assert!(
+                chunks.iter().any(|c| c.lines().any(|l| l == line)),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(chunks.last().unwrap(), "The end.");
+    }
+
+    #[test]
+    fn text_without_any_break_is_still_split() {
+        let text = "x".repeat(1000);
+        let chunks = split_into_chunks(&text, 10, "gpt-4");
+        assert_eq!(chunks.len(), 25);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 40));
+        assert_eq!(chunks.concat(), text);
+
+        let text = "東".repeat(101);
+        let chunks = split_into_chunks(&text, 10, "gpt-4");
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), text);
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{db_join::*, db_manipulate::*, db_populate::*, issue_tracker::*, vector_search::*};
+use crate::{
+    bot_templates::*, budget_recommend::*, db_join::*, db_manipulate::*, db_populate::*,
+    issue_bot::*, issue_tracker::*, llm_usage::*, pr_matcher::*, spam_score::*,
+    stale_assignments::*, tag_taxonomy::*, vector_search::*,
+};
 use crate::{ISSUE_LABEL, NEXT_HOUR, PR_LABEL, START_DATE, THIS_HOUR};
 
 use anyhow::Ok;
@@ -40,6 +44,15 @@ pub fn inner_query_1_hour(
 }
 
 pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
+    let result = run_hourly_steps(pool).await;
+    // llm calls are kept in memory during the run, save them even when a step failed
+    if let Err(e) = flush_llm_usage(pool).await {
+        log::error!("Error flushing llm usage: {:?}", e);
+    }
+    result
+}
+
+async fn run_hourly_steps(pool: &Pool) -> anyhow::Result<()> {
     let _ = popuate_dbs_save_issues_open(pool).await?;
 
     let _ = open_master(pool).await?;
@@ -59,6 +72,8 @@ pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
     let _ = popuate_dbs_save_pull_requests(pool).await?;
 
     let _ = project_master_back_sync(&pool).await?;
+    let _ = classification_master(&pool).await?;
+    let _ = update_tag_taxonomy(pool).await;
 
     let _ = populate_vector_db(pool).await?;
 
@@ -66,7 +81,11 @@ pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
 
     let _ = sum_budget_to_project(&pool).await?;
 
-    let _ = remove_pull_by_issued_linked_pr(&pool).await?;
+    let _ = link_assignee_pulls(&pool).await?;
+    let _ = mark_linked_pulls(&pool).await?;
+    let _ = match_orphan_pulls(pool).await;
+    let _ = score_issues_for_spam(pool).await;
+    let _ = recommend_budgets(pool).await;
     let _ = delete_issues_open_assigned_closed(&pool).await?;
 
     // let _ = note_issues(pool).await?;
@@ -209,10 +228,19 @@ pub async fn popuate_dbs_fill_projects(pool: &Pool) -> anyhow::Result<()> {
 }
 
 pub async fn populate_vector_db(pool: &Pool) -> anyhow::Result<()> {
+    // unindexed summaries stay marked as such and are picked up once spending resumes
+    if let Some(reason) = spend_pause_reason(pool).await? {
+        log::info!("Not indexing, {}", reason);
+        return Ok(());
+    }
     for item in get_issues_repos_from_db().await.expect("msg") {
         log::info!("uploading to vector_db: {:?}", item.0);
-        let _ = upload_to_collection(&item.0, item.1.clone()).await;
-        let _ = mark_id_indexed(&pool, &item.0).await;
+        match upload_to_collection(&item.0, item.1.clone(), item.2).await {
+            Ok(point_id) => {
+                let _ = mark_id_indexed(&pool, &item.0, point_id).await;
+            }
+            Err(e) => log::error!("Error uploading {} to vector_db: {:?}", item.0, e),
+        }
     }
     let _ = check_vector_db("gosim_search").await;
 
@@ -222,8 +250,8 @@ pub async fn populate_vector_db(pool: &Pool) -> anyhow::Result<()> {
 pub async fn note_issues(pool: &Pool) -> anyhow::Result<()> {
     let _ = note_budget_allocated(pool).await?;
     let _ = note_issue_declined(pool).await?;
+    let _ = note_stale_assignments(pool).await?;
     let _ = note_distribute_fund(pool).await?;
-    let _ = note_one_months_no_pr(pool).await?;
     Ok(())
 }
 
@@ -234,9 +262,7 @@ pub async fn note_budget_allocated(pool: &Pool) -> anyhow::Result<()> {
         issue_ids.len()
     );
     for issue_id in issue_ids {
-        let comment = format!("{}/n Congratulations! GOSIM grant approved. Your proposal is approved to get $100 fund to fix the issue.", issue_id);
-
-        // let _ = mock_comment_on_issue(1, &comment).await?;
+        let _ = queue_bot_message(pool, NotificationKind::BudgetAllocated, &issue_id).await;
     }
     Ok(())
 }
@@ -248,32 +274,21 @@ pub async fn note_issue_declined(pool: &Pool) -> anyhow::Result<()> {
         issue_ids.len()
     );
     for issue_id in issue_ids {
-        let comment = format!("{}/n  I’m sorry your proposal wasn't approved", issue_id);
-
-        // let _ = mock_comment_on_issue(2, &comment).await?;
+        let _ = queue_bot_message(pool, NotificationKind::IssueDeclined, &issue_id).await;
     }
     Ok(())
 }
 
 pub async fn note_distribute_fund(pool: &Pool) -> anyhow::Result<()> {
+    // the other notes still go out while the form link is missing
+    if let Err(e) = fund_form_link() {
+        log::error!("{}", e);
+        return Ok(());
+    }
     let issue_ids: Vec<(Option<String>, String, i32)> = get_issue_ids_distribute_fund(pool).await?;
     log::info!("Issue_ids to split fund, count: {:?}", issue_ids.len());
-    for (issue_assignee, _issue_id, issue_budget) in issue_ids {
-        let comment = format!("@{:?}, Well done!  According to the PR commit history. @{:?} should receive ${}. Please fill in this form to claim your fund. ", issue_assignee, issue_assignee, issue_budget);
-
-        // let _ = mock_comment_on_issue(3, &comment).await?;
-    }
-    Ok(())
-}
-
-pub async fn note_one_months_no_pr(pool: &Pool) -> anyhow::Result<()> {
-    let issue_ids = get_issue_ids_one_month_no_activity(pool).await?;
-    log::info!("Issue_ids no activity, count: {:?}", issue_ids.len());
-
-    for issue_id in issue_ids {
-        let comment = format!("{}\n @{} please link your PR to the issue it fixed in three days. Or this issue will be deemed not completed, then we can’t provide the fund.", issue_id, "issue_assignee" );
-
-        // let _ = mock_comment_on_issue(4, &comment).await?;
+    for (_issue_assignee, issue_id, _issue_budget) in issue_ids {
+        let _ = queue_bot_message(pool, NotificationKind::DistributeFund, &issue_id).await;
     }
     Ok(())
 }
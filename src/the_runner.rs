@@ -1,5 +1,11 @@
-use crate::{db_join::*, db_manipulate::*, db_populate::*, issue_tracker::*};
-use crate::{ISSUE_LABEL, NEXT_HOUR, PR_LABEL, START_DATE, THIS_HOUR};
+use crate::{
+    bot_templates::*, budget_recommend::*, db_join::*, db_manipulate::*, db_populate::*,
+    issue_bot::*, issue_tracker::*, llm_usage::*, pr_matcher::*, reconcile::*, spam_score::*,
+    stale_assignments::*, tag_taxonomy::*,
+};
+use crate::{
+    ISSUE_LABEL, NEXT_HOUR, PR_LABEL, RECONCILE_HOUR, START_DATE, THIS_HOUR, TODAY_THIS_HOUR,
+};
 
 use anyhow::Ok;
 use mysql_async::Pool;
@@ -40,10 +46,27 @@ pub fn inner_query_1_hour(
 }
 
 pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
+    let result = run_hourly_steps(pool).await;
+    // llm calls are kept in memory during the run, save them even when a step failed
+    if let Err(e) = flush_llm_usage(pool).await {
+        log::error!("Error flushing llm usage: {:?}", e);
+    }
+    result
+}
+
+async fn run_hourly_steps(pool: &Pool) -> anyhow::Result<()> {
     let _ = popuate_dbs(pool).await?;
     let _ = join_ops(pool).await?;
     let _ = cleanup_ops(pool).await?;
-    // let _ = note_issues(pool).await?;
+    // the hourly windows miss changes made outside them, a daily full pass catches those
+    if *TODAY_THIS_HOUR == RECONCILE_HOUR {
+        let _ = reconcile_issues(pool).await;
+    }
+    // notes queued in earlier runs are sent even when queueing new ones failed
+    if let Err(e) = note_issues(pool).await {
+        log::error!("Error queueing notifications: {:?}", e);
+    }
+    let _ = send_pending_notifications(pool).await?;
     Ok(())
 }
 pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
@@ -166,12 +189,26 @@ pub async fn join_ops(pool: &Pool) -> anyhow::Result<()> {
         let _ = summarize_project_add_in_db(&pool, repo_data).await?;
     }
     let _ = project_master_back_sync(&pool).await?;
+    let _ = classification_master(&pool).await?;
+    if let Err(e) = update_tag_taxonomy(&pool).await {
+        log::error!("Error updating tag taxonomy: {:?}", e);
+    }
 
     Ok(())
 }
 
 pub async fn cleanup_ops(pool: &Pool) -> anyhow::Result<()> {
-    let _ = remove_pull_by_issued_linked_pr(&pool).await?;
+    let _ = link_assignee_pulls(&pool).await?;
+    let _ = mark_linked_pulls(&pool).await?;
+    if let Err(e) = match_orphan_pulls(&pool).await {
+        log::error!("Error matching orphan pull requests: {:?}", e);
+    }
+    if let Err(e) = score_issues_for_spam(&pool).await {
+        log::error!("Error scoring issues for spam: {:?}", e);
+    }
+    if let Err(e) = recommend_budgets(&pool).await {
+        log::error!("Error suggesting budgets: {:?}", e);
+    }
     let _ = delete_issues_open_assigned_closed(&pool).await?;
 
     Ok(())
@@ -180,8 +217,8 @@ pub async fn cleanup_ops(pool: &Pool) -> anyhow::Result<()> {
 pub async fn note_issues(pool: &Pool) -> anyhow::Result<()> {
     let _ = note_budget_allocated(pool).await?;
     let _ = note_issue_declined(pool).await?;
+    let _ = note_stale_assignments(pool).await?;
     let _ = note_distribute_fund(pool).await?;
-    let _ = note_one_months_no_pr(pool).await?;
     Ok(())
 }
 
@@ -192,9 +229,7 @@ pub async fn note_budget_allocated(pool: &Pool) -> anyhow::Result<()> {
         issue_ids.len()
     );
     for issue_id in issue_ids {
-        let comment = format!("{}/n Congratulations! GOSIM grant approved. Your proposal is approved to get $100 fund to fix the issue.", issue_id);
-
-        // let _ = mock_comment_on_issue(1, &comment).await?;
+        let _ = queue_bot_message(pool, NotificationKind::BudgetAllocated, &issue_id).await;
     }
     Ok(())
 }
@@ -206,32 +241,21 @@ pub async fn note_issue_declined(pool: &Pool) -> anyhow::Result<()> {
         issue_ids.len()
     );
     for issue_id in issue_ids {
-        let comment = format!("{}/n  I’m sorry your proposal wasn't approved", issue_id);
-
-        // let _ = mock_comment_on_issue(2, &comment).await?;
+        let _ = queue_bot_message(pool, NotificationKind::IssueDeclined, &issue_id).await;
     }
     Ok(())
 }
 
 pub async fn note_distribute_fund(pool: &Pool) -> anyhow::Result<()> {
+    // the other notes still go out while the form link is missing
+    if let Err(e) = fund_form_link() {
+        log::error!("{}", e);
+        return Ok(());
+    }
     let issue_ids: Vec<(Option<String>, String, i32)> = get_issue_ids_distribute_fund(pool).await?;
     log::info!("Issue_ids to split fund, count: {:?}", issue_ids.len());
-    for (issue_assignee, _issue_id, issue_budget) in issue_ids {
-        let comment = format!("@{:?}, Well done!  According to the PR commit history. @{:?} should receive ${}. Please fill in this form to claim your fund. ", issue_assignee, issue_assignee, issue_budget);
-
-        // let _ = mock_comment_on_issue(3, &comment).await?;
-    }
-    Ok(())
-}
-
-pub async fn note_one_months_no_pr(pool: &Pool) -> anyhow::Result<()> {
-    let issue_ids = get_issue_ids_one_month_no_activity(pool).await?;
-    log::info!("Issue_ids no activity, count: {:?}", issue_ids.len());
-
-    for issue_id in issue_ids {
-        let comment = format!("{}\n @{} please link your PR to the issue it fixed in three days. Or this issue will be deemed not completed, then we can’t provide the fund.", issue_id, "issue_assignee" );
-
-        // let _ = mock_comment_on_issue(4, &comment).await?;
+    for (_issue_assignee, issue_id, _issue_budget) in issue_ids {
+        let _ = queue_bot_message(pool, NotificationKind::DistributeFund, &issue_id).await;
     }
     Ok(())
 }
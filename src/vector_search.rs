@@ -1,59 +1,120 @@
+use crate::llm_usage::record_embedding;
 use openai_flows::{embeddings::EmbeddingsInput, OpenAIFlows};
 use serde_json::json;
 use std::env;
+use std::time::Instant;
 use vector_store_flows::*;
 
+// Embeds `content` into `point_id` when the item was embedded before, replacing the old
+// vector, otherwise into a new point. Returns the point used.
 pub async fn upload_to_collection(
     issue_or_project_id: &str,
     content: String,
-) -> anyhow::Result<()> {
+    point_id: Option<u64>,
+) -> anyhow::Result<u64> {
     let collection_name = env::var("collection_name").unwrap_or("gosim_search".to_string());
 
-    let mut id: u64 = match collection_info(&collection_name).await {
-        Ok(ci) => ci.points_count,
-        Err(e) => {
-            return Err(anyhow::anyhow!(
-                "Cannot get collection, can not init points_count: {}",
-                e
-            ))
-        }
-    };
-
     let mut openai = OpenAIFlows::new();
     openai.set_retry_times(3);
 
     let input = EmbeddingsInput::String(content.clone());
-    match openai.create_embeddings(input).await {
-        Ok(r) => {
-            for v in r.iter() {
-                let p = vec![Point {
-                    id: PointId::Num(id),
-                    vector: v.iter().map(|n| *n as f32).collect(),
-                    payload: json!({
-                        "issue_or_project_id": issue_or_project_id,
-                        "text": content})
-                    .as_object()
-                    .map(|m| m.to_owned()),
-                }];
-
-                if let Err(e) = upsert_points(&collection_name, p).await {
-                    log::error!("Cannot upsert into database! {}", e);
-                    return Ok(());
-                }
-                id += 1;
-                log::debug!(
-                    "Created vector {} with length {}",
-                    issue_or_project_id,
-                    v.len()
-                );
-            }
-            Ok(())
-        }
+    let started = Instant::now();
+    let embeddings = openai.create_embeddings(input).await;
+    record_embedding(&[&content], embeddings.is_ok(), started);
+    let vectors: Vec<Vec<f32>> = match embeddings {
+        Ok(r) => r
+            .iter()
+            .map(|v| v.iter().map(|n| *n as f32).collect())
+            .collect(),
         Err(e) => {
             log::error!("OpenAI returned an error: {}", e);
-            Err(anyhow::anyhow!("OpenAI returned an error: {}", e))
+            return Err(anyhow::anyhow!("OpenAI returned an error: {}", e));
+        }
+    };
+
+    let mut id: u64 = match point_id {
+        Some(id) => id,
+        None => {
+            let existing = match vectors.first() {
+                Some(v) => find_point_of(&collection_name, issue_or_project_id, v).await,
+                None => None,
+            };
+            match existing {
+                Some(id) => id,
+                None => match collection_info(&collection_name).await {
+                    Ok(ci) => ci.points_count,
+                    Err(e) => {
+                        return Err(anyhow::anyhow!(
+                            "Cannot get collection, can not init points_count: {}",
+                            e
+                        ))
+                    }
+                },
+            }
         }
+    };
+    let first_id = id;
+
+    for v in vectors {
+        let length = v.len();
+        let p = vec![Point {
+            id: PointId::Num(id),
+            vector: v,
+            payload: json!({
+                "issue_or_project_id": issue_or_project_id,
+                "text": content})
+            .as_object()
+            .map(|m| m.to_owned()),
+        }];
+
+        if let Err(e) = upsert_points(&collection_name, p).await {
+            log::error!("Cannot upsert into database! {}", e);
+            return Err(anyhow::anyhow!("Cannot upsert into database! {}", e));
+        }
+        id += 1;
+        log::debug!(
+            "Created vector {} with length {}",
+            issue_or_project_id,
+            length
+        );
     }
+    Ok(first_id)
+}
+
+// How many neighbours of a new vector are checked for an older point of the same item.
+pub static EXISTING_POINT_SEARCH_LIMIT: u64 = 20;
+
+// Items indexed before point ids were recorded have no vector_point_id, and the store
+// cannot delete or filter by payload. Their old summary is usually among the closest
+// points to the new one, so the point carrying the same id is found and reused.
+async fn find_point_of(
+    collection_name: &str,
+    issue_or_project_id: &str,
+    vector: &[f32],
+) -> Option<u64> {
+    let p = PointsSearchParams {
+        vector: vector.to_vec(),
+        limit: EXISTING_POINT_SEARCH_LIMIT,
+    };
+    let points = match search_points(collection_name, &p).await {
+        Ok(points) => points,
+        Err(e) => {
+            log::error!(
+                "Cannot look up the earlier point of {}: {}",
+                issue_or_project_id,
+                e
+            );
+            return None;
+        }
+    };
+
+    points.iter().find_map(|p| {
+        let id = p.payload.as_ref()?.get("issue_or_project_id")?.as_str()?;
+        match p.id {
+            PointId::Num(n) if id == issue_or_project_id => Some(n),
+            _ => None,
+        }
+    })
 }
 
 pub async fn check_vector_db(collection_name: &str) -> String {
@@ -82,10 +143,12 @@ pub async fn search_collection(
     let mut openai = OpenAIFlows::new();
     openai.set_retry_times(3);
 
-    let question_vector = match openai
+    let started = Instant::now();
+    let embeddings = openai
         .create_embeddings(EmbeddingsInput::String(question.to_string()))
-        .await
-    {
+        .await;
+    record_embedding(&[question], embeddings.is_ok(), started);
+    let question_vector = match embeddings {
         Ok(r) => {
             if r.len() < 1 {
                 log::error!("LLM returned no embedding for the question");
@@ -146,6 +209,32 @@ pub async fn search_collection(
     }
     Ok(out)
 }
+// Ids of the closest entries with their similarity scores, best first, without the
+// score cut-off search_collection applies.
+pub async fn search_similar(
+    text: &str,
+    collection_name: &str,
+    limit: u64,
+) -> anyhow::Result<Vec<(String, f32)>> {
+    let vector = match embed_texts(vec![text.to_string()]).await?.pop() {
+        Some(v) => v,
+        None => return Err(anyhow::anyhow!("LLM returned no embedding for the text")),
+    };
+
+    let p = PointsSearchParams { vector, limit };
+    let points = search_points(collection_name, &p)
+        .await
+        .map_err(|e| anyhow::anyhow!("Vector search returns error: {}", e))?;
+
+    Ok(points
+        .iter()
+        .filter_map(|p| {
+            let id = p.payload.as_ref()?.get("issue_or_project_id")?.as_str()?;
+            Some((id.to_string(), p.score))
+        })
+        .collect())
+}
+
 /* pub async fn search_collection_n(
     question: &str,
     collection_name: &str,
@@ -230,3 +319,43 @@ pub async fn create_my_collection(vector_size: u64, collection_name: &str) -> an
     check_vector_db(collection_name).await;
     Ok(())
 }
+
+// Embeddings for several texts in one request, in the same order.
+pub async fn embed_texts(texts: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+    if texts.is_empty() {
+        return Ok(Vec::new());
+    }
+    let expected = texts.len();
+
+    let mut openai = OpenAIFlows::new();
+    openai.set_retry_times(3);
+
+    let started = Instant::now();
+    let embeddings = openai
+        .create_embeddings(EmbeddingsInput::Vec(texts.clone()))
+        .await;
+    let text_refs: Vec<&str> = texts.iter().map(|t| t.as_str()).collect();
+    record_embedding(&text_refs, embeddings.is_ok(), started);
+    match embeddings {
+        Ok(r) if r.len() == expected => Ok(r
+            .iter()
+            .map(|v| v.iter().map(|n| *n as f32).collect())
+            .collect()),
+        Ok(r) => Err(anyhow::anyhow!(
+            "LLM returned {} embeddings for {} texts",
+            r.len(),
+            expected
+        )),
+        Err(e) => Err(anyhow::anyhow!("LLM returned an error: {}", e)),
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
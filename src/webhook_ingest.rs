@@ -0,0 +1,471 @@
+use crate::{budget_extract::*, db_join::*, db_populate::*, issue_tracker::*};
+use crate::{ISSUE_LABEL, PR_LABEL};
+use chrono::Utc;
+use mysql_async::Pool;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// GitHub webhook payloads, only the fields the staging tables need.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookPayload {
+    pub action: Option<String>,
+    pub issue: Option<WebhookIssue>,
+    pub pull_request: Option<WebhookPull>,
+    pub comment: Option<WebhookComment>,
+    pub assignee: Option<WebhookUser>,
+    pub label: Option<WebhookLabel>,
+    pub repository: Option<WebhookRepo>,
+    pub sender: Option<WebhookUser>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookIssue {
+    pub node_id: Option<String>,
+    pub html_url: String,
+    pub title: String,
+    pub body: Option<String>,
+    pub user: Option<WebhookUser>,
+    #[serde(default)]
+    pub labels: Vec<WebhookLabel>,
+    #[serde(default)]
+    pub assignees: Vec<WebhookUser>,
+    pub state: Option<String>,
+    pub updated_at: Option<String>,
+    // only present when the "issue" is a pull request
+    pub pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookPull {
+    pub node_id: Option<String>,
+    pub html_url: String,
+    pub title: Option<String>,
+    pub user: Option<WebhookUser>,
+    #[serde(default)]
+    pub merged: bool,
+    pub created_at: Option<String>,
+    pub merged_at: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<WebhookLabel>,
+    pub additions: Option<i32>,
+    pub deletions: Option<i32>,
+    pub changed_files: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookComment {
+    pub node_id: Option<String>,
+    pub user: Option<WebhookUser>,
+    pub body: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookUser {
+    pub id: Option<i64>,
+    pub node_id: Option<String>,
+    pub login: String,
+    pub avatar_url: Option<String>,
+}
+
+impl From<WebhookUser> for Contributor {
+    fn from(user: WebhookUser) -> Self {
+        Contributor {
+            login: user.login,
+            database_id: user.id,
+            node_id: user.node_id,
+            avatar_url: user.avatar_url,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookLabel {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookRepo {
+    pub node_id: Option<String>,
+    pub html_url: String,
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut key_block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        key_block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(key_block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    inner.update(message);
+    let inner_hash = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(key_block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.update(inner_hash);
+    outer.finalize().to_vec()
+}
+
+// Checks the `X-Hub-Signature-256: sha256=<hex>` header against the raw request body.
+pub fn verify_signature(secret: &str, body: &[u8], signature_header: &str) -> bool {
+    let expected_hex = match signature_header.trim().strip_prefix("sha256=") {
+        Some(h) => h.to_ascii_lowercase(),
+        None => return false,
+    };
+
+    let computed_hex = hmac_sha256(secret.as_bytes(), body)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    // compare in constant time, the header is attacker controlled
+    expected_hex.len() == computed_hex.len()
+        && expected_hex
+            .bytes()
+            .zip(computed_hex.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn has_label(labels: &[WebhookLabel], wanted: &str) -> bool {
+    labels.iter().any(|l| l.name.eq_ignore_ascii_case(wanted))
+}
+
+// Maps one webhook delivery onto the staging tables the hourly search fills, through the
+// same db_populate functions, then runs the matching join so the master table is current.
+// Returns false for deliveries that are not relevant to the campaign.
+pub async fn ingest_github_event(pool: &Pool, event: &str, payload: &[u8]) -> anyhow::Result<bool> {
+    let payload: WebhookPayload = serde_json::from_slice(payload)?;
+    let action = payload.action.clone().unwrap_or_default();
+
+    match event {
+        "issues" => ingest_issue_event(pool, &action, payload).await,
+        "issue_comment" => ingest_comment_event(pool, &action, payload).await,
+        "pull_request" => ingest_pull_event(pool, &action, payload).await,
+        // repository label changes don't touch any tracked record, labels on issues
+        // arrive as "issues" events with the labeled/unlabeled action
+        "label" => {
+            log::info!(
+                "Label {} {:?}, nothing to ingest",
+                action,
+                payload.label.map(|l| l.name)
+            );
+            Ok(false)
+        }
+        "ping" => Ok(false),
+        _ => {
+            log::info!("Ignoring github event: {}", event);
+            Ok(false)
+        }
+    }
+}
+
+async fn ingest_issue_event(
+    pool: &Pool,
+    action: &str,
+    payload: WebhookPayload,
+) -> anyhow::Result<bool> {
+    let issue = match payload.issue {
+        Some(i) if i.pull_request.is_none() && has_label(&i.labels, ISSUE_LABEL) => i,
+        _ => return Ok(false),
+    };
+    let (project_id, project_node_id) = match payload.repository {
+        Some(r) => (r.html_url, r.node_id),
+        None => return Ok(false),
+    };
+    match action {
+        "opened" | "edited" | "labeled" | "reopened" => {
+            let issue_description = issue
+                .body
+                .unwrap_or_default()
+                .chars()
+                .take(8000)
+                .collect::<String>();
+            let issue_labels = issue
+                .labels
+                .iter()
+                .map(|l| l.name.clone())
+                .collect::<Vec<String>>();
+            let issue_budget_evidence =
+                extract_budget_with_fallback(&issue_description, &issue_labels).await;
+            let issue_budget = issue_budget_evidence.as_ref().map_or(0, |c| c.amount);
+
+            let (issue_creator, issue_creator_node_id) = issue
+                .user
+                .map_or((String::new(), None), |u| (u.login, u.node_id));
+            let issue_state = match issue.state.as_deref() {
+                Some("closed") => "closed",
+                _ => "open",
+            };
+            let issue_open = IssueOpen {
+                issue_title: issue.title,
+                issue_id: issue.html_url,
+                issue_node_id: issue.node_id,
+                issue_creator,
+                issue_budget,
+                issue_description,
+                project_id,
+                project_node_id,
+                issue_creator_node_id,
+                issue_budget_evidence,
+            };
+            // edits and reopens reach rows the hourly search recorded before
+            upsert_issue_master(pool, &issue_open, issue_state).await?;
+            // skipped when the title and body are what was summarized before
+            let _ = summarize_issue_add_in_db(pool, &issue_open).await;
+        }
+        "assigned" | "unassigned" => {
+            let assignee_profile: Contributor = match payload.assignee {
+                Some(a) => a.into(),
+                None => return Ok(false),
+            };
+            // the delivery has no event id or date, they come from the issue timeline; the
+            // delivery time stands in when GitHub can't be asked, the hourly search adds the
+            // real event later
+            let (event_node_id, date_assigned) = match get_assignment_event(
+                &issue.html_url,
+                &assignee_profile.login,
+                action,
+            )
+            .await
+            {
+                Ok(Some((id, date))) => (Some(id), date),
+                res => {
+                    if let Err(e) = res {
+                        log::error!(
+                            "Error getting {} event of {}: {:?}",
+                            action,
+                            issue.html_url,
+                            e
+                        );
+                    }
+                    (None, Utc::now().format("%Y-%m-%d %H:%M:%S").to_string())
+                }
+            };
+
+            add_issues_assigned(
+                pool,
+                IssueAssigned {
+                    issue_id: issue.html_url,
+                    issue_node_id: issue.node_id,
+                    event_node_id,
+                    event_type: action.to_string(),
+                    issue_assignee: assignee_profile.login.clone(),
+                    assignee_node_id: assignee_profile.node_id.clone(),
+                    assignee_profile: Some(assignee_profile),
+                    event_actor: payload.sender.map(|s| s.login),
+                    date_assigned,
+                },
+            )
+            .await?;
+            assigned_master(pool).await?;
+        }
+        "closed" => {
+            // the payload doesn't say what closed the issue, look for the PR in the timeline
+            let linked_pr = get_issue_linked_pr(&issue.html_url)
+                .await
+                .unwrap_or_else(|e| {
+                    log::error!("Error getting linked PR of {}: {:?}", issue.html_url, e);
+                    None
+                });
+            let assignee_profiles: Vec<Contributor> =
+                issue.assignees.into_iter().map(|a| a.into()).collect();
+            let issue_assignees = Some(assignee_profiles.iter().map(|a| a.login.clone()).collect())
+                .filter(|a: &Vec<String>| !a.is_empty());
+
+            add_issues_closed(
+                pool,
+                IssueClosed {
+                    issue_id: issue.html_url,
+                    issue_node_id: issue.node_id,
+                    issue_assignees,
+                    assignee_profiles,
+                    issue_linked_pr: linked_pr.as_ref().map(|l| l.pull_id.clone()),
+                    issue_linked_pr_node_id: linked_pr
+                        .as_ref()
+                        .and_then(|l| l.pull_node_id.clone()),
+                    issue_linked_pr_method: linked_pr
+                        .as_ref()
+                        .map(|l| l.method.as_str().to_string()),
+                    issue_linked_pr_confidence: linked_pr.as_ref().map(|l| l.confidence),
+                },
+            )
+            .await?;
+            closed_master(pool).await?;
+            link_assignee_pulls(pool).await?;
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+async fn ingest_comment_event(
+    pool: &Pool,
+    action: &str,
+    payload: WebhookPayload,
+) -> anyhow::Result<bool> {
+    if action != "created" && action != "edited" {
+        return Ok(false);
+    }
+    let (issue, comment) = match (payload.issue, payload.comment) {
+        (Some(i), Some(c)) if i.pull_request.is_none() && has_label(&i.labels, ISSUE_LABEL) => {
+            (i, c)
+        }
+        _ => return Ok(false),
+    };
+
+    let comment_date = match comment
+        .updated_at
+        .as_deref()
+        .and_then(|d| convert_datetime(d).ok())
+    {
+        Some(d) => d,
+        None => return Ok(false),
+    };
+
+    add_issues_comment(
+        pool,
+        IssueComment {
+            issue_id: issue.html_url,
+            issue_node_id: issue.node_id,
+            comment_node_id: comment.node_id,
+            comment_creator: comment
+                .user
+                .as_ref()
+                .map(|u| u.login.clone())
+                .unwrap_or_default(),
+            comment_creator_node_id: comment.user.and_then(|u| u.node_id),
+            comment_date,
+            comment_body: comment.body.unwrap_or_default(),
+        },
+    )
+    .await?;
+
+    Ok(true)
+}
+
+// Only merged PRs with the accepted label are recorded, like the hourly search; the
+// search also requires an approving review, which the hourly run still checks.
+async fn ingest_pull_event(
+    pool: &Pool,
+    action: &str,
+    payload: WebhookPayload,
+) -> anyhow::Result<bool> {
+    let pull = match payload.pull_request {
+        Some(p) if (action == "closed" || action == "labeled") && p.merged => p,
+        _ => return Ok(false),
+    };
+    if !has_label(&pull.labels, PR_LABEL) {
+        return Ok(false);
+    }
+    let (project_id, project_node_id) = match payload.repository {
+        Some(r) => (r.html_url, r.node_id),
+        None => return Ok(false),
+    };
+
+    let created_at = pull
+        .created_at
+        .as_deref()
+        .and_then(|d| convert_datetime(d).ok())
+        .unwrap_or_default();
+    let merged_at = pull
+        .merged_at
+        .as_deref()
+        .and_then(|d| convert_datetime(d).ok())
+        .unwrap_or_default();
+
+    add_pull_request(
+        pool,
+        OuterPull {
+            pull_id: pull.html_url,
+            pull_node_id: pull.node_id,
+            pull_title: pull.title.unwrap_or_default(),
+            pull_author: pull.user.as_ref().map(|u| u.login.clone()),
+            pull_author_node_id: pull.user.and_then(|u| u.node_id),
+            project_id,
+            project_node_id,
+            created_at,
+            merged_at,
+            additions: pull.additions,
+            deletions: pull.deletions,
+            changed_files: pull.changed_files,
+            // the delivery has no reviews or checks, the hourly search fills them in
+            approvers: None,
+            ci_status: None,
+            pull_labels: pull.labels.into_iter().map(|l| l.name).collect(),
+        },
+    )
+    .await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // keys longer than a block are hashed first
+        assert_eq!(
+            hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    // the example from GitHub's webhook documentation
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        assert!(verify_signature(SECRET, BODY, SIGNATURE));
+        assert!(verify_signature(
+            SECRET,
+            BODY,
+            &SIGNATURE.to_uppercase().replace("SHA256=", "sha256=")
+        ));
+        assert!(verify_signature(SECRET, BODY, &format!(" {} ", SIGNATURE)));
+    }
+
+    #[test]
+    fn rejects_a_wrong_secret_or_body() {
+        assert!(!verify_signature("another secret", BODY, SIGNATURE));
+        assert!(!verify_signature(SECRET, b"Hello, World?", SIGNATURE));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(!verify_signature(SECRET, BODY, ""));
+        assert!(!verify_signature(SECRET, BODY, "sha256="));
+        assert!(!verify_signature(
+            SECRET,
+            BODY,
+            &SIGNATURE.replace("sha256=", "sha1=")
+        ));
+        assert!(!verify_signature(
+            SECRET,
+            BODY,
+            &SIGNATURE[..SIGNATURE.len() - 2]
+        ));
+    }
+}
@@ -1,10 +1,14 @@
 use dotenv::dotenv;
 use flowsnet_platform_sdk::logger;
+use gosim_project::budget_recommend::*;
 use gosim_project::db_join::*;
 use gosim_project::db_manipulate::*;
 use gosim_project::db_populate::*;
 use gosim_project::issue_tracker::*;
-use gosim_project::llm_utils::chat_inner_async;
+use gosim_project::llm_provider::{chat_for_task, LlmTask};
+use gosim_project::llm_usage::*;
+use gosim_project::prompt_eval::*;
+use gosim_project::tag_taxonomy::*;
 use gosim_project::the_paced_runner::*;
 use gosim_project::vector_search::*;
 use mysql_async::*;
@@ -51,6 +55,9 @@ async fn handler(
     router
         .insert("/vector/delete", vec![post(delete_vdb_handler)])
         .unwrap();
+    router
+        .insert("/prompts/eval", vec![post(prompt_eval_handler)])
+        .unwrap();
 
     if let Err(e) = route(router).await {
         match e {
@@ -168,6 +175,56 @@ async fn check_vdb_by_post_handler(
         );
     }
 }
+async fn prompt_eval_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    #[derive(Serialize, Deserialize, Clone, Debug, Default)]
+    pub struct EvalLoad {
+        pub prompt: Option<String>,
+        pub version_a: String,
+        pub version_b: String,
+    }
+
+    let load: EvalLoad = match serde_json::from_slice(&_body) {
+        Ok(obj) => obj,
+        Err(_e) => {
+            log::error!("failed to parse body: {}", _e);
+            return;
+        }
+    };
+    let prompt = load.prompt.unwrap_or(String::from("issue_summary"));
+
+    let result = evaluate_prompt_versions(&prompt, &load.version_a, &load.version_b).await;
+    let _ = flush_llm_usage(&get_pool().await).await;
+    match result {
+        Ok(report) => {
+            send_response(
+                200,
+                vec![
+                    (
+                        String::from("content-type"),
+                        String::from("application/json"),
+                    ),
+                    (
+                        String::from("Access-Control-Allow-Origin"),
+                        String::from("*"),
+                    ),
+                ],
+                json!(report).to_string().as_bytes().to_vec(),
+            );
+        }
+        Err(e) => {
+            log::error!("Error evaluating prompts: {:?}", e);
+            send_response(
+                400,
+                vec![(String::from("content-type"), String::from("text/plain"))],
+                e.to_string().as_bytes().to_vec(),
+            );
+        }
+    }
+}
 async fn _check_deep_handler(
     _headers: Vec<(String, String)>,
     _qry: HashMap<String, Value>,
@@ -181,7 +238,9 @@ async fn _check_deep_handler(
     if let Ok(load) = serde_json::from_slice::<VectorLoad>(&_body) {
         if let Some(text) = load.text {
             log::info!("text: {text}");
-            if let Ok(reply) = chat_inner_async("you're an AI assistant", &text, 100).await {
+            if let Ok(reply) =
+                chat_for_task(LlmTask::Summarization, "you're an AI assistant", &text, 100).await
+            {
                 send_response(
                     200,
                     vec![
@@ -314,12 +373,17 @@ async fn trigger(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>,
             "11" => populate_vector_db(&pool).await,
             "12" => popuate_dbs_save_issues_comment(&pool).await,
             "13" => sum_budget_to_project(&pool).await,
-            "14" => remove_pull_by_issued_linked_pr(&pool).await,
+            "14" => mark_linked_pulls(&pool).await,
             "15" => delete_issues_open_assigned_closed(&pool).await,
             "16" => force_issue_to_summary_update_db(&pool).await,
+            "17" => link_assignee_pulls(&pool).await,
+            "18" => classification_master(&pool).await,
+            "19" => recommend_budgets(&pool).await,
+            "20" => update_tag_taxonomy(&pool).await,
             _ => panic!(),
         };
     }
+    let _ = flush_llm_usage(&pool).await;
 }
 
 pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
@@ -375,10 +439,19 @@ pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
 }
 
 pub async fn populate_vector_db(pool: &Pool) -> anyhow::Result<()> {
+    // unindexed summaries stay marked as such and are picked up once spending resumes
+    if let Some(reason) = spend_pause_reason(pool).await? {
+        log::info!("Not indexing, {}", reason);
+        return Ok(());
+    }
     for item in get_issues_repos_from_db().await.expect("msg") {
         log::info!("uploading to vector_db: {:?}", item.0);
-        let _ = upload_to_collection(&item.0, item.1.clone()).await;
-        let _ = mark_id_indexed(&pool, &item.0).await;
+        match upload_to_collection(&item.0, item.1.clone(), item.2).await {
+            Ok(point_id) => {
+                let _ = mark_id_indexed(&pool, &item.0, point_id).await;
+            }
+            Err(e) => log::error!("Error uploading {} to vector_db: {:?}", item.0, e),
+        }
     }
     let _ = check_vector_db("gosim_search").await;
 
@@ -410,7 +483,8 @@ pub async fn join_ops(pool: &Pool) -> anyhow::Result<()> {
 }
 
 pub async fn cleanup_ops(pool: &Pool) -> anyhow::Result<()> {
-    let _ = remove_pull_by_issued_linked_pr(&pool).await?;
+    let _ = link_assignee_pulls(&pool).await?;
+    let _ = mark_linked_pulls(&pool).await?;
     let _ = delete_issues_open_assigned_closed(&pool).await?;
 
     Ok(())